@@ -0,0 +1,158 @@
+//! Offline stand-in for yt-dlp and ffmpeg used by the test suite.
+//!
+//! Behaviour is selected by keywords in the search query (i.e. the track title),
+//! so parallel tests never need to share environment variables:
+//!
+//! * `FAIL`    - exit with a yt-dlp style error on stderr
+//! * `FLAKY`   - fail only for the `... topic` query variant
+//! * `PARTIAL` - leave only a `.part` file behind and exit successfully
+//! * `NOFILE`  - exit successfully without writing anything
+//! * `HANG`    - sleep for an hour, simulating a stalled download
+//! * `TRICKLE` - print progress forever without finishing
+//! * `LOUD`    - measured by the fake loudnorm filter at -8 LUFS instead of -20
+//! * `SILENT`  - `silencedetect` reports silence at both ends of the file
+//! * `SKIT`    - SponsorBlock reports a `music_offtopic` segment
+//!
+//! Any other query produces a small but valid MP3 file, plus the `.info.json`
+//! and thumbnail when those are requested.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--version" || a == "-version") {
+        println!("fake-tool 0.0.0");
+        return;
+    }
+    if args.iter().any(|a| a == "-i") {
+        fake_ffmpeg(&args);
+    } else {
+        fake_yt_dlp(&args);
+    }
+}
+
+fn arg_after<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).map(|s| s.as_str())
+}
+
+fn fake_yt_dlp(args: &[String]) {
+    let template = arg_after(args, "-o").unwrap_or("%(title)s.%(ext)s");
+    let format = arg_after(args, "--audio-format").unwrap_or("mp3");
+    let query = args
+        .iter()
+        .find_map(|a| a.strip_prefix("ytsearch1:"))
+        .unwrap_or_default();
+
+    if query.contains("HANG") {
+        sleep(Duration::from_secs(3600));
+    }
+    if query.contains("TRICKLE") {
+        for percent in 0.. {
+            print!("\r[download] {:>5.1}% of 3.00MiB", (percent % 1000) as f32 / 10.0);
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+            sleep(Duration::from_millis(50));
+        }
+    }
+    if query.contains("FAIL") || (query.contains("FLAKY") && query.ends_with(" topic")) {
+        eprintln!("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable");
+        exit(1);
+    }
+
+    let title = query.replace(['/', '\\'], "_");
+    let output = template.replace("%(title)s", &title);
+    println!("[youtube:search] Extracting URL: ytsearch1:{query}");
+    if query.contains("NOFILE") {
+        return;
+    }
+    if query.contains("PARTIAL") {
+        let part = output.replace("%(ext)s", "webm.part");
+        fs::write(&part, b"partial").expect("write partial file");
+        println!("[download] Destination: {part}");
+        return;
+    }
+
+    println!("[download] Destination: {}", output.replace("%(ext)s", "webm"));
+    if let Some(template) = arg_after(args, "--progress-template") {
+        let template = template.strip_prefix("download:").unwrap_or(template);
+        for done in [0, 8340, 16680] {
+            println!(
+                "{}",
+                template
+                    .replace("%(progress.downloaded_bytes)s", &done.to_string())
+                    .replace("%(progress.total_bytes,progress.total_bytes_estimate)s", "16680")
+                    .replace("%(progress.speed)s", "8340.0")
+                    .replace("%(progress.eta)s", &((16680 - done) / 8340).to_string())
+            );
+        }
+    }
+
+    if args.iter().any(|a| a == "--write-info-json") {
+        let id: String = title.chars().filter(char::is_ascii_alphanumeric).take(11).collect();
+        let chapters = if query.contains("SKIT") && args.iter().any(|a| a == "--sponsorblock-remove") {
+            r#"[{"start_time": 10.0, "end_time": 25.5, "category": "music_offtopic", "title": "Non-Music Section"}]"#
+        } else {
+            "[]"
+        };
+        let info = format!(
+            "{{\"id\": \"{id}\", \"title\": \"{} (Official Audio)\", \"webpage_url\": \"https://www.youtube.com/watch?v={id}\", \
+             \"sponsorblock_chapters\": {chapters}}}",
+            title.replace('"', "'")
+        );
+        fs::write(output.replace("%(ext)s", "info.json"), info).expect("write info json");
+    }
+
+    if args.iter().any(|a| a == "--write-thumbnail") {
+        // 16:9 like a YouTube thumbnail, so cropping is exercised
+        let thumbnail = image::RgbImage::from_pixel(320, 180, image::Rgb([40, 90, 160]));
+        thumbnail.save(output.replace("%(ext)s", "png")).expect("write thumbnail");
+    }
+
+    let output = output.replace("%(ext)s", format);
+    write_mp3(Path::new(&output));
+    println!("[ExtractAudio] Destination: {output}");
+}
+
+fn fake_ffmpeg(args: &[String]) {
+    let Some(output) = args.last() else { exit(1) };
+    let input = arg_after(args, "-i").unwrap_or_default();
+    if arg_after(args, "-af").is_some_and(|filter| filter.contains("print_format=json")) {
+        let integrated = if input.contains("LOUD") { "-8.00" } else { "-20.00" };
+        eprintln!(
+            "[Parsed_loudnorm_0 @ 0x0]\n{{\n\t\"input_i\" : \"{integrated}\",\n\t\"input_tp\" : \"-3.00\",\n\t\
+             \"input_lra\" : \"5.00\",\n\t\"input_thresh\" : \"-30.00\",\n\t\"target_offset\" : \"0.00\"\n}}"
+        );
+    }
+    if input.contains("SILENT") && arg_after(args, "-af").is_some_and(|filter| filter.starts_with("silencedetect")) {
+        eprintln!("[silencedetect @ 0x0] silence_start: 0\n[silencedetect @ 0x0] silence_end: 0.2 | silence_duration: 0.2");
+        eprintln!("[silencedetect @ 0x0] silence_start: 0.8");
+    }
+    if output == "-" {
+        // Decode check (`-f null -`): inputs named CORRUPT report a decoding error
+        if input.contains("CORRUPT") {
+            eprintln!("[mp3float @ 0x0] Header missing");
+            exit(1);
+        }
+        return;
+    }
+    write_mp3(Path::new(output));
+}
+
+/// Write an empty ID3v2.4 tag followed by ~1s of silent MPEG-1 Layer III frames.
+fn write_mp3(path: &Path) {
+    let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    // 128 kbps, 44.1 kHz, no padding: 417 bytes per frame.
+    let mut frame = vec![0u8; 417];
+    frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    for _ in 0..40 {
+        data.extend_from_slice(&frame);
+    }
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    fs::write(path, data).expect("write fake mp3");
+}
@@ -0,0 +1,168 @@
+use crate::audio::read_id3v2;
+use crate::config::CoverArtConfig;
+use crate::csvparse::TrackInfo;
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use lofty::mp4::Mp4File;
+use lofty::{AudioFile, FileType, MimeType, ParseOptions, Picture, PictureType, TagExt, TaggedFileExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Image files accepted as covers, also used to recognise thumbnails written by yt-dlp.
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Look for a user-supplied cover for `track` in `dir`.
+/// Files named `Artist - Album`, `Album`, `Artist - Title` or `Title` are tried in that order.
+pub fn find_user_image(dir: &Path, track: &TrackInfo) -> Option<PathBuf> {
+    let images: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| is_image(p))
+        .collect();
+    let mut names = Vec::new();
+    if !track.album.is_empty() {
+        names.push(format!("{} - {}", track.artist, track.album));
+        names.push(track.album.clone());
+    }
+    names.push(format!("{} - {}", track.artist, track.title));
+    names.push(track.title.clone());
+    names.iter().find_map(|name| {
+        images
+            .iter()
+            .find(|p| p.file_stem().and_then(|s| s.to_str()).is_some_and(|s| s.eq_ignore_ascii_case(name)))
+            .cloned()
+    })
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Centre-crop the image at `path` to a square no larger than `max_size` and encode it as JPEG.
+pub fn prepare_cover(path: &Path, max_size: u32) -> Result<Vec<u8>> {
+    let img = image::open(path).with_context(|| format!("Failed to read image {:?}", path))?;
+    let side = img.width().min(img.height());
+    let mut img = img.crop_imm((img.width() - side) / 2, (img.height() - side) / 2, side, side);
+    if max_size > 0 && side > max_size {
+        img = img.resize_exact(max_size, max_size, FilterType::Lanczos3);
+    }
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&img.to_rgb8())
+        .context("Failed to encode cover")?;
+    Ok(jpeg)
+}
+
+/// Replace the front cover of the audio file at `path` with `jpeg`.
+pub fn embed_cover(path: &Path, jpeg: &[u8]) -> Result<()> {
+    embed_picture(path, Picture::new_unchecked(PictureType::CoverFront, Some(MimeType::Jpeg), None, jpeg.to_vec()))
+}
+
+/// Copy the front cover (or else the first picture) of `src` into `dst`, e.g. after ffmpeg
+/// left it out of a transcode. Returns `Ok(false)` if `src` has no picture.
+pub fn copy_cover(src: &Path, dst: &Path) -> Result<bool> {
+    let Ok(tagged) = lofty::read_from_path(src) else {
+        return Ok(false);
+    };
+    let pictures: Vec<&Picture> = tagged.tags().iter().flat_map(|tag| tag.pictures()).collect();
+    let Some(picture) = pictures.iter().find(|p| p.pic_type() == PictureType::CoverFront).or(pictures.first()) else {
+        return Ok(false);
+    };
+    let cover = Picture::new_unchecked(PictureType::CoverFront, picture.mime_type().cloned(), None, picture.data().to_vec());
+    embed_picture(dst, cover)?;
+    Ok(true)
+}
+
+/// Replace the front cover of the audio file at `path` with `cover`.
+///
+/// ID3v2 (MP3, raw AAC) and MP4 tags are edited in their native form so multi-value frames and
+/// custom atoms written by [`crate::audio`] survive the round trip.
+fn embed_picture(path: &Path, cover: Picture) -> Result<()> {
+    let read_err = || format!("Failed to read tags from {:?}", path);
+    let save_err = || format!("Failed to save cover to {:?}", path);
+    match FileType::from_path(path) {
+        Some(FileType::Mpeg | FileType::Aac) => {
+            let mut tag = read_id3v2(path)?;
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.insert_picture(cover);
+            tag.save_to_path(path).with_context(save_err)?;
+        }
+        Some(FileType::Mp4) => {
+            let file = Mp4File::read_from(&mut fs::File::open(path)?, ParseOptions::new()).with_context(read_err)?;
+            // `covr` has no picture types, so the cover simply replaces what was there
+            let mut tag = file.ilst().cloned().unwrap_or_default();
+            tag.remove_pictures();
+            tag.insert_picture(cover);
+            tag.save_to_path(path).with_context(save_err)?;
+        }
+        _ => {
+            let mut tagged = lofty::read_from_path(path).with_context(read_err)?;
+            if tagged.primary_tag().is_none() {
+                tagged.insert_tag(lofty::Tag::new(tagged.primary_tag_type()));
+            }
+            let tag = tagged.primary_tag_mut().expect("primary tag was just inserted");
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(cover);
+            tag.save_to_path(path).with_context(save_err)?;
+        }
+    }
+    Ok(())
+}
+
+/// Pick, prepare and embed the cover for a converted track.
+///
+/// A user image wins over the video `thumbnail`. Returns `Ok(false)` if there was no image.
+pub fn apply_cover(output: &Path, track: &TrackInfo, thumbnail: Option<&Path>, config: &CoverArtConfig) -> Result<bool> {
+    let user_image = config.image_dir.as_deref().and_then(|dir| find_user_image(dir, track));
+    let Some(source) = user_image.as_deref().or(thumbnail) else {
+        return Ok(false);
+    };
+    let jpeg = prepare_cover(source, config.max_size)?;
+    embed_cover(output, &jpeg)?;
+    if config.write_folder_cover {
+        if let Some(folder) = output.parent() {
+            let cover = folder.join("cover.jpg");
+            if !cover.exists() {
+                fs::write(&cover, &jpeg).with_context(|| format!("Failed to write {:?}", cover))?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::track;
+
+    #[test]
+    fn crops_to_square_and_limits_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wide.png");
+        image::RgbImage::from_pixel(300, 100, image::Rgb([200, 30, 30])).save(&path).unwrap();
+
+        let cover = image::load_from_memory(&prepare_cover(&path, 64).unwrap()).unwrap();
+        assert_eq!((cover.width(), cover.height()), (64, 64));
+        let small = image::load_from_memory(&prepare_cover(&path, 600).unwrap()).unwrap();
+        assert_eq!((small.width(), small.height()), (100, 100));
+    }
+
+    #[test]
+    fn finds_user_image_by_album_then_title() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("artist - album.PNG"), b"").unwrap();
+        fs::write(dir.path().join("Song.jpg"), b"").unwrap();
+        fs::write(dir.path().join("Album.txt"), b"").unwrap();
+
+        let found = find_user_image(dir.path(), &track("Song", "Artist", "Album")).unwrap();
+        assert_eq!(found.file_name().unwrap(), "artist - album.PNG");
+        let found = find_user_image(dir.path(), &track("Song", "Artist", "Other")).unwrap();
+        assert_eq!(found.file_name().unwrap(), "Song.jpg");
+        assert!(find_user_image(dir.path(), &track("None", "Artist", "")).is_none());
+    }
+}
@@ -1,53 +1,337 @@
-use std::fs;
-use std::io::Read;
-use std::path::Path;
-use anyhow::{Context, Result};
-use lofty::{Accessor, AudioFile, Tag, TagType, TaggedFileExt};
-
-pub fn set_mp3_tags(path: impl AsRef<Path>, title: &str, artist: &str, album: &str) -> Result<()> {
-    let path = path.as_ref();
-    let mut tagged_file = lofty::read_from_path(path)
-        .with_context(|| format!("Failed to read tags from {:?}", path))?;
-
-    if tagged_file.primary_tag_mut().is_none() {
-        tagged_file.insert_tag(Tag::new(TagType::Id3v2));
-    }
-    if let Some(tag) = tagged_file.primary_tag_mut() {
-        tag.set_title(title.to_string());
-        tag.set_artist(artist.to_string());
-        tag.set_album(album.to_string());
-    }
-
-    tagged_file.save_to_path(path)
-        .with_context(|| format!("Failed to save tags to {:?}", path))?;
-    Ok(())
-}
-
-pub fn set_m4a_tags(path: impl AsRef<Path>, title: &str, artist: &str, album: &str) -> Result<()> {
-    let path = path.as_ref();
-    let mut tagged_file = lofty::read_from_path(path)
-        .with_context(|| format!("Failed to read tags from {:?}", path))?;
-
-    if tagged_file.primary_tag_mut().is_none() {
-        tagged_file.insert_tag(Tag::new(TagType::Mp4Ilst));
-    }
-    if let Some(tag) = tagged_file.primary_tag_mut() {
-        tag.set_title(title.to_string());
-        tag.set_artist(artist.to_string());
-        tag.set_album(album.to_string());
-    }
-
-    tagged_file.save_to_path(path)
-        .with_context(|| format!("Failed to save tags to {:?}", path))?;
-    Ok(())
-}
-
-pub fn is_valid_mp3(path: &Path) -> bool {
-    if let Ok(mut file) = fs::File::open(path) {
-        let mut header = [0u8; 3];
-        if file.read_exact(&mut header).is_ok() {
-            return &header == b"ID3" || &header == b"TAG";
-        }
-    }
-    false
+use std::path::Path;
+use anyhow::{Context, Result};
+use lofty::id3::v2::{Frame, FrameFlags, FrameValue, Id3v2Tag, TextInformationFrame};
+use lofty::mp4::{Atom, AtomData, AtomIdent, Ilst};
+use lofty::aac::AacFile;
+use lofty::flac::FlacFile;
+use lofty::mpeg::MpegFile;
+use lofty::mp4::Mp4File;
+use lofty::ogg::{OpusFile, VorbisComments, VorbisFile};
+use lofty::{Accessor, AudioFile, FileType, ItemKey, ParseOptions, Tag, TagExt, TagType, TextEncoding};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use crate::artwork::copy_cover;
+use crate::config::OutputFormat;
+use crate::csvparse::TrackInfo;
+use crate::spotify2media::Tools;
+
+/// Name of the custom ID3 TXXX frame / MP4 freeform atom holding the Spotify URI.
+pub const SPOTIFY_URI_KEY: &str = "SPOTIFY_URI";
+
+/// Format-neutral tag with every field known for `track`.
+/// `source_url` (the video the audio came from) is stored as the comment.
+fn generic_tag(track: &TrackInfo, source_url: Option<&str>, tag_type: TagType) -> Tag {
+    let mut tag = Tag::new(tag_type);
+    tag.set_title(track.title.clone());
+    tag.set_artist(track.artist.clone());
+    tag.set_album(track.album.clone());
+    let mut text = |key, value: &str| {
+        if !value.is_empty() {
+            tag.insert_text(key, value.to_string());
+        }
+    };
+    text(ItemKey::AlbumArtist, &track.album_artist);
+    text(ItemKey::RecordingDate, &track.release_date);
+    text(ItemKey::Isrc, &track.isrc);
+    text(ItemKey::Genre, &track.genres.join("; "));
+    text(ItemKey::Comment, source_url.unwrap_or_default());
+    if let Some(n) = track.track_number {
+        tag.set_track(n);
+    }
+    if let Some(n) = track.track_total {
+        tag.set_track_total(n);
+    }
+    if let Some(n) = track.disc_number {
+        tag.set_disk(n);
+    }
+    if let Some(n) = track.disc_total {
+        tag.set_disk_total(n);
+    }
+    tag
+}
+
+/// The ID3v2 tag of an MP3 or raw ADTS AAC file, or an empty one if it has none.
+pub fn read_id3v2(path: &Path) -> Result<Id3v2Tag> {
+    let read_err = || format!("Failed to read tags from {:?}", path);
+    let mut file = fs::File::open(path).with_context(read_err)?;
+    let options = ParseOptions::new();
+    let tag = match FileType::from_path(path) {
+        Some(FileType::Aac) => AacFile::read_from(&mut file, options).with_context(read_err)?.id3v2().cloned(),
+        _ => MpegFile::read_from(&mut file, options).with_context(read_err)?.id3v2().cloned(),
+    };
+    Ok(tag.unwrap_or_default())
+}
+
+/// The ilst tag of an MP4/M4A, or an empty one if it has none.
+fn read_ilst(path: &Path) -> Result<Ilst> {
+    let mut file = fs::File::open(path).with_context(|| format!("Failed to read tags from {:?}", path))?;
+    let mp4 = Mp4File::read_from(&mut file, ParseOptions::new()).with_context(|| format!("Failed to read tags from {:?}", path))?;
+    Ok(mp4.ilst().cloned().unwrap_or_default())
+}
+
+/// The Vorbis comments of a FLAC, Opus or Ogg Vorbis file; empty ones for a FLAC without any.
+fn read_vorbis(path: &Path) -> Result<VorbisComments> {
+    let read_err = || format!("Failed to read tags from {:?}", path);
+    let mut file = fs::File::open(path).with_context(read_err)?;
+    let options = ParseOptions::new();
+    Ok(match FileType::from_path(path) {
+        Some(FileType::Flac) => FlacFile::read_from(&mut file, options).with_context(read_err)?.vorbis_comments().cloned().unwrap_or_default(),
+        Some(FileType::Opus) => OpusFile::read_from(&mut file, options).with_context(read_err)?.vorbis_comments().clone(),
+        Some(FileType::Vorbis) => VorbisFile::read_from(&mut file, options).with_context(read_err)?.vorbis_comments().clone(),
+        _ => anyhow::bail!("{:?} has no Vorbis comments", path),
+    })
+}
+
+/// Update the ID3v2.4 tag of an MP3 (or raw ADTS AAC, which has no MP4 atoms) with
+/// everything known about `track`. Frames we don't
+/// write (lyrics, ReplayGain, other comments, artwork) stay as they were.
+pub fn set_mp3_tags(path: impl AsRef<Path>, track: &TrackInfo, source_url: Option<&str>) -> Result<()> {
+    let path = path.as_ref();
+    let mut tag = read_id3v2(path)?;
+    // Text frames are replaced by ID, comments and TXXX frames by description
+    for frame in Id3v2Tag::from(generic_tag(track, source_url, TagType::Id3v2)) {
+        tag.insert(frame);
+    }
+    if track.genres.len() > 1 {
+        // ID3v2.4 separates multiple values of a text frame with NUL
+        let genres = TextInformationFrame { encoding: TextEncoding::UTF8, value: track.genres.join("\0") };
+        tag.insert(Frame::new("TCON", FrameValue::Text(genres), FrameFlags::default())?);
+    }
+    if !track.spotify_uri.is_empty() {
+        tag.insert_user_text(SPOTIFY_URI_KEY.to_string(), track.spotify_uri.clone());
+    }
+    tag.save_to_path(path)
+        .with_context(|| format!("Failed to save tags to {:?}", path))?;
+    Ok(())
+}
+
+/// Update the M4A's ilst tag with everything known about `track`, keeping the other atoms.
+pub fn set_m4a_tags(path: impl AsRef<Path>, track: &TrackInfo, source_url: Option<&str>) -> Result<()> {
+    let path = path.as_ref();
+    let mut tag = read_ilst(path)?;
+    for atom in Ilst::from(generic_tag(track, source_url, TagType::Mp4Ilst)) {
+        tag.replace_atom(atom);
+    }
+    if track.genres.len() > 1 {
+        let genres = track.genres.iter().map(|g| AtomData::UTF8(g.clone())).collect();
+        tag.replace_atom(Atom::from_collection(AtomIdent::Fourcc(*b"\xa9gen"), genres).expect("at least one genre"));
+    }
+    if !track.spotify_uri.is_empty() {
+        tag.replace_atom(Atom::new(
+            AtomIdent::Freeform { mean: "com.apple.iTunes".into(), name: SPOTIFY_URI_KEY.into() },
+            AtomData::UTF8(track.spotify_uri.clone()),
+        ));
+    }
+    tag.save_to_path(path)
+        .with_context(|| format!("Failed to save tags to {:?}", path))?;
+    Ok(())
+}
+
+/// Update the Vorbis comments of an Ogg (Opus/Vorbis) or FLAC file with everything known
+/// about `track`, keeping the other fields and pictures.
+pub fn set_vorbis_tags(path: impl AsRef<Path>, track: &TrackInfo, source_url: Option<&str>) -> Result<()> {
+    let path = path.as_ref();
+    let mut tag = read_vorbis(path)?;
+    let fields = VorbisComments::from(generic_tag(track, source_url, TagType::VorbisComments));
+    for (key, value) in fields.items() {
+        tag.insert(key.to_string(), value.to_string());
+    }
+    if track.genres.len() > 1 {
+        // Vorbis comments repeat the field for multiple values
+        let _ = tag.remove("GENRE");
+        for genre in &track.genres {
+            tag.push("GENRE".to_string(), genre.clone());
+        }
+    }
+    if !track.spotify_uri.is_empty() {
+        tag.insert(SPOTIFY_URI_KEY.to_string(), track.spotify_uri.clone());
+    }
+    tag.save_to_path(path)
+        .with_context(|| format!("Failed to save tags to {:?}", path))?;
+    Ok(())
+}
+
+/// Tag `path` in the native format of its container.
+/// Containers without tag support (e.g. WebM kept by "best original") are left untouched.
+pub fn write_tags(path: &Path, track: &TrackInfo, source_url: Option<&str>) -> Result<()> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "mp3" | "aac" => set_mp3_tags(path, track, source_url),
+        "m4a" | "mp4" => set_m4a_tags(path, track, source_url),
+        "opus" | "ogg" | "oga" | "flac" => set_vorbis_tags(path, track, source_url),
+        _ => Ok(()),
+    }
+}
+
+/// Add or replace free-form text fields (ID3 TXXX, MP4 `----:com.apple.iTunes:` atoms,
+/// Vorbis comments) without touching the rest of the tag.
+pub fn set_custom_fields(path: &Path, fields: &BTreeMap<&str, String>) -> Result<()> {
+    let save_err = || format!("Failed to save tags to {:?}", path);
+    match FileType::from_path(path) {
+        Some(FileType::Mpeg | FileType::Aac) => {
+            let mut tag = read_id3v2(path)?;
+            for (key, value) in fields {
+                tag.insert_user_text(key.to_string(), value.clone());
+            }
+            tag.save_to_path(path).with_context(save_err)
+        }
+        Some(FileType::Mp4) => {
+            let mut tag = read_ilst(path)?;
+            for (key, value) in fields {
+                // iTunes convention: lower-case names under the com.apple.iTunes mean
+                tag.replace_atom(Atom::new(
+                    AtomIdent::Freeform { mean: "com.apple.iTunes".into(), name: key.to_lowercase().into() },
+                    AtomData::UTF8(value.clone()),
+                ));
+            }
+            tag.save_to_path(path).with_context(save_err)
+        }
+        Some(FileType::Flac | FileType::Opus | FileType::Vorbis) => {
+            let mut tag = read_vorbis(path)?;
+            for (key, value) in fields {
+                tag.insert(key.to_string(), value.clone());
+            }
+            tag.save_to_path(path).with_context(save_err)
+        }
+        _ => anyhow::bail!("custom tags are not supported for {:?}", path),
+    }
+}
+
+/// Re-encode `path` in place with extra ffmpeg output options (filters, `-ss`/`-to`, ...),
+/// keeping its format and metadata. The result goes to a temporary file first, so a failed
+/// run leaves the original untouched.
+pub fn reencode_in_place(tools: &Tools, path: &Path, options: Vec<OsString>, bitrate_kbps: u32) -> Result<()> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let format = OutputFormat::from_extension(ext).with_context(|| format!("cannot re-encode .{ext} files"))?;
+    let temp = path.with_extension(format!("processing.{ext}"));
+    encode(tools, path, &temp, options, format, bitrate_kbps)?;
+    fs::rename(&temp, path).with_context(|| format!("Failed to replace {:?}", path))
+}
+
+/// Encode the audio of `src` into `dst` as `format`, keeping the tags and the cover.
+/// `options` go before the codec arguments. A failed run leaves no `dst` behind.
+pub fn encode(
+    tools: &Tools,
+    src: &Path,
+    dst: &Path,
+    options: Vec<OsString>,
+    format: OutputFormat,
+    bitrate_kbps: u32,
+) -> Result<()> {
+    let mut args: Vec<OsString> =
+        vec!["-y".into(), "-i".into(), src.into(), "-map".into(), "0:a".into(), "-map_metadata".into(), "0".into()];
+    args.extend(options);
+    args.extend(format.ffmpeg_codec_args(bitrate_kbps).into_iter().map(OsString::from));
+    args.push(dst.into());
+    let output = tools.runner.run(tools.ffmpeg, &args).context("Failed to start ffmpeg")?;
+    if !output.success {
+        let _ = fs::remove_file(dst);
+        anyhow::bail!("ffmpeg failed: {}", output.stderr.trim());
+    }
+    // Only the audio stream is mapped, so the cover is carried over separately
+    if let Err(e) = copy_cover(src, dst) {
+        log::warn!("Could not keep the cover of {:?}: {e:#}", src);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{track, write_mp3};
+    use lofty::id3::v2::{CommentFrame, UnsynchronizedTextFrame};
+    use lofty::TaggedFileExt;
+
+    /// A FLAC stream with STREAMINFO (44.1 kHz, stereo, 16 bit) and PADDING blocks but no frames.
+    fn write_empty_flac(path: &Path) {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 34]);
+        data.extend_from_slice(&[0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&((44_100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&[0x81, 0x00, 0x00, 16]);
+        data.extend_from_slice(&[0; 16]);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn keeps_frames_it_does_not_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        write_mp3(&path);
+        let mut old = Id3v2Tag::default();
+        old.set_title("Old title".into());
+        let lyrics = UnsynchronizedTextFrame { encoding: TextEncoding::UTF8, language: *b"eng", description: String::new(), content: "la la la".into() };
+        old.insert(Frame::new("USLT", FrameValue::UnsynchronizedText(lyrics), FrameFlags::default()).unwrap());
+        let notes = CommentFrame { encoding: TextEncoding::UTF8, language: *b"eng", description: "notes".into(), content: "great".into() };
+        old.insert(Frame::new("COMM", FrameValue::Comment(notes), FrameFlags::default()).unwrap());
+        old.insert_user_text("REPLAYGAIN_TRACK_GAIN".into(), "-3.00 dB".into());
+        old.save_to_path(&path).unwrap();
+
+        write_tags(&path, &track("Song", "Artist", "Album"), None).unwrap();
+
+        let tag = read_id3v2(&path).unwrap();
+        assert_eq!(tag.title().as_deref(), Some("Song"));
+        assert_eq!(tag.get_user_text("REPLAYGAIN_TRACK_GAIN"), Some("-3.00 dB"));
+        assert_eq!(tag.unsync_text().map(|f| f.content.as_str()).collect::<Vec<_>>(), ["la la la"]);
+        let notes = tag.into_iter().find_map(|f| match f.content() {
+            FrameValue::Comment(c) => Some(c.content.clone()),
+            _ => None,
+        });
+        assert_eq!(notes.as_deref(), Some("great"));
+    }
+
+    #[test]
+    fn tags_raw_aac_with_id3() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.aac");
+        // ADTS frames (AAC LC, 44.1 kHz, stereo) of 13 bytes each, without an MP4 container
+        let frame = [0xFF, 0xF1, 0x50, 0x80, 0x01, 0xBF, 0xFC, 0, 0, 0, 0, 0, 0];
+        fs::write(&path, frame.repeat(8)).unwrap();
+
+        write_tags(&path, &track("Song", "Artist", "Album"), None).unwrap();
+
+        let tag = read_id3v2(&path).unwrap();
+        assert_eq!((tag.title().as_deref(), tag.album().as_deref()), (Some("Song"), Some("Album")));
+    }
+
+    #[test]
+    fn writes_vorbis_comments_to_flac() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        write_empty_flac(&path);
+        let mut song = track("Song", "Artist", "Album");
+        song.track_number = Some(4);
+        song.genres = vec!["rock".into(), "pop".into()];
+        song.spotify_uri = "spotify:track:abc".into();
+
+        write_tags(&path, &song, Some("https://example.com/v")).unwrap();
+
+        let file = <lofty::flac::FlacFile as lofty::AudioFile>::read_from(&mut fs::File::open(&path).unwrap(), lofty::ParseOptions::new()).unwrap();
+        let tag = file.vorbis_comments().unwrap();
+        assert_eq!(tag.get("TITLE"), Some("Song"));
+        assert_eq!(tag.get("TRACKNUMBER"), Some("4"));
+        assert_eq!(tag.get_all("GENRE").collect::<Vec<_>>(), ["rock", "pop"]);
+        assert_eq!(tag.get(SPOTIFY_URI_KEY), Some("spotify:track:abc"));
+        assert_eq!(tag.get("COMMENT"), Some("https://example.com/v"));
+    }
+
+    #[test]
+    fn encode_keeps_the_cover() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("song.mp3"), dir.path().join("song.device.mp3"));
+        write_mp3(&src);
+        crate::artwork::embed_cover(&src, b"jpeg bytes").unwrap();
+        let tool = crate::test_support::fake_tool();
+        let runner = crate::subprocess::SystemRunner::default();
+        let tools = Tools::new(&runner, Some(&tool), Some(&tool));
+
+        encode(&tools, &src, &dst, Vec::new(), OutputFormat::Mp3, 128).unwrap();
+
+        let tagged = lofty::read_from_path(&dst).unwrap();
+        let pictures: Vec<_> = tagged.tags().iter().flat_map(|t| t.pictures()).collect();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data(), b"jpeg bytes");
+    }
 }
\ No newline at end of file
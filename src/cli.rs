@@ -0,0 +1,161 @@
+use crate::config::{settings_path, Settings};
+use crate::csvparse::parse_csv;
+use crate::playlist::convert_playlist;
+use crate::events::ConversionEvent;
+use crate::logging;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::thread;
+
+const USAGE: &str = "Usage: spotify2media_rust --csv <playlist.csv> --output <dir> [options]
+
+Options:
+  --dry-run          Resolve tracks and write plan.json/plan.csv without downloading
+  --sync             Only convert tracks added since the last run into <dir>, and
+                     archive or delete the ones removed from the playlist
+  --device <path>    Copy the converted playlist to a device mounted at <path>
+  --yt-dlp <path>    yt-dlp executable (default: the one set in the GUI, or yt-dlp)
+  --ffmpeg <path>    ffmpeg executable (default: the one set in the GUI, or ffmpeg)
+  --config <path>    Settings file (default: config.json in the app's config folder)
+  --profile <name>   Settings profile to use (default: the one last saved in the GUI)
+  -h, --help         Show this help";
+
+/// Options for a headless run.
+#[derive(Debug, PartialEq)]
+pub struct CliArgs {
+    pub csv: PathBuf,
+    pub output: PathBuf,
+    /// Tool paths; `None` for the ones saved in the settings file.
+    pub yt_dlp: Option<PathBuf>,
+    pub ffmpeg: Option<PathBuf>,
+    /// Settings file; `None` for the one the GUI uses.
+    pub config: Option<PathBuf>,
+    /// Settings profile; `None` for the active one.
+    pub profile: Option<String>,
+    pub dry_run: bool,
+    pub sync: bool,
+    pub device: Option<PathBuf>,
+}
+
+/// Parse command line arguments (without the program name). `None` means help was asked for.
+pub fn parse_args(args: &[String]) -> Result<Option<CliArgs>, String> {
+    let mut csv = None;
+    let mut output = None;
+    let mut parsed = CliArgs {
+        csv: PathBuf::new(),
+        output: PathBuf::new(),
+        yt_dlp: None,
+        ffmpeg: None,
+        config: None,
+        profile: None,
+        dry_run: false,
+        sync: false,
+        device: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("Missing value for {arg}\n\n{USAGE}"));
+        match arg.as_str() {
+            "--csv" => csv = Some(value()?.into()),
+            "--output" | "-o" => output = Some(value()?.into()),
+            "--yt-dlp" => parsed.yt_dlp = Some(value()?.into()),
+            "--ffmpeg" => parsed.ffmpeg = Some(value()?.into()),
+            "--config" => parsed.config = Some(value()?.into()),
+            "--profile" => parsed.profile = Some(value()?.clone()),
+            "--dry-run" => parsed.dry_run = true,
+            "--sync" => parsed.sync = true,
+            "--device" => parsed.device = Some(value()?.into()),
+            "-h" | "--help" => return Ok(None),
+            other => return Err(format!("Unknown argument: {other}\n\n{USAGE}")),
+        }
+    }
+
+    parsed.csv = csv.ok_or_else(|| format!("--csv is required\n\n{USAGE}"))?;
+    parsed.output = output.ok_or_else(|| format!("--output is required\n\n{USAGE}"))?;
+    Ok(Some(parsed))
+}
+
+/// Run a conversion without the GUI, printing progress to stdout.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let Some(args) = parse_args(args)? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let config_path = args.config.clone().unwrap_or_else(settings_path);
+    let settings = Settings::load(&config_path).map_err(|e| format!("{e:#}"))?;
+    let profile = args.profile.as_deref().unwrap_or(&settings.active_profile);
+    let mut config = settings.profile(profile).map_err(|e| format!("{e:#}"))?.clone();
+    logging::set_level(config.log_level);
+    // The same tools as the GUI, unless given on the command line
+    let yt_dlp = args.yt_dlp.clone().unwrap_or_else(|| settings.gui.yt_dlp_path.clone());
+    let ffmpeg = args.ffmpeg.clone().unwrap_or_else(|| settings.gui.ffmpeg_path.clone());
+    config.dry_run = args.dry_run;
+    config.sync.enabled |= args.sync;
+    if let Some(mount) = &args.device {
+        config.device.enabled = true;
+        config.device.mount = mount.clone();
+    }
+
+    let tracks = parse_csv(&args.csv).map_err(|e| format!("CSV error: {e}"))?;
+    let (tx, rx) = mpsc::channel();
+    let cancel = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let (tracks, config, cancel, yt_dlp, ffmpeg) = (&tracks, &config, &cancel, &yt_dlp, &ffmpeg);
+        // The worker owns the sender, so `rx` is closed once it returns
+        let worker = scope.spawn(move || {
+            convert_playlist(tracks, config, yt_dlp, ffmpeg, &args.output, &tx, cancel)
+        });
+        let mut last_phase = None;
+        for event in rx {
+            match &event {
+                ConversionEvent::Progress(p) => {
+                    let key = (p.index, p.phase);
+                    if last_phase.replace(key) != Some(key) {
+                        println!("[{}/{}] {}: {}", p.index + 1, p.total, p.title, p.phase.label());
+                    }
+                }
+                ConversionEvent::Failed { .. } => {}
+                _ => {
+                    if let Some(line) = event.log_line() {
+                        log::log!(event.log_level(), "{line}");
+                        println!("{line}");
+                    }
+                }
+            }
+        }
+        worker.join().expect("conversion thread panicked").map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_dry_run_and_paths() {
+        let parsed = parse_args(&args(&["--csv", "a.csv", "-o", "out", "--dry-run", "--yt-dlp", "/bin/yt", "--sync", "--profile", "car USB"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.csv, PathBuf::from("a.csv"));
+        assert_eq!(parsed.output, PathBuf::from("out"));
+        assert_eq!(parsed.yt_dlp, Some(PathBuf::from("/bin/yt")));
+        assert_eq!(parsed.ffmpeg, None);
+        assert!(parsed.dry_run);
+        assert!(parsed.sync);
+        assert_eq!(parsed.profile.as_deref(), Some("car USB"));
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_arguments() {
+        assert!(parse_args(&args(&["--csv", "a.csv"])).unwrap_err().starts_with("--output is required"));
+        assert!(parse_args(&args(&["--csv"])).unwrap_err().starts_with("Missing value for --csv"));
+        assert!(parse_args(&args(&["--bogus"])).unwrap_err().starts_with("Unknown argument"));
+        assert_eq!(parse_args(&args(&["--csv", "a.csv", "--help"])), Ok(None));
+    }
+}
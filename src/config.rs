@@ -1,36 +1,901 @@
-use serde::{Serialize, Deserialize};
-use std::fs;
-use std::path::Path;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct AppConfig {
-    pub transcode_mp3: bool,
-    pub generate_m3u: bool,
-    pub exclude_instrumentals: bool,
-}
-
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            transcode_mp3: true,
-            generate_m3u: true,
-            exclude_instrumentals: false,
-        }
-    }
-}
-
-impl AppConfig {
-    pub fn load(path: &Path) -> Self {
-        if let Ok(txt) = fs::read_to_string(path) {
-            if let Ok(cfg) = serde_json::from_str(&txt) {
-                return cfg;
-            }
-        }
-        Self::default()
-    }
-
-    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
-        let txt = serde_json::to_string_pretty(self).unwrap();
-        fs::write(path, txt)
-    }
+use crate::naming::DEFAULT_TEMPLATE;
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use log::LevelFilter;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How a track found in the local library is placed into the output folder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibraryMode {
+    /// Copy the file as-is (tags are rewritten on the copy).
+    #[default]
+    Copy,
+    /// Re-encode the file to the configured output format with ffmpeg.
+    Transcode,
+    /// Hard link (or symlink) the file, leaving the original untouched.
+    Link,
+}
+
+impl LibraryMode {
+    pub const ALL: [LibraryMode; 3] = [LibraryMode::Copy, LibraryMode::Transcode, LibraryMode::Link];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LibraryMode::Copy => "Copy",
+            LibraryMode::Transcode => "Transcode",
+            LibraryMode::Link => "Link",
+        }
+    }
+}
+
+/// Container/codec of the converted files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Mp3,
+    /// AAC in an MP4 container.
+    M4a,
+    Opus,
+    /// Ogg Vorbis.
+    Vorbis,
+    Flac,
+    /// Keep the best audio stream as downloaded, without transcoding.
+    Original,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 6] = [
+        OutputFormat::Mp3,
+        OutputFormat::M4a,
+        OutputFormat::Opus,
+        OutputFormat::Vorbis,
+        OutputFormat::Flac,
+        OutputFormat::Original,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "MP3",
+            OutputFormat::M4a => "M4A (AAC)",
+            OutputFormat::Opus => "Opus",
+            OutputFormat::Vorbis => "Ogg Vorbis",
+            OutputFormat::Flac => "FLAC",
+            OutputFormat::Original => "Best original (no transcode)",
+        }
+    }
+
+    /// File extension of converted files; `None` for [`OutputFormat::Original`], which keeps the source's.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Mp3 => Some("mp3"),
+            OutputFormat::M4a => Some("m4a"),
+            OutputFormat::Opus => Some("opus"),
+            OutputFormat::Vorbis => Some("ogg"),
+            OutputFormat::Flac => Some("flac"),
+            OutputFormat::Original => None,
+        }
+    }
+
+    /// Value for yt-dlp's `--audio-format`.
+    pub fn yt_dlp_format(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::M4a => "m4a",
+            OutputFormat::Opus => "opus",
+            OutputFormat::Vorbis => "vorbis",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Original => "best",
+        }
+    }
+
+    /// Format of an existing file, judged by its extension.
+    pub fn from_extension(ext: &str) -> Option<OutputFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "mp3" => Some(OutputFormat::Mp3),
+            "m4a" | "mp4" | "aac" => Some(OutputFormat::M4a),
+            "opus" => Some(OutputFormat::Opus),
+            "ogg" | "oga" => Some(OutputFormat::Vorbis),
+            "flac" => Some(OutputFormat::Flac),
+            _ => None,
+        }
+    }
+
+    /// Whether a bitrate can be chosen; FLAC is lossless and the original is not re-encoded.
+    pub fn has_bitrate(self) -> bool {
+        !matches!(self, OutputFormat::Flac | OutputFormat::Original)
+    }
+
+    /// ffmpeg codec arguments; `bitrate_kbps == 0` selects the encoder's best VBR setting.
+    pub fn ffmpeg_codec_args(self, bitrate_kbps: u32) -> Vec<String> {
+        let bitrate = |default: u32| {
+            let kbps = if bitrate_kbps > 0 { bitrate_kbps } else { default };
+            vec!["-b:a".to_string(), format!("{kbps}k")]
+        };
+        let (codec, quality) = match self {
+            OutputFormat::Mp3 if bitrate_kbps == 0 => ("libmp3lame", vec!["-q:a".into(), "0".into()]),
+            OutputFormat::Mp3 => ("libmp3lame", bitrate(0)),
+            OutputFormat::M4a => ("aac", bitrate(256)),
+            OutputFormat::Opus => ("libopus", bitrate(160)),
+            OutputFormat::Vorbis if bitrate_kbps == 0 => ("libvorbis", vec!["-q:a".into(), "8".into()]),
+            OutputFormat::Vorbis => ("libvorbis", bitrate(0)),
+            OutputFormat::Flac => ("flac", Vec::new()),
+            OutputFormat::Original => ("copy", Vec::new()),
+        };
+        let mut args = vec!["-codec:a".to_string(), codec.to_string()];
+        args.extend(quality);
+        args
+    }
+}
+
+/// Retries for transient yt-dlp failures (rate limiting, network errors).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Extra attempts per query after the first one.
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 5_000,
+            max_backoff_ms: 120_000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Pause before retry number `retry` (starting at 0).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ms = self.initial_backoff_ms as f64 * self.multiplier.powi(retry as i32);
+        Duration::from_millis(ms.min(self.max_backoff_ms as f64) as u64)
+    }
+}
+
+/// Front cover embedded into converted files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoverArtConfig {
+    pub embed: bool,
+    /// Folder with user-supplied covers named after the album or track
+    /// (e.g. `Artist - Album.jpg`); preferred over the video thumbnail.
+    pub image_dir: Option<PathBuf>,
+    /// Covers are cropped to a square and scaled down to at most this many pixels per side.
+    pub max_size: u32,
+    /// Also write `cover.jpg` into each output folder that doesn't have one yet.
+    pub write_folder_cover: bool,
+}
+
+impl Default for CoverArtConfig {
+    fn default() -> Self {
+        Self {
+            embed: true,
+            image_dir: None,
+            max_size: 600,
+            write_folder_cover: false,
+        }
+    }
+}
+
+/// Which filesystem output names must be valid on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamingProfile {
+    /// Only `/` and control characters are replaced.
+    Linux,
+    /// No `<>:"/\|?*`, no trailing dots, no reserved names like `CON`, paths under 260 characters.
+    #[default]
+    Windows,
+    /// Windows rules, and no characters outside the Basic Multilingual Plane (e.g. emoji),
+    /// which car stereos and media players often mangle on FAT32/exFAT cards.
+    Fat32,
+    /// FAT32 rules with every name transliterated to plain ASCII.
+    Ascii,
+}
+
+impl NamingProfile {
+    pub const ALL: [NamingProfile; 4] =
+        [NamingProfile::Linux, NamingProfile::Windows, NamingProfile::Fat32, NamingProfile::Ascii];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NamingProfile::Linux => "Linux / macOS",
+            NamingProfile::Windows => "Windows",
+            NamingProfile::Fat32 => "FAT32 / exFAT (SD cards, car stereos)",
+            NamingProfile::Ascii => "ASCII only",
+        }
+    }
+}
+
+/// Playlist file written next to the converted tracks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistFormat {
+    /// Extended M3U (`#EXTM3U`), the original output, in the system's codepage as legacy
+    /// players expect. On Windows, names outside the codepage need [`PlaylistFormat::M3u8`].
+    M3u,
+    /// The same as UTF-8 `.m3u8`, which some players require for non-ASCII names.
+    M3u8,
+    Pls,
+    /// XML Shareable Playlist Format with full track metadata.
+    Xspf,
+    /// Windows Media Player playlist.
+    Wpl,
+    /// iTunes / Apple Music library XML with one playlist, importable via File > Library > Import Playlist.
+    ItunesXml,
+    /// Rekordbox collection XML (`DJ_PLAYLISTS`), imported via the rekordbox xml tree.
+    Rekordbox,
+    /// Traktor collection (`.nml`), imported via Import Collection.
+    Traktor,
+}
+
+impl PlaylistFormat {
+    pub const ALL: [PlaylistFormat; 8] = [
+        PlaylistFormat::M3u,
+        PlaylistFormat::M3u8,
+        PlaylistFormat::Pls,
+        PlaylistFormat::Xspf,
+        PlaylistFormat::Wpl,
+        PlaylistFormat::ItunesXml,
+        PlaylistFormat::Rekordbox,
+        PlaylistFormat::Traktor,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "M3U",
+            PlaylistFormat::M3u8 => "M3U8",
+            PlaylistFormat::Pls => "PLS",
+            PlaylistFormat::Xspf => "XSPF",
+            PlaylistFormat::Wpl => "WPL (Windows Media)",
+            PlaylistFormat::ItunesXml => "iTunes XML",
+            PlaylistFormat::Rekordbox => "Rekordbox XML",
+            PlaylistFormat::Traktor => "Traktor NML",
+        }
+    }
+
+    /// File name suffix, without the leading dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Wpl => "wpl",
+            // Not `.xml`, so it can sit next to other exports of the same playlist
+            PlaylistFormat::ItunesXml => "itunes.xml",
+            PlaylistFormat::Rekordbox => "rekordbox.xml",
+            PlaylistFormat::Traktor => "nml",
+        }
+    }
+}
+
+/// How playlists refer to the converted files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistPaths {
+    /// Relative to the playlist's folder, so the whole output folder can be moved.
+    #[default]
+    Relative,
+    Absolute,
+}
+
+/// Media server that reads the exported playlist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaServer {
+    /// Imports `.m3u8` files found in the music folder; honours `#PLAYLIST:` names.
+    #[default]
+    Navidrome,
+    /// Imports playlist files placed in a music library folder.
+    Jellyfin,
+    /// Has no folder import; the written file has to be imported into Plex by hand.
+    Plex,
+}
+
+impl MediaServer {
+    pub const ALL: [MediaServer; 3] = [MediaServer::Navidrome, MediaServer::Jellyfin, MediaServer::Plex];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MediaServer::Navidrome => "Navidrome",
+            MediaServer::Jellyfin => "Jellyfin",
+            MediaServer::Plex => "Plex",
+        }
+    }
+}
+
+/// Extra playlist whose entries point at the files as the media server sees them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaServerConfig {
+    pub enabled: bool,
+    pub server: MediaServer,
+    /// Local folder that the server has mounted as `server_root`; empty = the output folder.
+    pub local_root: PathBuf,
+    /// The same folder as the server sees it, e.g. `/music` or `D:\Music`.
+    pub server_root: String,
+    /// Where to write the playlist; unset = the output folder.
+    pub playlist_dir: Option<PathBuf>,
+}
+
+/// What a device export does when the next track would exceed the size budget.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPolicy {
+    /// Copy tracks in playlist order until one doesn't fit.
+    #[default]
+    Stop,
+    /// Leave out tracks that don't fit but keep filling with later, smaller ones.
+    SkipLarger,
+}
+
+impl BudgetPolicy {
+    pub const ALL: [BudgetPolicy; 2] = [BudgetPolicy::Stop, BudgetPolicy::SkipLarger];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BudgetPolicy::Stop => "Stop at the first track that doesn't fit",
+            BudgetPolicy::SkipLarger => "Skip tracks that don't fit",
+        }
+    }
+}
+
+/// Copying the converted playlist to a portable player or USB stick after a run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub enabled: bool,
+    /// Where the device is mounted, e.g. `E:\` or `/media/me/PLAYER`.
+    pub mount: PathBuf,
+    /// Music folder on the device, relative to `mount`.
+    pub folder: PathBuf,
+    /// Format on the device; [`OutputFormat::Original`] copies the files unchanged.
+    pub format: OutputFormat,
+    pub bitrate_kbps: u32,
+    /// Most space the playlist may take up on the device, in MB (0 = no limit).
+    pub size_budget_mb: u64,
+    pub budget_policy: BudgetPolicy,
+    /// File system rules for names on the device.
+    pub naming_profile: NamingProfile,
+    pub playlist_format: PlaylistFormat,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mount: PathBuf::new(),
+            folder: PathBuf::from("Music"),
+            format: OutputFormat::Original,
+            bitrate_kbps: 128,
+            size_budget_mb: 0,
+            budget_policy: BudgetPolicy::Stop,
+            naming_profile: NamingProfile::Fat32,
+            playlist_format: PlaylistFormat::M3u,
+        }
+    }
+}
+
+/// What sync does with the files of tracks that left the playlist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncRemoval {
+    /// Move them to the archive folder.
+    #[default]
+    Archive,
+    Delete,
+}
+
+impl SyncRemoval {
+    pub const ALL: [SyncRemoval; 2] = [SyncRemoval::Archive, SyncRemoval::Delete];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SyncRemoval::Archive => "Move to archive",
+            SyncRemoval::Delete => "Delete",
+        }
+    }
+}
+
+/// Re-exporting into a folder from an earlier run: only new tracks are converted and
+/// tracks dropped from the playlist are removed; see [`crate::sync`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub enabled: bool,
+    pub removal: SyncRemoval,
+    /// Where [`SyncRemoval::Archive`] moves files, relative to the output folder.
+    pub archive_dir: PathBuf,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            removal: SyncRemoval::Archive,
+            archive_dir: PathBuf::from("Removed"),
+        }
+    }
+}
+
+/// What to do about volume differences between tracks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoudnessMode {
+    #[default]
+    Off,
+    /// Measure and write ReplayGain track/album tags; the audio is untouched.
+    ReplayGain,
+    /// Re-encode every track to the target loudness (EBU R128).
+    Normalize,
+}
+
+impl LoudnessMode {
+    pub const ALL: [LoudnessMode; 3] = [LoudnessMode::Off, LoudnessMode::ReplayGain, LoudnessMode::Normalize];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LoudnessMode::Off => "Off",
+            LoudnessMode::ReplayGain => "ReplayGain tags",
+            LoudnessMode::Normalize => "Normalize (EBU R128)",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnessConfig {
+    pub mode: LoudnessMode,
+    /// Integrated loudness targeted by [`LoudnessMode::Normalize`], in LUFS.
+    pub target_lufs: f64,
+    /// Maximum true peak after normalization, in dBTP.
+    pub true_peak_db: f64,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            mode: LoudnessMode::Off,
+            target_lufs: -14.0,
+            true_peak_db: -1.0,
+        }
+    }
+}
+
+/// Removing non-music parts of a track.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrimConfig {
+    /// Cut leading and trailing silence found by ffmpeg's `silencedetect`.
+    pub silence: bool,
+    /// Audio quieter than this counts as silence, in dBFS.
+    pub silence_threshold_db: f64,
+    /// Shorter silences are left alone.
+    pub min_silence_secs: f64,
+    /// Let yt-dlp remove SponsorBlock `music_offtopic` segments (skits, intros in music videos).
+    pub sponsorblock: bool,
+}
+
+impl Default for TrimConfig {
+    fn default() -> Self {
+        Self {
+            silence: false,
+            silence_threshold_db: -50.0,
+            min_silence_secs: 1.0,
+            sponsorblock: false,
+        }
+    }
+}
+
+/// Checks run on every downloaded file before it is tagged.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerifyConfig {
+    pub enabled: bool,
+    /// Decode the whole file with ffmpeg to catch corrupt frames (slower).
+    pub decode_check: bool,
+    /// Allowed difference from the expected track length before a file is flagged as suspect.
+    pub duration_tolerance_secs: u64,
+    /// Lossy files below this bitrate are flagged as suspect (0 = no check).
+    pub min_bitrate_kbps: u32,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            decode_check: false,
+            duration_tolerance_secs: 15,
+            min_bitrate_kbps: 64,
+        }
+    }
+}
+
+/// Version of the settings format written by this build; see [`MIGRATIONS`].
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Profile that holds the settings of files from before profiles existed.
+pub const DEFAULT_PROFILE: &str = "Default";
+
+/// Settings file name, in the config directory and (before it moved there) the working directory.
+pub const CONFIG_FILE: &str = "config.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub output_format: OutputFormat,
+    /// Target bitrate in kbit/s for lossy formats; 0 = best variable bitrate.
+    pub bitrate_kbps: u32,
+    /// Output path of each track relative to the output folder, e.g.
+    /// `{album_artist}/{album}/{track:02} - {title}`; see [`crate::naming::NameTemplate`].
+    pub file_name_template: String,
+    pub naming_profile: NamingProfile,
+    /// Playlist files to write after a run; empty for none.
+    pub playlist_formats: Vec<PlaylistFormat>,
+    pub playlist_paths: PlaylistPaths,
+    pub media_server: MediaServerConfig,
+    pub exclude_instrumentals: bool,
+    /// Download each song only once even if it appears several times in the input.
+    pub skip_duplicates: bool,
+    /// Folders with music we already own; matching tracks are taken from here instead of yt-dlp.
+    pub library_dirs: Vec<PathBuf>,
+    pub library_mode: LibraryMode,
+    pub retry: RetryPolicy,
+    /// Kill yt-dlp/ffmpeg after this many seconds (0 = no limit).
+    pub download_timeout_secs: u64,
+    /// Kill yt-dlp/ffmpeg if it prints nothing for this many seconds (0 = no limit).
+    pub stall_timeout_secs: u64,
+    pub cover_art: CoverArtConfig,
+    pub verify: VerifyConfig,
+    pub loudness: LoudnessConfig,
+    pub trim: TrimConfig,
+    pub sync: SyncConfig,
+    pub device: DeviceConfig,
+    /// Most detailed records written to the log file; `debug` adds every tool command and its stderr.
+    pub log_level: LevelFilter,
+    /// Only resolve and write a plan report; never download or create audio files.
+    #[serde(skip)]
+    pub dry_run: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            output_format: OutputFormat::Mp3,
+            bitrate_kbps: 0,
+            file_name_template: DEFAULT_TEMPLATE.to_string(),
+            naming_profile: NamingProfile::Windows,
+            playlist_formats: vec![PlaylistFormat::M3u],
+            playlist_paths: PlaylistPaths::Relative,
+            media_server: MediaServerConfig::default(),
+            exclude_instrumentals: false,
+            skip_duplicates: true,
+            library_dirs: Vec::new(),
+            library_mode: LibraryMode::Copy,
+            retry: RetryPolicy::default(),
+            download_timeout_secs: 1800,
+            stall_timeout_secs: 180,
+            cover_art: CoverArtConfig::default(),
+            verify: VerifyConfig::default(),
+            loudness: LoudnessConfig::default(),
+            trim: TrimConfig::default(),
+            sync: SyncConfig::default(),
+            device: DeviceConfig::default(),
+            log_level: LevelFilter::Info,
+            dry_run: false,
+        }
+    }
+}
+
+/// Entries kept in each of the GUI's recent-files lists.
+const MAX_RECENT: usize = 10;
+
+/// What the GUI remembers between launches besides the profiles. Tool paths and folders
+/// belong to this machine, so they are not part of exported profiles.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiState {
+    pub yt_dlp_path: PathBuf,
+    pub ffmpeg_path: PathBuf,
+    pub output_dir: Option<PathBuf>,
+    pub dark_theme: bool,
+    /// Newest first.
+    pub recent_csvs: Vec<PathBuf>,
+    /// Newest first.
+    pub recent_output_dirs: Vec<PathBuf>,
+    /// Inner size of the main window, in points, when it was last closed.
+    pub window_size: Option<[f32; 2]>,
+}
+
+impl Default for GuiState {
+    fn default() -> Self {
+        Self {
+            yt_dlp_path: PathBuf::from("yt-dlp"),
+            ffmpeg_path: PathBuf::from("ffmpeg"),
+            output_dir: None,
+            dark_theme: true,
+            recent_csvs: Vec::new(),
+            recent_output_dirs: Vec::new(),
+            window_size: None,
+        }
+    }
+}
+
+/// Move `path` to the front of a recent-files list, dropping the oldest entry if it is full.
+pub fn remember(recent: &mut Vec<PathBuf>, path: &Path) {
+    recent.retain(|p| p != path);
+    recent.insert(0, path.to_path_buf());
+    recent.truncate(MAX_RECENT);
+}
+
+/// Everything in the settings file: named sets of [`AppConfig`] for different kinds
+/// of runs, e.g. "DJ lossless" and "car USB". An exported profile is a settings file
+/// with just that profile, so it goes through the same migrations when imported.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Format version of the file this was loaded from; files without one are version 0.
+    pub version: u32,
+    /// Profile used when none is chosen.
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, AppConfig>,
+    pub gui: GuiState,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), AppConfig::default())]),
+            gui: GuiState::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Read settings from `path`, upgrading older formats. A missing file gives the
+    /// defaults; a file that can't be read or parsed is an error.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(txt) => Self::parse(&txt, path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read settings {:?}", path)),
+        }
+    }
+
+    fn parse(txt: &str, path: &Path) -> Result<Self> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(txt).with_context(|| format!("Settings file {:?} is not valid JSON", path))?;
+        let obj = value.as_object_mut().with_context(|| format!("Settings file {:?} is not a JSON object", path))?;
+        let version = obj.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        if version > CONFIG_VERSION {
+            log::warn!("{:?} was written by a newer version (format {version}); unknown settings are ignored", path);
+        }
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            migration(obj);
+        }
+        obj.insert("version".into(), CONFIG_VERSION.into());
+        let mut settings: Self = serde_json::from_value(value).with_context(|| format!("Invalid settings in {:?}", path))?;
+        if settings.profiles.is_empty() {
+            settings.profiles.insert(DEFAULT_PROFILE.to_string(), AppConfig::default());
+        }
+        if !settings.profiles.contains_key(&settings.active_profile) {
+            settings.active_profile = settings.profiles.keys().next().unwrap().clone();
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let txt = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, txt)
+    }
+
+    /// The settings of the active profile.
+    pub fn active(&self) -> &AppConfig {
+        &self.profiles[&self.active_profile]
+    }
+
+    /// The profile called `name`; the error lists the ones there are.
+    pub fn profile(&self, name: &str) -> Result<&AppConfig> {
+        self.profiles.get(name).with_context(|| {
+            let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!("No settings profile named {name:?}; there are: {}", names.join(", "))
+        })
+    }
+
+    /// `name`, or `name (2)`, `name (3)`, ... if a profile of that name exists.
+    pub fn free_name(&self, name: &str) -> String {
+        let name = name.trim();
+        let name = if name.is_empty() { DEFAULT_PROFILE } else { name };
+        (1..)
+            .map(|n| if n == 1 { name.to_string() } else { format!("{name} ({n})") })
+            .find(|candidate| !self.profiles.contains_key(candidate))
+            .unwrap()
+    }
+
+    /// Write profile `name` to `path` as a file of its own, to share with others.
+    pub fn export(&self, name: &str, path: &Path) -> Result<()> {
+        let profile = Self {
+            version: CONFIG_VERSION,
+            active_profile: name.to_string(),
+            profiles: BTreeMap::from([(name.to_string(), self.profile(name)?.clone())]),
+            gui: GuiState::default(),
+        };
+        profile.save(path).with_context(|| format!("Failed to export profile to {:?}", path))
+    }
+
+    /// Add the profiles in the exported file (or settings file) at `path`, renaming the
+    /// ones whose name is taken, and return the names they were added under. The
+    /// importing machine's GUI state is kept.
+    pub fn import(&mut self, path: &Path) -> Result<Vec<String>> {
+        let txt = fs::read_to_string(path).with_context(|| format!("Failed to read profile {:?}", path))?;
+        let imported = Self::parse(&txt, path)?;
+        let mut names = Vec::with_capacity(imported.profiles.len());
+        for (name, config) in imported.profiles {
+            let name = self.free_name(&name);
+            self.profiles.insert(name.clone(), config);
+            names.push(name);
+        }
+        Ok(names)
+    }
+}
+
+/// The settings file in the platform's config directory. The first time it is asked for,
+/// a `config.json` left in the working directory by older versions is copied there.
+/// Falls back to the working directory if the platform has no config directory.
+pub fn settings_path() -> PathBuf {
+    let Some(dirs) = ProjectDirs::from("", "", "spotify2media") else { return PathBuf::from(CONFIG_FILE) };
+    let path = dirs.config_dir().join(CONFIG_FILE);
+    match migrate_legacy_file(Path::new(CONFIG_FILE), &path) {
+        Ok(true) => log::info!("Moved settings from {} in the working directory to {}", CONFIG_FILE, path.display()),
+        Ok(false) => {}
+        Err(e) => log::warn!("Could not copy {} to {}: {e}", CONFIG_FILE, path.display()),
+    }
+    path
+}
+
+/// Copy `legacy` to `target` unless `target` already exists. The original stays,
+/// as it may sit in a read-only folder or be shared with an older build.
+fn migrate_legacy_file(legacy: &Path, target: &Path) -> std::io::Result<bool> {
+    if target.exists() || !legacy.is_file() {
+        return Ok(false);
+    }
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(legacy, target)?;
+    Ok(true)
+}
+
+type Migration = fn(&mut serde_json::Map<String, serde_json::Value>);
+
+/// Upgrades between settings formats; entry `n` turns version `n` into `n + 1`.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0, migrate_v1];
+
+/// Version 0: files from before `version` existed, with the old boolean options.
+fn migrate_v0(obj: &mut serde_json::Map<String, serde_json::Value>) {
+    // `transcode_mp3: bool` chose between MP3 and M4A before `output_format` existed
+    if let Some(mp3) = obj.remove("transcode_mp3") {
+        if !obj.contains_key("output_format") {
+            let format = if mp3.as_bool().unwrap_or(true) { OutputFormat::Mp3 } else { OutputFormat::M4a };
+            obj.insert("output_format".into(), serde_json::to_value(format).unwrap());
+        }
+    }
+    // `generate_m3u: bool` became one of several `playlist_formats`
+    if let Some(m3u) = obj.remove("generate_m3u") {
+        if !obj.contains_key("playlist_formats") {
+            let formats = if m3u.as_bool().unwrap_or(true) { vec![PlaylistFormat::M3u] } else { Vec::new() };
+            obj.insert("playlist_formats".into(), serde_json::to_value(formats).unwrap());
+        }
+    }
+}
+
+/// Version 1: a single set of settings, which becomes the default profile.
+fn migrate_v1(obj: &mut serde_json::Map<String, serde_json::Value>) {
+    let mut config = std::mem::take(obj);
+    config.remove("version");
+    obj.insert("active_profile".into(), DEFAULT_PROFILE.into());
+    obj.insert("profiles".into(), serde_json::json!({ DEFAULT_PROFILE: config }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_transcode_mp3_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{"transcode_mp3": false, "generate_m3u": false}"#).unwrap();
+
+        let settings = Settings::load(&path).unwrap();
+
+        assert_eq!(settings.version, CONFIG_VERSION);
+        let config = settings.active();
+        assert_eq!(config.output_format, OutputFormat::M4a);
+        assert!(config.playlist_formats.is_empty());
+        settings.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains(r#""output_format": "m4a""#) && !saved.contains("transcode_mp3"));
+        assert!(!saved.contains("generate_m3u"));
+        assert!(saved.contains(&format!(r#""version": {CONFIG_VERSION}"#)));
+    }
+
+    #[test]
+    fn exports_and_imports_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, r#"{"version": 1, "output_format": "flac"}"#).unwrap();
+        let mut settings = Settings::load(&path).unwrap();
+        assert_eq!(settings.profile(DEFAULT_PROFILE).unwrap().output_format, OutputFormat::Flac);
+        let car = AppConfig { output_format: OutputFormat::Mp3, bitrate_kbps: 128, ..AppConfig::default() };
+        settings.profiles.insert("car USB".into(), car);
+
+        let shared = dir.path().join("car.json");
+        settings.export("car USB", &shared).unwrap();
+        assert!(format!("{:#}", settings.export("DJ", &shared).unwrap_err()).contains("there are: Default, car USB"));
+
+        assert_eq!(settings.import(&shared).unwrap(), ["car USB (2)"]);
+        assert_eq!(settings.profile("car USB (2)").unwrap().bitrate_kbps, 128);
+        // An old settings file imports as one profile, too
+        assert_eq!(settings.import(&path).unwrap(), ["Default (2)"]);
+        assert!(settings.import(&dir.path().join("missing.json")).is_err());
+    }
+
+    #[test]
+    fn keeps_gui_state_out_of_exported_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut settings = Settings::default();
+        settings.gui.yt_dlp_path = PathBuf::from("/opt/yt-dlp");
+        settings.gui.window_size = Some([900.0, 720.0]);
+        for n in 0..12 {
+            remember(&mut settings.gui.recent_csvs, Path::new(&format!("{n}.csv")));
+        }
+        remember(&mut settings.gui.recent_csvs, Path::new("5.csv"));
+        assert_eq!(settings.gui.recent_csvs.len(), MAX_RECENT);
+        assert_eq!(settings.gui.recent_csvs[..2], [PathBuf::from("5.csv"), PathBuf::from("11.csv")]);
+
+        let path = dir.path().join("config.json");
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path).unwrap().gui, settings.gui);
+        let shared = dir.path().join("shared.json");
+        settings.export(DEFAULT_PROFILE, &shared).unwrap();
+        assert_eq!(Settings::load(&shared).unwrap().gui, GuiState::default());
+        settings.import(&shared).unwrap();
+        assert_eq!(settings.gui.yt_dlp_path, PathBuf::from("/opt/yt-dlp"));
+    }
+
+    #[test]
+    fn reports_broken_settings_and_copies_legacy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("config.json");
+        fs::write(&legacy, r#"{"bitrate_kbps": "loud"}"#).unwrap();
+        assert!(format!("{:#}", Settings::load(&legacy).unwrap_err()).contains("Invalid settings"));
+        fs::write(&legacy, "{").unwrap();
+        assert!(Settings::load(&legacy).unwrap_err().to_string().contains("not valid JSON"));
+        assert_eq!(Settings::load(&dir.path().join("missing.json")).unwrap().version, CONFIG_VERSION);
+
+        let target = dir.path().join("config").join("config.json");
+        assert!(migrate_legacy_file(&legacy, &target).unwrap());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "{");
+        fs::write(&legacy, "{}").unwrap();
+        assert!(!migrate_legacy_file(&legacy, &target).unwrap());
+        assert!(legacy.exists());
+    }
 }
\ No newline at end of file
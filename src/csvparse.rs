@@ -1,43 +1,117 @@
-use std::path::Path;
-
-#[derive(Clone, Debug)]
-pub struct TrackInfo {
-    pub title: String,
-    pub artist: String,
-    pub album: String,
-}
-
-pub fn parse_csv(path: &Path) -> Result<Vec<TrackInfo>, String> {
-    let mut rdr = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
-    let headers = rdr.headers().map_err(|e| e.to_string())?.clone();
-    let has_header = headers.iter().any(|h| h.eq_ignore_ascii_case("title") || h.eq_ignore_ascii_case("track"));
-
-    let mut result = Vec::new();
-    for (i, rec) in rdr.records().enumerate() {
-        let rec = rec.map_err(|e| format!("CSV parse error on row {}: {}", i + 2, e))?;
-        // If header present, use header names, else use index
-        let (title, artist, album) = if has_header {
-            (
-                rec.get(headers.iter().position(|h| h.eq_ignore_ascii_case("title") || h.eq_ignore_ascii_case("track")).unwrap_or(0)).unwrap_or(""),
-                rec.get(headers.iter().position(|h| h.eq_ignore_ascii_case("artist")).unwrap_or(1)).unwrap_or(""),
-                rec.get(headers.iter().position(|h| h.eq_ignore_ascii_case("album")).unwrap_or(2)).unwrap_or(""),
-            )
-        } else {
-            (
-                rec.get(0).unwrap_or(""),
-                rec.get(1).unwrap_or(""),
-                rec.get(2).unwrap_or(""),
-            )
-        };
-        // Skip empty rows
-        if title.trim().is_empty() && artist.trim().is_empty() {
-            continue;
-        }
-        result.push(TrackInfo {
-            title: title.trim().to_string(),
-            artist: artist.trim().to_string(),
-            album: album.trim().to_string(),
-        });
-    }
-    Ok(result)
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TrackInfo {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    // Optional metadata, written to tags when the CSV provides it (e.g. Exportify exports)
+    pub album_artist: String,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    /// Release date as given, e.g. `2021` or `2021-05-14`.
+    pub release_date: String,
+    pub genres: Vec<String>,
+    pub isrc: String,
+    /// e.g. `spotify:track:4uLU6hMCjMI75M1A2tKUQC`.
+    pub spotify_uri: String,
+    /// Length of the original track, used to verify downloads.
+    pub duration_ms: Option<u64>,
+}
+
+/// Accepted header names for each column, compared case-insensitively.
+const TITLE_HEADERS: &[&str] = &["title", "track", "track name"];
+const ARTIST_HEADERS: &[&str] = &["artist", "artists", "artist name(s)"];
+const ALBUM_HEADERS: &[&str] = &["album", "album name"];
+
+pub fn parse_csv(path: &Path) -> Result<Vec<TrackInfo>, String> {
+    let mut rdr = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+    let headers = rdr.headers().map_err(|e| e.to_string())?.clone();
+    let column = |names: &[&str]| headers.iter().position(|h| names.iter().any(|n| h.trim().eq_ignore_ascii_case(n)));
+    let has_header = column(TITLE_HEADERS).is_some();
+
+    let mut result = Vec::new();
+    for (i, rec) in rdr.records().enumerate() {
+        let rec = rec.map_err(|e| format!("CSV parse error on row {}: {}", i + 2, e))?;
+        // If header present, use header names, else use index
+        let (title, artist, album) = if has_header {
+            (
+                rec.get(column(TITLE_HEADERS).unwrap_or(0)).unwrap_or(""),
+                rec.get(column(ARTIST_HEADERS).unwrap_or(1)).unwrap_or(""),
+                rec.get(column(ALBUM_HEADERS).unwrap_or(2)).unwrap_or(""),
+            )
+        } else {
+            (
+                rec.get(0).unwrap_or(""),
+                rec.get(1).unwrap_or(""),
+                rec.get(2).unwrap_or(""),
+            )
+        };
+        // Skip empty rows
+        if title.trim().is_empty() && artist.trim().is_empty() {
+            continue;
+        }
+        // Extra columns are only recognised by header
+        let text = |names: &[&str]| {
+            column(names)
+                .filter(|_| has_header)
+                .and_then(|i| rec.get(i))
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default()
+        };
+        let number = |names: &[&str]| text(names).parse::<u32>().ok().filter(|n| *n > 0);
+        result.push(TrackInfo {
+            title: title.trim().to_string(),
+            artist: artist.trim().to_string(),
+            album: album.trim().to_string(),
+            album_artist: text(&["album artist", "album artist name(s)", "albumartist"]),
+            track_number: number(&["track number", "tracknumber"]),
+            track_total: number(&["track total", "total tracks", "tracktotal"]),
+            disc_number: number(&["disc number", "discnumber"]),
+            disc_total: number(&["disc total", "total discs", "disctotal"]),
+            release_date: text(&["release date", "album release date", "date", "year"]),
+            genres: text(&["genres", "genre", "artist genres"])
+                .split([',', ';'])
+                .map(str::trim)
+                .filter(|g| !g.is_empty())
+                .map(String::from)
+                .collect(),
+            isrc: text(&["isrc"]),
+            spotify_uri: text(&["spotify uri", "track uri", "uri"]),
+            duration_ms: text(&["track duration (ms)", "duration (ms)", "duration_ms"]).parse().ok().filter(|ms| *ms > 0),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_exportify_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("export.csv");
+        std::fs::write(
+            &csv,
+            "Track URI,Track Name,Artist Name(s),Album Name,Album Artist Name(s),Album Release Date,Disc Number,Track Number,Track Duration (ms),ISRC,Artist Genres\n\
+             spotify:track:abc,Song,Artist A,Album,Various Artists,2021-05-14,1,3,215000,USRC17607839,\"indie pop,dream pop\"\n",
+        )
+        .unwrap();
+
+        let tracks = parse_csv(&csv).unwrap();
+
+        let t = &tracks[0];
+        assert_eq!((t.title.as_str(), t.artist.as_str(), t.album.as_str()), ("Song", "Artist A", "Album"));
+        assert_eq!(t.album_artist, "Various Artists");
+        assert_eq!((t.track_number, t.disc_number, t.track_total), (Some(3), Some(1), None));
+        assert_eq!(t.release_date, "2021-05-14");
+        assert_eq!(t.genres, ["indie pop", "dream pop"]);
+        assert_eq!(t.isrc, "USRC17607839");
+        assert_eq!(t.duration_ms, Some(215_000));
+        assert_eq!(t.spotify_uri, "spotify:track:abc");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,353 @@
+use crate::audio::encode;
+use crate::config::{AppConfig, BudgetPolicy, DeviceConfig, OutputFormat, PlaylistPaths};
+use crate::events::ConversionEvent;
+use crate::naming::sanitize_component;
+use crate::playlist::write_playlists;
+use crate::progress::{TrackPhase, TrackProgress};
+use crate::spotify2media::{Tools, TrackResult};
+use crate::verify::probe;
+use anyhow::{bail, Context, Result};
+use lofty::AudioFile;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+const MB: u64 = 1024 * 1024;
+
+/// What a device export did.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceReport {
+    /// Folder on the device holding the playlist file.
+    pub dir: PathBuf,
+    /// Files written to the device in this run.
+    pub copied: Vec<PathBuf>,
+    /// Tracks whose file was already on the device.
+    pub existing: usize,
+    /// Indices of tracks left out because of the size budget.
+    pub left_out: Vec<usize>,
+    /// Space the playlist takes up on the device, including files that were already there.
+    pub bytes: u64,
+    pub playlist_files: Vec<PathBuf>,
+}
+
+impl DeviceReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Copied {} track(s) to {} ({} already there, {} did not fit, {:.1} MB used).",
+            self.copied.len(),
+            self.dir.display(),
+            self.existing,
+            self.left_out.len(),
+            self.bytes as f64 / MB as f64
+        )
+    }
+}
+
+/// Check the device settings before a run starts, so a missing stick is noticed before the downloads.
+pub fn check_device(device: &DeviceConfig) -> Result<()> {
+    if device.mount.as_os_str().is_empty() {
+        bail!("device export needs the folder the device is mounted at");
+    }
+    if !device.mount.is_dir() {
+        bail!("device folder {:?} does not exist; is the device plugged in?", device.mount);
+    }
+    Ok(())
+}
+
+/// Where `output` goes on the device: the same path below the playlist's folder, with
+/// names made valid for the device and the extension of the device format.
+fn device_path(output: &Path, output_dir: &Path, playlist_dir: &Path, device: &DeviceConfig) -> PathBuf {
+    let relative = output.strip_prefix(output_dir).unwrap_or(output);
+    let mut path = playlist_dir.to_path_buf();
+    if let Some(folders) = relative.parent() {
+        for part in folders.iter() {
+            path.push(sanitize_component(&part.to_string_lossy(), device.naming_profile));
+        }
+    }
+    // Only the stem is sanitized, so cutting a long name can't take the extension with it
+    let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = sanitize_component(&stem, device.naming_profile);
+    let ext = device.format.extension().map(str::to_string).or_else(|| relative.extension().map(|e| e.to_string_lossy().into_owned()));
+    if let Some(ext) = ext {
+        file_name.push('.');
+        file_name.push_str(&ext);
+    }
+    path.push(file_name);
+    path
+}
+
+/// Rough size of `result` once transcoded to the device format, so the budget can be checked
+/// before spending time on the encode. Lossless output is taken to be as big as the source.
+fn estimated_size(result: &TrackResult, device: &DeviceConfig) -> u64 {
+    let source_size = fs::metadata(&result.output).map(|m| m.len()).unwrap_or(0);
+    // Typical bitrates of the quality settings used when none is configured
+    let kbps = match device.format {
+        OutputFormat::Flac | OutputFormat::Original => return source_size,
+        _ if device.bitrate_kbps > 0 => device.bitrate_kbps,
+        OutputFormat::Mp3 => 245,
+        OutputFormat::M4a | OutputFormat::Vorbis => 256,
+        OutputFormat::Opus => 160,
+    };
+    let duration = lofty::read_from_path(&result.output)
+        .ok()
+        .map(|file| file.properties().duration())
+        .filter(|d| !d.is_zero())
+        .or(result.track.duration_ms.map(Duration::from_millis));
+    match duration {
+        Some(duration) => (duration.as_secs_f64() * f64::from(kbps) * 1000.0 / 8.0) as u64,
+        None => source_size,
+    }
+}
+
+/// Whether the file already on the device can stay. Copies are compared by size;
+/// transcoded files can't be, so they count if they are not older than the source.
+fn up_to_date(src: &Path, dst: &Path, transcode: bool) -> Option<u64> {
+    let (src, on_device) = (fs::metadata(src).ok()?, fs::metadata(dst).ok()?);
+    let current = if transcode {
+        matches!((src.modified(), on_device.modified()), (Ok(source), Ok(copy)) if copy >= source)
+    } else {
+        src.len() == on_device.len()
+    };
+    current.then_some(on_device.len())
+}
+
+/// Encode `src` next to `dst` and move it into place once done, so an interrupted
+/// encode never leaves a partial file that a later run would take as finished.
+fn transcode_to(tools: &Tools, src: &Path, dst: &Path, device: &DeviceConfig) -> Result<()> {
+    // The extension stays last so ffmpeg and lofty still recognize the format
+    let mut name = dst.file_stem().unwrap_or_default().to_os_string();
+    name.push(".part");
+    if let Some(ext) = dst.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    let partial = dst.with_file_name(name);
+    encode(tools, src, &partial, Vec::new(), device.format, device.bitrate_kbps)?;
+    if let Err(e) = fs::rename(&partial, dst) {
+        let _ = fs::remove_file(&partial);
+        return Err(e).with_context(|| format!("Failed to move {:?} to {:?}", partial, dst));
+    }
+    Ok(())
+}
+
+/// Copy (or transcode) the converted tracks to `config.device`, in playlist order and
+/// within the size budget, then write a playlist next to them with device-relative paths.
+pub fn export_to_device(
+    tools: &Tools,
+    results: &[TrackResult],
+    name: &str,
+    config: &AppConfig,
+    output_dir: &Path,
+    events: &Sender<ConversionEvent>,
+    cancel: &AtomicBool,
+) -> Result<DeviceReport> {
+    let device = &config.device;
+    let dir = device.mount.join(&device.folder);
+    let playlist_dir = dir.join(sanitize_component(name, device.naming_profile));
+    let transcode = device.format != OutputFormat::Original;
+    let budget = (device.size_budget_mb > 0).then_some(device.size_budget_mb * MB);
+    let fits = |used: u64, size: u64| budget.is_none_or(|b| used + size <= b);
+
+    let mut report = DeviceReport { dir: dir.clone(), ..DeviceReport::default() };
+    let mut on_device = Vec::with_capacity(results.len());
+    for (n, result) in results.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let dst = device_path(&result.output, output_dir, &playlist_dir, device);
+        let mut progress = TrackProgress::new(n, results.len(), &result.track.title);
+        progress.phase = TrackPhase::Copying;
+        let _ = events.send(ConversionEvent::Progress(progress));
+
+        let existing = up_to_date(&result.output, &dst, transcode);
+        let create_parent = || match dst.parent() {
+            Some(parent) => fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent)),
+            None => Ok(()),
+        };
+        let size = match existing {
+            Some(size) => size,
+            // Tracks that clearly won't fit aren't transcoded; the real size is only known afterwards
+            None if transcode => {
+                let estimate = estimated_size(result, device);
+                if fits(report.bytes, estimate) {
+                    create_parent()?;
+                    transcode_to(tools, &result.output, &dst, device)?;
+                    fs::metadata(&dst)?.len()
+                } else {
+                    estimate
+                }
+            }
+            None => fs::metadata(&result.output)?.len(),
+        };
+        if !fits(report.bytes, size) {
+            if existing.is_none() && transcode {
+                let _ = fs::remove_file(&dst);
+            }
+            report.left_out.push(result.index);
+            let message = format!("not copied to the device: over the {} MB budget", device.size_budget_mb);
+            let _ = events.send(ConversionEvent::Warning { index: result.index, message });
+            if device.budget_policy == BudgetPolicy::Stop {
+                report.left_out.extend(results[n + 1..].iter().map(|r| r.index));
+                break;
+            }
+            continue;
+        }
+        match existing {
+            Some(_) => report.existing += 1,
+            None => {
+                if !transcode {
+                    create_parent()?;
+                    fs::copy(&result.output, &dst).with_context(|| format!("Failed to copy {:?} to {:?}", result.output, dst))?;
+                }
+                report.copied.push(dst.clone());
+            }
+        }
+        report.bytes += size;
+        // A transcoded copy has its own bitrate, so it is read again
+        let media = if transcode { probe(&dst).ok() } else { result.media.clone() };
+        on_device.push(TrackResult { output: dst, media, ..result.clone() });
+    }
+
+    let playlist_config = AppConfig {
+        playlist_formats: vec![device.playlist_format],
+        playlist_paths: PlaylistPaths::Relative,
+        naming_profile: device.naming_profile,
+        ..config.clone()
+    };
+    // Nothing may have been copied, so the folder might not exist yet
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    report.playlist_files = write_playlists(&dir, name, &on_device, &playlist_config)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify2media::TrackSource;
+    use crate::subprocess::{SystemRunner, ToolOutput};
+    use crate::test_support::{fake_tool, track, RecordingRunner};
+
+    fn result(index: usize, path: PathBuf) -> TrackResult {
+        TrackResult {
+            index,
+            track: track(&format!("Song {index}"), "Artist", ""),
+            source: TrackSource::Previous,
+            output: path,
+            suspect: None,
+            loudness: None,
+            cuts: Vec::new(),
+            media: None,
+        }
+    }
+
+    fn export(results: &[TrackResult], config: &AppConfig, out: &Path) -> DeviceReport {
+        let tool = fake_tool();
+        let runner = SystemRunner::default();
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let tools = Tools::new(&runner, Some(&tool), Some(&tool));
+        export_to_device(&tools, results, "Car: Mix", config, out, &tx, &AtomicBool::new(false)).unwrap()
+    }
+
+    #[test]
+    fn copies_within_budget_and_skips_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, stick) = (dir.path().join("out"), dir.path().join("stick"));
+        fs::create_dir_all(&out).unwrap();
+        let sizes = [400_000, 700_000, 300_000];
+        let results: Vec<_> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| {
+                let path = out.join(format!("Song {i}: live.mp3"));
+                fs::write(&path, vec![0u8; *size]).unwrap();
+                result(i, path)
+            })
+            .collect();
+        let mut config = AppConfig::default();
+        config.device.mount = stick.clone();
+        config.device.size_budget_mb = 1;
+
+        let report = export(&results, &config, &out);
+
+        let folder = stick.join("Music").join("Car_ Mix");
+        assert_eq!(report.copied, [folder.join("Song 0_ live.mp3")]);
+        assert_eq!(report.left_out, [1, 2]);
+        assert!(!folder.join("Song 1_ live.mp3").exists());
+        let m3u = fs::read_to_string(stick.join("Music").join("Car_ Mix.m3u")).unwrap();
+        assert!(m3u.contains(&format!("Car_ Mix{}Song 0_ live.mp3", std::path::MAIN_SEPARATOR)));
+        assert!(!m3u.contains("Song 1"));
+
+        // Prioritizing by order fills the rest with later tracks; the first copy stays
+        config.device.budget_policy = BudgetPolicy::SkipLarger;
+        let report = export(&results, &config, &out);
+        assert_eq!(report.copied, [folder.join("Song 2_ live.mp3")]);
+        assert_eq!((report.existing, report.left_out.as_slice(), report.bytes), (1, [1].as_slice(), 700_000));
+
+        // Transcoding writes the device format
+        config.device = DeviceConfig { mount: stick.clone(), format: OutputFormat::Opus, ..DeviceConfig::default() };
+        let report = export(&results[..1], &config, &out);
+        assert_eq!(report.copied, [folder.join("Song 0_ live.opus")]);
+    }
+
+    #[test]
+    fn transcoded_copies_older_than_the_source_are_redone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, stick) = (dir.path().join("out"), dir.path().join("stick"));
+        fs::create_dir_all(&out).unwrap();
+        let path = out.join("Song 0.mp3");
+        fs::write(&path, vec![0u8; 1000]).unwrap();
+        let results = [result(0, path.clone())];
+        let device = DeviceConfig { mount: stick.clone(), format: OutputFormat::Opus, ..DeviceConfig::default() };
+        let config = AppConfig { device, ..AppConfig::default() };
+        let folder = stick.join("Music").join("Car_ Mix");
+        let dst = folder.join("Song 0.opus");
+
+        assert_eq!(export(&results, &config, &out).copied, std::slice::from_ref(&dst));
+        let names: Vec<_> = fs::read_dir(&folder).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["Song 0.opus"]);
+        assert_eq!(export(&results, &config, &out).existing, 1);
+
+        // The source was converted again after the copy was made
+        let older = fs::metadata(&path).unwrap().modified().unwrap() - Duration::from_secs(60);
+        fs::File::options().write(true).open(&dst).unwrap().set_modified(older).unwrap();
+        let report = export(&results, &config, &out);
+        assert_eq!((report.copied.as_slice(), report.existing), ([dst].as_slice(), 0));
+    }
+
+    #[test]
+    fn long_names_keep_their_extension() {
+        let out = Path::new("/out");
+        let output = out.join("Live").join(format!("{}.mp3", "x".repeat(300)));
+        let device = DeviceConfig { format: OutputFormat::Opus, ..DeviceConfig::default() };
+
+        let dst = device_path(&output, out, Path::new("/stick/Mix"), &device);
+
+        assert_eq!(dst.parent().unwrap(), Path::new("/stick/Mix/Live"));
+        assert_eq!(dst.extension().unwrap(), "opus");
+        assert!(dst.file_stem().unwrap().len() <= 200);
+    }
+
+    #[test]
+    fn tracks_over_budget_are_not_transcoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, stick) = (dir.path().join("out"), dir.path().join("stick"));
+        fs::create_dir_all(&out).unwrap();
+        let path = out.join("Song 0.flac");
+        fs::write(&path, b"not really flac").unwrap();
+        let mut long = result(0, path);
+        long.track.duration_ms = Some(600_000);
+        let device = DeviceConfig { mount: stick, format: OutputFormat::Opus, size_budget_mb: 1, ..DeviceConfig::default() };
+        let config = AppConfig { device, ..AppConfig::default() };
+        let runner = RecordingRunner::new(|_| Ok(ToolOutput { success: true, ..ToolOutput::default() }));
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let tool = fake_tool();
+        let tools = Tools::new(&runner, Some(&tool), Some(&tool));
+
+        let report = export_to_device(&tools, &[long], "Mix", &config, &out, &tx, &AtomicBool::new(false)).unwrap();
+
+        assert_eq!(report.left_out, [0]);
+        assert_eq!(runner.call_count(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::playlist::{itunes_url, xml_escape, Entry};
+use std::fs;
+use std::path::{Component, Path, Prefix};
+
+/// Rekordbox's `Kind` column, e.g. `MP3 File`.
+fn kind(path: &Path) -> String {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("audio").to_ascii_uppercase();
+    format!("{ext} File")
+}
+
+fn attr(name: &str, value: impl ToString) -> String {
+    format!(" {name}=\"{}\"", xml_escape(&value.to_string()))
+}
+
+/// Rekordbox `DJ_PLAYLISTS` XML: every track in `COLLECTION` and one playlist node under `ROOT`.
+/// Rekordbox needs absolute `file://localhost/` locations, whatever the playlist path setting.
+pub fn rekordbox_xml(title: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<DJ_PLAYLISTS Version=\"1.0.0\">\n");
+    xml.push_str(&format!(
+        "  <PRODUCT Name=\"spotify2media\" Version=\"{}\" Company=\"\"/>\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    xml.push_str(&format!("  <COLLECTION Entries=\"{}\">\n", entries.len()));
+    for (n, e) in entries.iter().enumerate() {
+        let t = e.track();
+        let mut track = String::from("    <TRACK");
+        track += &attr("TrackID", n + 1);
+        track += &attr("Name", &t.title);
+        track += &attr("Artist", &t.artist);
+        track += &attr("Album", &t.album);
+        track += &attr("Genre", t.genres.join(", "));
+        track += &attr("Kind", kind(&e.absolute));
+        track += &attr("Size", fs::metadata(&e.absolute).map(|m| m.len()).unwrap_or(0));
+        track += &attr("TotalTime", e.duration_ms.map(|ms| ms / 1000).unwrap_or(0));
+        track += &attr("DiscNumber", t.disc_number.unwrap_or(0));
+        track += &attr("TrackNumber", t.track_number.unwrap_or(0));
+        track += &attr("Year", t.release_date.get(..4).unwrap_or(""));
+        track += &attr("BitRate", e.media.as_ref().and_then(|m| m.bitrate_kbps).unwrap_or(0));
+        track += &attr("SampleRate", e.media.as_ref().and_then(|m| m.sample_rate).unwrap_or(0));
+        track += &attr("Comments", &t.spotify_uri);
+        track += &attr("Location", itunes_url(&e.absolute));
+        xml.push_str(&track);
+        xml.push_str("/>\n");
+    }
+    xml.push_str("  </COLLECTION>\n  <PLAYLISTS>\n    <NODE Type=\"0\" Name=\"ROOT\" Count=\"1\">\n");
+    xml.push_str(&format!(
+        "      <NODE Name=\"{}\" Type=\"1\" KeyType=\"0\" Entries=\"{}\">\n",
+        xml_escape(title),
+        entries.len()
+    ));
+    for n in 1..=entries.len() {
+        xml.push_str(&format!("        <TRACK Key=\"{n}\"/>\n"));
+    }
+    xml.push_str("      </NODE>\n    </NODE>\n  </PLAYLISTS>\n</DJ_PLAYLISTS>\n");
+    xml
+}
+
+/// A file location the way Traktor stores it: the volume (`C:` on Windows, the volume name
+/// on macOS), the folder as `/:dir/:sub/:` and the bare file name. Other systems have no
+/// volume names, so their locations leave the volume empty.
+#[derive(Debug, PartialEq)]
+struct TraktorLocation {
+    volume: String,
+    dir: String,
+    file: String,
+}
+
+/// Name of the macOS boot volume: the entry in `/Volumes` that links back to `/`.
+/// Empty elsewhere, or if there is no such entry.
+fn boot_volume() -> String {
+    if !cfg!(target_os = "macos") {
+        return String::new();
+    }
+    let root = Path::new("/");
+    fs::read_dir("/Volumes")
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .find(|e| fs::canonicalize(e.path()).is_ok_and(|target| target == root))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl TraktorLocation {
+    /// Paths without a drive letter or `/Volumes/<name>` prefix get `boot_volume`.
+    fn new(path: &Path, boot_volume: &str) -> Self {
+        let mut volume = None;
+        let mut dirs = Vec::new();
+        for component in path.parent().unwrap_or(Path::new("")).components() {
+            match component {
+                Component::Prefix(prefix) => {
+                    volume = Some(match prefix.kind() {
+                        Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => format!("{}:", letter as char),
+                        _ => prefix.as_os_str().to_string_lossy().into_owned(),
+                    })
+                }
+                Component::Normal(name) => dirs.push(name.to_string_lossy().into_owned()),
+                _ => {}
+            }
+        }
+        if volume.is_none() && dirs.first().is_some_and(|d| d == "Volumes") && dirs.len() > 1 {
+            dirs.remove(0);
+            volume = Some(dirs.remove(0));
+        }
+        let dir: String = dirs.iter().map(|d| format!("/:{d}")).collect::<String>() + "/:";
+        Self {
+            volume: volume.unwrap_or_else(|| boot_volume.to_string()),
+            dir,
+            file: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        }
+    }
+
+    /// The key playlists use to refer to collection entries.
+    fn primary_key(&self) -> String {
+        format!("{}{}{}", self.volume, self.dir, self.file)
+    }
+}
+
+/// Stable 32-digit hex id for the playlist, so re-exports replace rather than duplicate it.
+///
+/// Uses FNV-1a rather than the std hasher, whose output may change between Rust releases.
+fn playlist_uuid(title: &str) -> String {
+    let half = |salt: u8| {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &byte in std::iter::once(&salt).chain(title.as_bytes()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        hash
+    };
+    format!("{:016x}{:016x}", half(0), half(1))
+}
+
+/// Traktor NML collection with one playlist. Locations are always absolute.
+pub fn traktor_nml(title: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\" ?>\n<NML VERSION=\"19\">\n");
+    xml.push_str("<HEAD COMPANY=\"www.native-instruments.com\" PROGRAM=\"Traktor\"></HEAD>\n");
+    xml.push_str(&format!("<COLLECTION ENTRIES=\"{}\">\n", entries.len()));
+    let boot = boot_volume();
+    let locations: Vec<_> = entries.iter().map(|e| TraktorLocation::new(&e.absolute, &boot)).collect();
+    for (e, location) in entries.iter().zip(&locations) {
+        let t = e.track();
+        xml.push_str(&format!("<ENTRY{}{}>\n", attr("TITLE", &t.title), attr("ARTIST", &t.artist)));
+        xml.push_str(&format!(
+            "<LOCATION{}{}{}></LOCATION>\n",
+            attr("DIR", &location.dir),
+            attr("FILE", &location.file),
+            attr("VOLUME", &location.volume)
+        ));
+        let mut album = String::from("<ALBUM");
+        if let Some(n) = t.track_number {
+            album += &attr("TRACK", n);
+        }
+        album += &attr("TITLE", &t.album);
+        xml.push_str(&(album + "></ALBUM>\n"));
+        let mut info = String::from("<INFO");
+        if let Some(genre) = t.genres.first() {
+            info += &attr("GENRE", genre);
+        }
+        if let Some(ms) = e.duration_ms {
+            info += &attr("PLAYTIME", ms / 1000);
+            info += &attr("PLAYTIME_FLOAT", format!("{:.6}", ms as f64 / 1000.0));
+        }
+        if let Some(kbps) = e.media.as_ref().and_then(|m| m.bitrate_kbps) {
+            info += &attr("BITRATE", kbps * 1000);
+        }
+        // Traktor writes dates as YYYY/M/D
+        let date: Vec<u32> = t.release_date.split('-').filter_map(|p| p.parse().ok()).collect();
+        if !date.is_empty() {
+            let parts = [date[0], date.get(1).copied().unwrap_or(1), date.get(2).copied().unwrap_or(1)];
+            info += &attr("RELEASE_DATE", format!("{}/{}/{}", parts[0], parts[1], parts[2]));
+        }
+        if let Ok(meta) = fs::metadata(&e.absolute) {
+            info += &attr("FILESIZE", meta.len() / 1024);
+        }
+        xml.push_str(&(info + "></INFO>\n</ENTRY>\n"));
+    }
+    xml.push_str("</COLLECTION>\n<PLAYLISTS>\n<NODE TYPE=\"FOLDER\" NAME=\"$ROOT\">\n<SUBNODES COUNT=\"1\">\n");
+    xml.push_str(&format!("<NODE TYPE=\"PLAYLIST\"{}>\n", attr("NAME", title)));
+    xml.push_str(&format!(
+        "<PLAYLIST ENTRIES=\"{}\" TYPE=\"LIST\" UUID=\"{}\">\n",
+        entries.len(),
+        playlist_uuid(title)
+    ));
+    for location in &locations {
+        xml.push_str(&format!(
+            "<ENTRY><PRIMARYKEY TYPE=\"TRACK\"{}></PRIMARYKEY></ENTRY>\n",
+            attr("KEY", location.primary_key())
+        ));
+    }
+    xml.push_str("</PLAYLIST>\n</NODE>\n</SUBNODES>\n</NODE>\n</PLAYLISTS>\n</NML>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traktor_locations_split_volume_and_dirs() {
+        let mac = TraktorLocation::new(Path::new("/Volumes/USB Stick/Music/Mix/01 - Song.mp3"), "Macintosh HD");
+        assert_eq!(mac.volume, "USB Stick");
+        assert_eq!(mac.dir, "/:Music/:Mix/:");
+        assert_eq!(mac.primary_key(), "USB Stick/:Music/:Mix/:01 - Song.mp3");
+        let boot = TraktorLocation::new(Path::new("/Users/dj/Song.mp3"), "Macintosh HD");
+        assert_eq!(boot.primary_key(), "Macintosh HD/:Users/:dj/:Song.mp3");
+        let linux = TraktorLocation::new(Path::new("/home/dj/Song.mp3"), "");
+        assert_eq!((linux.volume.as_str(), linux.primary_key().as_str()), ("", "/:home/:dj/:Song.mp3"));
+    }
+
+    #[test]
+    fn playlist_uuid_is_fixed_per_title() {
+        assert_eq!(playlist_uuid("Road Trip"), "5d4a1ff864b54c785ce1fbdf340c0485");
+        assert_ne!(playlist_uuid("Road Trip"), playlist_uuid("Road Trip 2"));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::csvparse::TrackInfo;
+use crate::device::DeviceReport;
+use crate::progress::TrackProgress;
+use crate::spotify2media::{ConversionReport, TrackFailure, TrackResult, TrackSource};
+use crate::trim::Cut;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Everything the engine reports while converting a playlist.
+///
+/// Sent over an `mpsc` channel; the GUI, the CLI and the log all consume the
+/// same stream instead of inferring state from status strings.
+#[derive(Clone, Debug)]
+pub enum ConversionEvent {
+    /// The plan is ready and conversion begins.
+    Started { total: usize, dry_run: bool },
+    /// Work on a track begins.
+    TrackStarted { index: usize, total: usize, track: TrackInfo },
+    /// Live progress of the current track.
+    Progress(TrackProgress),
+    /// A transient failure is about to be retried.
+    Retrying { index: usize, query: String, attempt: u32, delay: Duration, reason: String },
+    /// The source for a track was picked: a library file or a search query that produced a file.
+    CandidateChosen { index: usize, source: TrackSource },
+    TrackSucceeded { index: usize, result: TrackResult },
+    TrackFailed { index: usize, failure: TrackFailure },
+    TrackSkipped { index: usize, track: TrackInfo, reason: String },
+    /// Sync took the file of a track no longer in the playlist out of the folder.
+    TrackRemoved { file: PathBuf, archived: Option<PathBuf> },
+    /// Something optional went wrong for a track that is otherwise fine.
+    Warning { index: usize, message: String },
+    /// A step after the tracks were converted (playlists, device export, report) failed.
+    /// The converted files are fine and the run still finishes.
+    StepFailed { message: String },
+    /// The converted playlist was copied to the portable device.
+    DeviceExported(DeviceReport),
+    /// The run completed (possibly with failed tracks).
+    Finished(ConversionReport),
+    /// The user cancelled; `completed` tracks were processed before stopping.
+    Cancelled { completed: usize },
+    /// The run stopped on an error that is not tied to a single track.
+    Failed { error: String },
+}
+
+/// ` [cut 3.0s: leading silence 0:00-0:03, ...]`, or nothing if the audio was left whole.
+fn cut_note(cuts: &[Cut]) -> String {
+    if cuts.is_empty() {
+        return String::new();
+    }
+    let total: f64 = cuts.iter().map(Cut::length).sum();
+    format!(" [cut {total:.1}s: {}]", cuts.iter().map(Cut::to_string).collect::<Vec<_>>().join(", "))
+}
+
+impl ConversionEvent {
+    /// How the [`log_line`](Self::log_line) is logged.
+    pub fn log_level(&self) -> log::Level {
+        match self {
+            ConversionEvent::Failed { .. } => log::Level::Error,
+            ConversionEvent::Retrying { .. }
+            | ConversionEvent::TrackFailed { .. }
+            | ConversionEvent::Warning { .. }
+            | ConversionEvent::StepFailed { .. } => log::Level::Warn,
+            _ => log::Level::Info,
+        }
+    }
+
+    /// Text for the log, or `None` for high-frequency events that would flood it.
+    pub fn log_line(&self) -> Option<String> {
+        match self {
+            ConversionEvent::Started { total, dry_run } => Some(format!(
+                "Starting {} of {} tracks",
+                if *dry_run { "dry run" } else { "conversion" },
+                total
+            )),
+            ConversionEvent::TrackStarted { index, total, track } => {
+                Some(format!("Progress: {}/{} - {}", index + 1, total, track.title))
+            }
+            ConversionEvent::Progress(_) => None,
+            ConversionEvent::Retrying { query, attempt, delay, reason, .. } => Some(format!(
+                "Retrying \"{}\" in {:.1}s (attempt {}): {}",
+                query,
+                delay.as_secs_f32(),
+                attempt,
+                reason
+            )),
+            ConversionEvent::CandidateChosen { .. } => None,
+            ConversionEvent::TrackSucceeded { result: r, .. } => Some(match &r.source {
+                TrackSource::Library(src) => format!(
+                    "[library] {} - {} <- {} -> {}",
+                    r.track.artist,
+                    r.track.title,
+                    src.display(),
+                    r.output.display()
+                ),
+                TrackSource::YouTube { query, url, .. } => format!(
+                    "[yt-dlp] {} - {} <- \"{}\"{} -> {}",
+                    r.track.artist,
+                    r.track.title,
+                    query,
+                    url.as_ref().map(|u| format!(" ({u})")).unwrap_or_default(),
+                    r.output.display()
+                ),
+                TrackSource::Previous => {
+                    format!("[unchanged] {} - {} -> {}", r.track.artist, r.track.title, r.output.display())
+                }
+            } + &cut_note(&r.cuts)
+                + &r.suspect.as_ref().map(|reason| format!(" [suspect: {reason}]")).unwrap_or_default()),
+            ConversionEvent::TrackFailed { failure: f, .. } => Some(format!(
+                "[failed: {}] {} - {} after {} attempt(s): {}",
+                f.kind, f.track.artist, f.track.title, f.attempts, f.message
+            )),
+            ConversionEvent::TrackSkipped { track, reason, .. } => {
+                Some(format!("[skip] {} - {}: {}", track.artist, track.title, reason))
+            }
+            ConversionEvent::TrackRemoved { file, archived } => Some(match archived {
+                Some(to) => format!("[removed] {} -> {}", file.display(), to.display()),
+                None => format!("[removed] {} (deleted)", file.display()),
+            }),
+            ConversionEvent::Warning { index, message } => Some(format!("[warning] track {}: {}", index + 1, message)),
+            ConversionEvent::StepFailed { message } => Some(format!("[warning] {message}")),
+            ConversionEvent::DeviceExported(device) => Some(device.summary()),
+            ConversionEvent::Finished(report) => Some(match &report.plan_file {
+                Some(plan) => format!("Dry run finished. {} Plan written to {}", report.summary(), plan.display()),
+                None => format!("Playlist conversion finished successfully. {}", report.summary()),
+            }),
+            ConversionEvent::Cancelled { completed } => Some(format!("Conversion cancelled after {completed} track(s).")),
+            ConversionEvent::Failed { error } => Some(format!("Error during playlist conversion: {error}")),
+        }
+    }
+}
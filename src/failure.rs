@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Why a yt-dlp run failed, derived from its stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// HTTP 429 / "Too Many Requests".
+    RateLimited,
+    /// Removed, private, or blocked in this country.
+    Unavailable,
+    /// Requires signing in to confirm age.
+    AgeRestricted,
+    /// DNS, connection or TLS problems.
+    Network,
+    /// yt-dlp could not parse the site; usually needs a yt-dlp update.
+    ExtractorBroken,
+    /// Killed by the overall or no-progress timeout.
+    TimedOut,
+    /// Download worked but ffmpeg post-processing failed.
+    PostprocessFailed,
+    /// yt-dlp exited successfully but left no audio file.
+    NoOutput,
+    /// The output is unreadable, corrupt, or far shorter than the track.
+    VerificationFailed,
+    /// The file was there but could not be imported, tagged or put in place.
+    OutputFailed,
+    Other,
+}
+
+impl FailureKind {
+    /// Failures worth retrying the same query after a pause.
+    pub fn is_transient(self) -> bool {
+        matches!(self, FailureKind::RateLimited | FailureKind::Network | FailureKind::TimedOut)
+    }
+
+    /// Failures caused by the chosen video, so another query variant may succeed.
+    pub fn try_next_query(self) -> bool {
+        matches!(
+            self,
+            FailureKind::Unavailable
+                | FailureKind::AgeRestricted
+                | FailureKind::NoOutput
+                | FailureKind::VerificationFailed
+                | FailureKind::Other
+        )
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FailureKind::RateLimited => "rate-limited",
+            FailureKind::Unavailable => "unavailable",
+            FailureKind::AgeRestricted => "age-restricted",
+            FailureKind::Network => "network",
+            FailureKind::TimedOut => "timed out",
+            FailureKind::ExtractorBroken => "extractor broken",
+            FailureKind::PostprocessFailed => "ffmpeg postprocess failed",
+            FailureKind::NoOutput => "no output",
+            FailureKind::VerificationFailed => "verification failed",
+            FailureKind::OutputFailed => "output failed",
+            FailureKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Sort yt-dlp stderr into a [`FailureKind`].
+pub fn classify(stderr: &str) -> FailureKind {
+    let s = stderr.to_lowercase();
+    let any = |needles: &[&str]| needles.iter().any(|n| s.contains(n));
+
+    if any(&["http error 429", "too many requests", "rate-limit", "rate limit"]) {
+        FailureKind::RateLimited
+    } else if any(&["confirm your age", "age-restricted", "age restricted", "inappropriate for some users"]) {
+        FailureKind::AgeRestricted
+    } else if any(&[
+        "video unavailable",
+        "available in your country",
+        "geo restrict",
+        "geo-restrict",
+        "private video",
+        "has been removed",
+        "this video is not available",
+    ]) {
+        FailureKind::Unavailable
+    } else if any(&["postprocessing:", "ffmpeg not found", "ffprobe and ffmpeg not found", "conversion failed"]) {
+        FailureKind::PostprocessFailed
+    } else if any(&["unable to extract", "please report this issue", "nsig extraction failed", "unsupported url"]) {
+        FailureKind::ExtractorBroken
+    } else if any(&[
+        "unable to download webpage",
+        "connection reset",
+        "connection refused",
+        "timed out",
+        "name or service not known",
+        "temporary failure in name resolution",
+        "network is unreachable",
+        "ssl:",
+        "http error 5",
+    ]) {
+        FailureKind::Network
+    } else {
+        FailureKind::Other
+    }
+}
+
+/// Error returned by `run_yt_dlp` when yt-dlp ran but did not deliver a file.
+#[derive(Debug)]
+pub struct YtDlpError {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl fmt::Display for YtDlpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.kind)
+    }
+}
+
+impl std::error::Error for YtDlpError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_common_yt_dlp_errors() {
+        let cases = [
+            ("ERROR: Unable to download webpage: HTTP Error 429: Too Many Requests", FailureKind::RateLimited),
+            ("ERROR: [youtube] abc: Video unavailable", FailureKind::Unavailable),
+            ("ERROR: [youtube] abc: The uploader has not made this video available in your country", FailureKind::Unavailable),
+            ("ERROR: [youtube] abc: Sign in to confirm your age", FailureKind::AgeRestricted),
+            ("ERROR: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>", FailureKind::Network),
+            ("ERROR: [youtube] abc: Unable to extract uploader id; please report this issue", FailureKind::ExtractorBroken),
+            ("ERROR: Postprocessing: ffprobe and ffmpeg not found", FailureKind::PostprocessFailed),
+            ("ERROR: something odd", FailureKind::Other),
+        ];
+        for (stderr, kind) in cases {
+            assert_eq!(classify(stderr), kind, "{stderr}");
+        }
+    }
+}
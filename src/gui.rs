@@ -1,335 +1,1046 @@
-use crate::config::AppConfig;
-use crate::csvparse::{parse_csv, TrackInfo};
-use crate::playlist::convert_playlist;
-use eframe::{egui, App};
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use rfd::FileDialog;
-use egui::{RichText, Color32};
-use std::time::{Instant, Duration};
-
-pub struct Spotify2MediaApp {
-    csv_path: Option<PathBuf>,
-    output_dir: Option<PathBuf>,
-    config: AppConfig,
-    status: Arc<Mutex<String>>,
-    is_running: bool,
-    tracks: Vec<TrackInfo>,
-    progress: Arc<Mutex<(usize, usize)>>,
-    last_error: Option<String>,
-    show_about: bool,
-    theme_is_dark: bool,
-    confirm_dialog_open: bool,
-    yt_dlp_path: PathBuf,
-    ffmpeg_path: PathBuf,
-    log: Arc<Mutex<Vec<String>>>,
-    start_time: Option<Instant>,
-    cancel_requested: Arc<Mutex<bool>>,
-}
-
-impl Default for Spotify2MediaApp {
-    fn default() -> Self {
-        let config_path = PathBuf::from("config.json");
-        let config = AppConfig::load(&config_path);
-        Self {
-            csv_path: None,
-            output_dir: None,
-            config,
-            status: Arc::new(Mutex::new("Waiting...".into())),
-            is_running: false,
-            tracks: vec![],
-            progress: Arc::new(Mutex::new((0, 1))),
-            last_error: None,
-            show_about: false,
-            theme_is_dark: true,
-            confirm_dialog_open: false,
-            yt_dlp_path: PathBuf::from("yt-dlp"),
-            ffmpeg_path: PathBuf::from("ffmpeg"),
-            log: Arc::new(Mutex::new(Vec::new())),
-            start_time: None,
-            cancel_requested: Arc::new(Mutex::new(false)),
-        }
-    }
-}
-
-impl App for Spotify2MediaApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                ui.heading("Spotify2Media (Rust Port)");
-                if ui.button("About").clicked() { self.show_about = true; }
-                if ui.button("Toggle Theme").clicked() {
-                    self.theme_is_dark = !self.theme_is_dark;
-                    ctx.set_visuals(if self.theme_is_dark { egui::Visuals::dark() } else { egui::Visuals::light() });
-                }
-                if ui.button("Reset").on_hover_text("Reset all fields").clicked() {
-                    *self = Self::default();
-                }
-            });
-        });
-
-        egui::CentralPanel::default().show(ctx, |ui| {
-            // Error/status area
-            if let Some(err) = &self.last_error {
-                ui.horizontal(|ui| {
-                    ui.colored_label(Color32::RED, format!("❌ Error: {err}"));
-                    if ui.button("Copy").on_hover_text("Copy error to clipboard").clicked() {
-                        ui.output_mut(|o| o.copied_text = err.clone());
-                    }
-                });
-            } else {
-                ui.colored_label(Color32::LIGHT_GREEN, format!("Status: {}", self.status.lock().unwrap()));
-            }
-
-            ui.separator();
-            ui.heading("Step 1: Select Files");
-
-            ui.horizontal(|ui| {
-                if ui.add_enabled(!self.is_running, egui::Button::new("Select CSV")).clicked() {
-                    if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).pick_file() {
-                        if path.extension().map(|e| e != "csv").unwrap_or(true) {
-                            self.last_error = Some("Please select a CSV file.".into());
-                        } else {
-                            self.csv_path = Some(path.clone());
-                            *self.status.lock().unwrap() = "CSV loaded.".into();
-                            match parse_csv(&path) {
-                                Ok(tracks) => self.tracks = tracks,
-                                Err(e) => self.last_error = Some(format!("CSV error: {e}")),
-                            }
-                        }
-                    }
-                }
-                if let Some(path) = &self.csv_path {
-                    if ui.link(path.display().to_string()).clicked() {
-                        // Optionally open in explorer
-                    }
-                }
-            });
-
-            ui.horizontal(|ui| {
-                if ui.add_enabled(!self.is_running, egui::Button::new("Select Output Folder")).clicked() {
-                    if let Some(dir) = FileDialog::new().pick_folder() {
-                        self.output_dir = Some(dir.clone());
-                        *self.status.lock().unwrap() = "Output folder selected.".into();
-                    }
-                }
-                if let Some(dir) = &self.output_dir {
-                    if ui.link(dir.display().to_string()).clicked() {
-                        // Optionally open in explorer
-                    }
-                }
-            });
-
-            // yt-dlp/ffmpeg path settings
-            ui.separator();
-            ui.collapsing("Advanced: yt-dlp/ffmpeg paths", |ui| {
-                ui.horizontal(|ui| {
-                    ui.label("yt-dlp path:");
-                    let mut path_str = self.yt_dlp_path.display().to_string();
-                    if ui.text_edit_singleline(&mut path_str).changed() {
-                        self.yt_dlp_path = PathBuf::from(path_str.clone());
-                    }
-                });
-                ui.horizontal(|ui| {
-                    ui.label("ffmpeg path:");
-                    let mut path_str = self.ffmpeg_path.display().to_string();
-                    if ui.text_edit_singleline(&mut path_str).changed() {
-                        self.ffmpeg_path = PathBuf::from(path_str.clone());
-                    }
-                });
-                if ui.button("Test yt-dlp/ffmpeg").clicked() {
-                    let yt_dlp = self.yt_dlp_path.clone();
-                    let ffmpeg = self.ffmpeg_path.clone();
-                    let log = Arc::clone(&self.log);
-                    thread::spawn(move || {
-                        let yt_dlp_ok = std::process::Command::new(&yt_dlp).arg("--version").output().is_ok();
-                        let ffmpeg_ok = std::process::Command::new(&ffmpeg).arg("-version").output().is_ok();
-                        let mut log = log.lock().unwrap();
-                        log.push(format!("yt-dlp: {}", if yt_dlp_ok { "OK" } else { "NOT FOUND" }));
-                        log.push(format!("ffmpeg: {}", if ffmpeg_ok { "OK" } else { "NOT FOUND" }));
-                    });
-                }
-            });
-
-            if !self.tracks.is_empty() {
-                ui.separator();
-                ui.collapsing(format!("CSV Preview (showing first 5 of {} tracks)", self.tracks.len()), |ui| {
-                    for t in self.tracks.iter().take(5) {
-                        ui.label(format!("{} — {} [{}]", t.title, t.artist, t.album));
-                    }
-                });
-                ui.label(format!("Total tracks loaded: {}", self.tracks.len()));
-            }
-
-            ui.separator();
-            ui.heading("Step 2: Settings");
-
-            ui.horizontal(|ui| {
-                ui.checkbox(&mut self.config.transcode_mp3, "Transcode to MP3")
-                    .on_hover_text("Convert downloaded files to MP3 for maximum compatibility.");
-                ui.checkbox(&mut self.config.generate_m3u, "Generate M3U")
-                    .on_hover_text("Create an M3U playlist file with the downloaded tracks.");
-                ui.checkbox(&mut self.config.exclude_instrumentals, "Exclude instrumentals")
-                    .on_hover_text("Try to skip instrumental versions in your playlist.");
-            });
-
-            ui.collapsing("Show current settings", |ui| {
-                ui.monospace(format!("{:#?}", self.config));
-            });
-
-            ui.separator();
-            ui.heading("Step 3: Convert");
-
-            let can_convert = !self.is_running && self.csv_path.is_some() && self.output_dir.is_some() && !self.tracks.is_empty();
-
-            let convert_btn = ui.add_enabled(
-                can_convert,
-                egui::Button::new("Convert Playlist")
-                    .fill(if can_convert { egui::Color32::DARK_GREEN } else { egui::Color32::GRAY })
-            ).on_hover_text("Start downloading and tagging tracks.");
-
-            if convert_btn.clicked() {
-                self.confirm_dialog_open = true;
-            }
-
-            if !can_convert {
-                ui.label(egui::RichText::new("Please select a CSV and output folder to enable conversion.").color(egui::Color32::YELLOW));
-            }
-
-            // Progress and cancel
-            let (curr, total) = *self.progress.lock().unwrap();
-            if self.is_running {
-                let elapsed = self.start_time.map(|t| t.elapsed()).unwrap_or(Duration::ZERO);
-                let percent = curr as f32 / total.max(1) as f32;
-                let eta = if curr > 0 {
-                    let avg = elapsed / curr as u32;
-                    avg * (total as u32 - curr as u32)
-                } else {
-                    Duration::ZERO
-                };
-                ui.add(egui::ProgressBar::new(percent)
-                    .show_percentage()
-                    .desired_width(400.0)
-                    .text(format!("{} / {} (ETA: {:?})", curr, total, eta)));
-                if ui.button("Cancel").clicked() {
-                    *self.cancel_requested.lock().unwrap() = true;
-                }
-                ui.spinner();
-            }
-
-            // Log window
-            ui.separator();
-            ui.collapsing("Log", |ui| {
-                let log = self.log.lock().unwrap();
-                for line in log.iter().rev().take(30) {
-                    ui.label(line);
-                }
-            });
-
-            // Confirm dialog logic
-            if self.confirm_dialog_open {
-                let mut dialog_open = true;
-                let mut should_close_dialog = false;
-                egui::Window::new("Confirm Conversion")
-                    .collapsible(false)
-                    .resizable(false)
-                    .open(&mut dialog_open)
-                    .show(ctx, |ui| {
-                        ui.label("Are you sure you want to start playlist conversion?");
-                        if ui.button("Yes, start").clicked() {
-                            should_close_dialog = true;
-                            if let (Some(_csv), Some(out_dir)) = (&self.csv_path, &self.output_dir) {
-                                let config = self.config.clone();
-                                let out_dir = out_dir.clone();
-                                let tracks = self.tracks.clone();
-                                let status_main = Arc::clone(&self.status);
-                                let progress_main = Arc::clone(&self.progress);
-                                let yt_dlp_path = self.yt_dlp_path.clone();
-                                let ffmpeg_path = self.ffmpeg_path.clone();
-                                let log = Arc::clone(&self.log);
-                                let cancel_requested = Arc::clone(&self.cancel_requested);
-
-                                self.is_running = true;
-                                self.last_error = None;
-                                *status_main.lock().unwrap() = "Starting conversion...".into();
-                                *progress_main.lock().unwrap() = (0, tracks.len().max(1));
-                                self.start_time = Some(Instant::now());
-
-                                thread::spawn(move || {
-                                    let status_cb = Arc::clone(&status_main);
-                                    let progress_cb = Arc::clone(&progress_main);
-                                    let log_cb = Arc::clone(&log);
-                                    let cancel_cb = Arc::clone(&cancel_requested);
-                                    let cb = move |i: usize, total: usize, track: &str| {
-                                        *progress_cb.lock().unwrap() = (i + 1, total.max(1));
-                                        *status_cb.lock().unwrap() = format!(
-                                            "Downloading: {} ({}/{})", track, i + 1, total
-                                        );
-                                    };
-                                    let result = convert_playlist(
-                                        &tracks, &config, &yt_dlp_path, &ffmpeg_path, &out_dir, cb
-                                    );
-                                    match result {
-                                        Ok(_) => *status_main.lock().unwrap() = format!(
-                                            "Conversion finished! Downloaded {}/{} tracks.", tracks.len(), tracks.len()
-                                        ),
-                                        Err(e) => {
-                                            *status_main.lock().unwrap() = format!("Error: {e}");
-                                            log_cb.lock().unwrap().push(format!("Error: {e}"));
-                                        }
-                                    }
-                                });
-                            } else {
-                                self.last_error = Some("Please select a CSV and output folder.".into());
-                            }
-                        }
-                        if ui.button("Cancel").clicked() {
-                            should_close_dialog = true;
-                        }
-                    });
-                if should_close_dialog || !dialog_open {
-                    self.confirm_dialog_open = false;
-                }
-            }
-
-            ui.separator();
-
-            ui.horizontal(|ui| {
-                if ui.button("Save Settings").on_hover_text("Save your configuration for next time.").clicked() {
-                    if let Err(e) = self.config.save(Path::new("config.json")) {
-                        self.last_error = Some(format!("Failed to save config: {e}"));
-                    } else {
-                        *self.status.lock().unwrap() = "Settings saved.".into();
-                    }
-                }
-            });
-
-            if self.is_running {
-                let s = self.status.lock().unwrap();
-                if s.starts_with("Conversion finished!") || s.starts_with("Error:") {
-                    self.is_running = false;
-                }
-            }
-
-            ctx.request_repaint_after(std::time::Duration::from_millis(150));
-        });
-
-        // About dialog
-        if self.show_about {
-            let mut show_about_open = true;
-            egui::Window::new("About Spotify2Media Rust Port")
-                .open(&mut show_about_open)
-                .collapsible(false)
-                .resizable(false)
-                .show(ctx, |ui| {
-                    ui.label("Spotify2Media Rust Port");
-                    ui.label("By fentbuscoding.");
-                    ui.label("Powered by eframe/egui.");
-                    ui.label("Downloads and tags music from Spotify playlists using yt-dlp & ffmpeg.");
-                    ui.label("Project: github.com/fentbuscoding/spotify2media-rust");
-                });
-            self.show_about = show_about_open;
-        }
-    }
+use crate::config::{remember, settings_path, AppConfig, BudgetPolicy, GuiState, LibraryMode, LoudnessMode, MediaServer, NamingProfile, OutputFormat, PlaylistFormat, PlaylistPaths, Settings, SyncRemoval};
+use crate::csvparse::{parse_csv, TrackInfo};
+use crate::naming::{NameTemplate, DEFAULT_TEMPLATE};
+use crate::playlist::convert_playlist;
+use crate::events::ConversionEvent;
+use crate::logging;
+use crate::progress::TrackProgress;
+use crate::report::REPORT_HTML;
+use crate::spotify2media::TrackSource;
+use eframe::{egui, App};
+use log::LevelFilter;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use rfd::FileDialog;
+use egui::Color32;
+use std::time::{Instant, Duration};
+
+pub struct Spotify2MediaApp {
+    csv_path: Option<PathBuf>,
+    /// Settings of the selected profile, including unsaved edits.
+    config: AppConfig,
+    /// All profiles as last loaded or saved; `config` goes back in when saving.
+    /// Tool paths, output folder, theme and recent files live in `settings.gui` and are
+    /// written as soon as they change.
+    settings: Settings,
+    /// Unsaved edits of the other profiles, kept while switching between them.
+    edits: BTreeMap<String, AppConfig>,
+    /// The settings file could not be read; it is left alone until the user saves.
+    settings_unreadable: bool,
+    profile: String,
+    /// Name typed for "Save as".
+    new_profile: String,
+    /// Where the settings are loaded from and saved to.
+    config_path: PathBuf,
+    status: String,
+    is_running: bool,
+    tracks: Vec<TrackInfo>,
+    progress: (usize, usize),
+    track_progress: Option<TrackProgress>,
+    last_error: Option<String>,
+    show_about: bool,
+    confirm_dialog_open: bool,
+    confirm_delete_open: bool,
+    /// Log panel filters: least severe level shown and text to look for.
+    log_filter: LevelFilter,
+    log_search: String,
+    start_time: Option<Instant>,
+    cancel_requested: Arc<AtomicBool>,
+    /// Events from the running conversion, if any.
+    events: Option<Receiver<ConversionEvent>>,
+}
+
+impl Default for Spotify2MediaApp {
+    fn default() -> Self {
+        let config_path = settings_path();
+        let (settings, last_error) = match Settings::load(&config_path) {
+            Ok(settings) => (settings, None),
+            // Keep the broken file until the user saves, so it can still be fixed by hand
+            Err(e) => (Settings::default(), Some(format!("{e:#}; using default settings"))),
+        };
+        let config = settings.active().clone();
+        if let Some(e) = &last_error {
+            log::error!("{e}");
+        }
+        logging::set_level(config.log_level);
+        Self {
+            csv_path: None,
+            config,
+            profile: settings.active_profile.clone(),
+            settings_unreadable: last_error.is_some(),
+            settings,
+            edits: BTreeMap::new(),
+            new_profile: String::new(),
+            config_path,
+            status: "Waiting...".into(),
+            is_running: false,
+            tracks: vec![],
+            progress: (0, 1),
+            track_progress: None,
+            last_error,
+            show_about: false,
+            confirm_dialog_open: false,
+            confirm_delete_open: false,
+            log_filter: LevelFilter::Info,
+            log_search: String::new(),
+            start_time: None,
+            cancel_requested: Arc::new(AtomicBool::new(false)),
+            events: None,
+        }
+    }
+}
+
+fn visuals(dark: bool) -> egui::Visuals {
+    if dark {
+        egui::Visuals::dark()
+    } else {
+        egui::Visuals::light()
+    }
+}
+
+/// Open `path` with the program the desktop associates with it.
+fn open_path(path: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut c = std::process::Command::new("cmd");
+        c.args(["/C", "start", ""]);
+        c
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command.arg(path).spawn().map(|_| ())
+}
+
+impl Spotify2MediaApp {
+    /// The app with the remembered theme applied.
+    pub fn new(cc: &eframe::CreationContext) -> Self {
+        let app = Self::default();
+        cc.egui_ctx.set_visuals(visuals(app.settings.gui.dark_theme));
+        app
+    }
+
+    /// Write `settings.gui` to the settings file, leaving the profiles in it as they are.
+    fn save_gui_state(&mut self) {
+        if self.settings_unreadable {
+            return;
+        }
+        let saved = Settings::load(&self.config_path).and_then(|mut on_disk| {
+            on_disk.gui = self.settings.gui.clone();
+            Ok(on_disk.save(&self.config_path)?)
+        });
+        if let Err(e) = saved {
+            log::warn!("Could not remember paths in {}: {e:#}", self.config_path.display());
+        }
+    }
+
+    fn load_csv(&mut self, path: PathBuf) {
+        self.csv_path = Some(path.clone());
+        self.status = "CSV loaded.".into();
+        match parse_csv(&path) {
+            Ok(tracks) => {
+                self.tracks = tracks;
+                remember(&mut self.settings.gui.recent_csvs, &path);
+                self.save_gui_state();
+            }
+            Err(e) => self.last_error = Some(format!("CSV error: {e}")),
+        }
+    }
+
+    fn select_output_dir(&mut self, dir: PathBuf) {
+        remember(&mut self.settings.gui.recent_output_dirs, &dir);
+        self.settings.gui.output_dir = Some(dir);
+        self.status = "Output folder selected.".into();
+        self.save_gui_state();
+    }
+
+    /// Start over with a new playlist; settings, paths and the output folder stay.
+    fn reset_session(&mut self) {
+        self.csv_path = None;
+        self.tracks.clear();
+        self.status = "Waiting...".into();
+        self.progress = (0, 1);
+        self.track_progress = None;
+        self.last_error = None;
+        self.start_time = None;
+    }
+
+    /// Put the selected profile, tool paths, output folder and theme back to their
+    /// defaults. The recent-files lists stay.
+    fn reset_settings(&mut self, ctx: &egui::Context) {
+        self.config = AppConfig::default();
+        let gui = &mut self.settings.gui;
+        *gui = GuiState {
+            recent_csvs: std::mem::take(&mut gui.recent_csvs),
+            recent_output_dirs: std::mem::take(&mut gui.recent_output_dirs),
+            ..GuiState::default()
+        };
+        ctx.set_visuals(visuals(gui.dark_theme));
+        logging::set_level(self.config.log_level);
+        self.save_gui_state();
+        self.status = format!("Profile \"{}\" reset to defaults; Save Settings keeps them.", self.profile);
+    }
+
+    /// Keep the edits to the current profile in memory and show profile `name`.
+    fn switch_profile(&mut self, name: String) {
+        self.edits.insert(self.profile.clone(), self.config.clone());
+        self.config = self.edits.remove(&name).unwrap_or_else(|| self.settings.profiles[&name].clone());
+        self.profile = name;
+        logging::set_level(self.config.log_level);
+    }
+
+    /// Remove the selected profile from the settings file and show the first one left.
+    fn delete_profile(&mut self) {
+        let deleted = self.profile.clone();
+        self.settings.profiles.remove(&deleted);
+        self.edits.remove(&deleted);
+        let name = self.settings.profiles.keys().next().unwrap().clone();
+        self.config = self.edits.remove(&name).unwrap_or_else(|| self.settings.profiles[&name].clone());
+        self.profile = name;
+        logging::set_level(self.config.log_level);
+        // Only the deletion is written; edits to the shown profile still wait for Save Settings
+        self.settings.active_profile = self.profile.clone();
+        match self.settings.save(&self.config_path) {
+            Ok(()) => self.status = format!("Profile \"{deleted}\" deleted."),
+            Err(e) => self.last_error = Some(format!("Failed to save config to {}: {e}", self.config_path.display())),
+        }
+    }
+
+    /// Store the edits under the selected profile, make it the one used next time and write the file.
+    fn save_settings(&mut self) {
+        self.settings.profiles.insert(self.profile.clone(), self.config.clone());
+        self.settings.active_profile = self.profile.clone();
+        if let Err(e) = self.settings.save(&self.config_path) {
+            self.last_error = Some(format!("Failed to save config to {}: {e}", self.config_path.display()));
+        } else {
+            self.settings_unreadable = false;
+            self.status = format!("Profile \"{}\" saved to {}.", self.profile, self.config_path.display());
+        }
+    }
+
+    /// Apply all pending events from the conversion thread.
+    fn poll_events(&mut self) {
+        let Some(rx) = &self.events else { return };
+        let mut done = false;
+        while !done {
+            let event = match rx.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The worker ended without a final event, e.g. because it panicked
+                    let error = "The conversion stopped unexpectedly; see the log for details.".to_string();
+                    log::error!("{error}");
+                    self.status = format!("Error: {error}");
+                    self.last_error = Some(error);
+                    done = true;
+                    break;
+                }
+            };
+            if let Some(line) = event.log_line() {
+                log::log!(event.log_level(), "{line}");
+            }
+            match event {
+                ConversionEvent::Started { total, .. } => self.progress = (0, total.max(1)),
+                ConversionEvent::TrackStarted { index, total, track } => {
+                    self.progress = (index, total.max(1));
+                    self.status = format!("Processing: {} ({}/{})", track.title, index + 1, total);
+                }
+                ConversionEvent::Progress(p) => {
+                    self.status = format!("{}: {} ({}/{})", p.phase.label(), p.title, p.index + 1, p.total);
+                    self.track_progress = Some(p);
+                }
+                ConversionEvent::TrackSucceeded { index, .. }
+                | ConversionEvent::TrackFailed { index, .. }
+                | ConversionEvent::TrackSkipped { index, .. } => self.progress.0 = index + 1,
+                ConversionEvent::Retrying { index, query, delay, .. } => {
+                    self.status = format!("Retrying \"{}\" in {:.1}s ({}/{})", query, delay.as_secs_f32(), index + 1, self.progress.1);
+                }
+                ConversionEvent::Warning { .. } | ConversionEvent::TrackRemoved { .. } => {}
+                ConversionEvent::StepFailed { message } => self.last_error = Some(message),
+                ConversionEvent::DeviceExported(device) => self.status = device.summary(),
+                ConversionEvent::CandidateChosen { index, source } => {
+                    let found = match source {
+                        TrackSource::Library(path) => format!("library file {}", path.display()),
+                        TrackSource::YouTube { query, .. } => format!("\"{query}\""),
+                        TrackSource::Previous => "the file from the last run".into(),
+                    };
+                    self.status = format!("Using {} ({}/{})", found, index + 1, self.progress.1);
+                }
+                ConversionEvent::Finished(report) => {
+                    self.status = format!("Conversion finished! {}", report.summary());
+                    done = true;
+                }
+                ConversionEvent::Cancelled { completed } => {
+                    self.status = format!("Cancelled after {completed} track(s).");
+                    done = true;
+                }
+                ConversionEvent::Failed { error } => {
+                    self.status = format!("Error: {error}");
+                    self.last_error = Some(error);
+                    done = true;
+                }
+            }
+        }
+        if done {
+            self.is_running = false;
+            self.track_progress = None;
+            self.events = None;
+        }
+    }
+}
+
+/// "Recent" dropdown over `recent`; returns the entry picked this frame.
+fn recent_menu(ui: &mut egui::Ui, id: &str, recent: &[PathBuf], enabled: bool) -> Option<PathBuf> {
+    let mut picked = None;
+    ui.add_enabled_ui(enabled && !recent.is_empty(), |ui| {
+        egui::ComboBox::from_id_source(id).selected_text("Recent").show_ui(ui, |ui| {
+            for path in recent {
+                if ui.selectable_label(false, path.display().to_string()).clicked() {
+                    picked = Some(path.clone());
+                }
+            }
+        });
+    });
+    picked
+}
+
+impl App for Spotify2MediaApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_events();
+        // Written on exit, so the window opens at the same size next time
+        if let Some(rect) = ctx.input(|i| i.viewport().inner_rect) {
+            self.settings.gui.window_size = Some([rect.width(), rect.height()]);
+        }
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Spotify2Media (Rust Port)");
+                if ui.button("About").clicked() { self.show_about = true; }
+                if ui.button("Toggle Theme").clicked() {
+                    self.settings.gui.dark_theme = !self.settings.gui.dark_theme;
+                    ctx.set_visuals(visuals(self.settings.gui.dark_theme));
+                    self.save_gui_state();
+                }
+                if ui.add_enabled(!self.is_running, egui::Button::new("Reset session")).on_hover_text("Clear the playlist, progress and errors").clicked() {
+                    self.reset_session();
+                }
+                if ui
+                    .add_enabled(!self.is_running, egui::Button::new("Reset settings"))
+                    .on_hover_text("Restore the default settings, tool paths, output folder and theme")
+                    .clicked()
+                {
+                    self.reset_settings(ctx);
+                }
+            });
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // Error/status area
+            if let Some(err) = &self.last_error {
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::RED, format!("❌ Error: {err}"));
+                    if ui.button("Copy").on_hover_text("Copy error to clipboard").clicked() {
+                        ui.output_mut(|o| o.copied_text = err.clone());
+                    }
+                });
+            } else {
+                ui.colored_label(Color32::LIGHT_GREEN, format!("Status: {}", self.status));
+            }
+
+            ui.separator();
+            ui.heading("Step 1: Select Files");
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!self.is_running, egui::Button::new("Select CSV")).clicked() {
+                    if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).pick_file() {
+                        if path.extension().map(|e| e != "csv").unwrap_or(true) {
+                            self.last_error = Some("Please select a CSV file.".into());
+                        } else {
+                            self.load_csv(path);
+                        }
+                    }
+                }
+                if let Some(path) = recent_menu(ui, "recent_csvs", &self.settings.gui.recent_csvs, !self.is_running) {
+                    self.load_csv(path);
+                }
+                if let Some(path) = &self.csv_path {
+                    if ui.link(path.display().to_string()).clicked() {
+                        // Optionally open in explorer
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!self.is_running, egui::Button::new("Select Output Folder")).clicked() {
+                    if let Some(dir) = FileDialog::new().pick_folder() {
+                        self.select_output_dir(dir);
+                    }
+                }
+                if let Some(dir) = recent_menu(ui, "recent_output_dirs", &self.settings.gui.recent_output_dirs, !self.is_running) {
+                    self.select_output_dir(dir);
+                }
+                if let Some(dir) = &self.settings.gui.output_dir {
+                    if ui.link(dir.display().to_string()).clicked() {
+                        // Optionally open in explorer
+                    }
+                }
+            });
+
+            // yt-dlp/ffmpeg path settings
+            ui.separator();
+            ui.collapsing("Advanced: yt-dlp/ffmpeg paths", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("yt-dlp path:");
+                    let mut path_str = self.settings.gui.yt_dlp_path.display().to_string();
+                    let response = ui.text_edit_singleline(&mut path_str);
+                    if response.changed() {
+                        self.settings.gui.yt_dlp_path = PathBuf::from(path_str.clone());
+                    }
+                    if response.lost_focus() {
+                        self.save_gui_state();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("ffmpeg path:");
+                    let mut path_str = self.settings.gui.ffmpeg_path.display().to_string();
+                    let response = ui.text_edit_singleline(&mut path_str);
+                    if response.changed() {
+                        self.settings.gui.ffmpeg_path = PathBuf::from(path_str.clone());
+                    }
+                    if response.lost_focus() {
+                        self.save_gui_state();
+                    }
+                });
+                if ui.button("Test yt-dlp/ffmpeg").clicked() {
+                    let yt_dlp = self.settings.gui.yt_dlp_path.clone();
+                    let ffmpeg = self.settings.gui.ffmpeg_path.clone();
+                    thread::spawn(move || {
+                        for (name, path, flag) in [("yt-dlp", yt_dlp, "--version"), ("ffmpeg", ffmpeg, "-version")] {
+                            match std::process::Command::new(&path).arg(flag).output() {
+                                Ok(_) => log::info!("{name}: OK ({})", path.display()),
+                                Err(e) => log::warn!("{name}: NOT FOUND ({}: {e})", path.display()),
+                            }
+                        }
+                    });
+                }
+            });
+
+            if !self.tracks.is_empty() {
+                ui.separator();
+                ui.collapsing(format!("CSV Preview (showing first 5 of {} tracks)", self.tracks.len()), |ui| {
+                    for t in self.tracks.iter().take(5) {
+                        ui.label(format!("{} — {} [{}]", t.title, t.artist, t.album));
+                    }
+                });
+                ui.label(format!("Total tracks loaded: {}", self.tracks.len()));
+            }
+
+            ui.separator();
+            ui.heading("Step 2: Settings");
+
+            ui.horizontal(|ui| {
+                let mut selected = self.profile.clone();
+                egui::ComboBox::from_label("Profile")
+                    .selected_text(&selected)
+                    .show_ui(ui, |ui| {
+                        for name in self.settings.profiles.keys() {
+                            ui.selectable_value(&mut selected, name.clone(), name);
+                        }
+                    })
+                    .response
+                    .on_hover_text("Named sets of settings, e.g. one for DJ sets and one for the car.");
+                if selected != self.profile {
+                    self.switch_profile(selected);
+                }
+                ui.add(egui::TextEdit::singleline(&mut self.new_profile).hint_text("New profile name").desired_width(140.0));
+                if ui.add_enabled(!self.new_profile.trim().is_empty(), egui::Button::new("Save as")).clicked() {
+                    self.profile = self.settings.free_name(&self.new_profile);
+                    self.new_profile.clear();
+                    self.save_settings();
+                }
+                if ui.add_enabled(self.settings.profiles.len() > 1, egui::Button::new("Delete")).clicked() {
+                    self.confirm_delete_open = true;
+                }
+                if ui.button("Import...").on_hover_text("Add profiles from a file exported by someone else.").clicked() {
+                    if let Some(path) = FileDialog::new().add_filter("Profile", &["json"]).pick_file() {
+                        match self.settings.import(&path) {
+                            Ok(names) => {
+                                self.switch_profile(names[0].clone());
+                                self.save_settings();
+                                self.status = format!("Imported profile(s): {}.", names.join(", "));
+                            }
+                            Err(e) => self.last_error = Some(format!("{e:#}")),
+                        }
+                    }
+                }
+                if ui.button("Export...").on_hover_text("Save the selected profile, with unsaved edits, as a file to share.").clicked() {
+                    let file_name = format!("{}.json", self.profile);
+                    if let Some(path) = FileDialog::new().add_filter("Profile", &["json"]).set_file_name(file_name).save_file() {
+                        // Export from a copy, so the unsaved edits don't end up in the settings file
+                        let mut shared = self.settings.clone();
+                        shared.profiles.insert(self.profile.clone(), self.config.clone());
+                        match shared.export(&self.profile, &path) {
+                            Ok(()) => self.status = format!("Exported profile \"{}\" to {}.", self.profile, path.display()),
+                            Err(e) => self.last_error = Some(format!("{e:#}")),
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Output format")
+                    .selected_text(self.config.output_format.label())
+                    .show_ui(ui, |ui| {
+                        for format in OutputFormat::ALL {
+                            ui.selectable_value(&mut self.config.output_format, format, format.label());
+                        }
+                    })
+                    .response
+                    .on_hover_text("MP3 plays everywhere; Opus and AAC are smaller at the same quality.");
+                ui.add_enabled_ui(self.config.output_format.has_bitrate(), |ui| {
+                    ui.label("Bitrate (kbit/s):");
+                    ui.add(egui::DragValue::new(&mut self.config.bitrate_kbps).speed(8).clamp_range(0..=512))
+                        .on_hover_text("0 picks the encoder's best variable bitrate.");
+                });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("File names:");
+                ui.add(egui::TextEdit::singleline(&mut self.config.file_name_template).desired_width(320.0))
+                    .on_hover_text(
+                        "Path of each file inside the output folder; '/' creates folders.\n\
+                         Fields: {title} {artist} {album} {album_artist} {track} {track_total} {disc} \
+                         {disc_total} {year} {date} {genre} {isrc} {index}. Pad numbers with {track:02}.",
+                    );
+                if ui.button("Default").clicked() {
+                    self.config.file_name_template = DEFAULT_TEMPLATE.to_string();
+                }
+                egui::ComboBox::from_label("Target")
+                    .selected_text(self.config.naming_profile.label())
+                    .show_ui(ui, |ui| {
+                        for profile in NamingProfile::ALL {
+                            ui.selectable_value(&mut self.config.naming_profile, profile, profile.label());
+                        }
+                    })
+                    .response
+                    .on_hover_text("Filesystem the files must work on; names and playlists are adjusted to its rules.");
+            });
+            if let Err(e) = NameTemplate::parse(&self.config.file_name_template) {
+                ui.colored_label(Color32::RED, format!("❌ {e}"));
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Playlists:");
+                for format in PlaylistFormat::ALL {
+                    let mut selected = self.config.playlist_formats.contains(&format);
+                    if ui.checkbox(&mut selected, format.label()).changed() {
+                        if selected {
+                            self.config.playlist_formats.push(format);
+                        } else {
+                            self.config.playlist_formats.retain(|f| *f != format);
+                        }
+                    }
+                }
+                ui.separator();
+                ui.radio_value(&mut self.config.playlist_paths, PlaylistPaths::Relative, "Relative paths")
+                    .on_hover_text("Playlists keep working when the output folder is moved or copied.");
+                ui.radio_value(&mut self.config.playlist_paths, PlaylistPaths::Absolute, "Absolute paths");
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.config.exclude_instrumentals, "Exclude instrumentals")
+                    .on_hover_text("Try to skip instrumental versions in your playlist.");
+                ui.checkbox(&mut self.config.skip_duplicates, "Skip duplicates")
+                    .on_hover_text("Download each song only once, even if the CSV lists it several times.");
+                ui.checkbox(&mut self.config.dry_run, "Dry run")
+                    .on_hover_text("Only write plan.json/plan.csv describing what would happen; nothing is downloaded.");
+            });
+
+            ui.horizontal(|ui| {
+                let sync = &mut self.config.sync;
+                ui.checkbox(&mut sync.enabled, "Sync with last run")
+                    .on_hover_text("Only convert tracks added since the last run into this folder; tracks removed from the playlist are taken out. Files this app didn't create are never touched.");
+                ui.add_enabled_ui(sync.enabled, |ui| {
+                    for removal in SyncRemoval::ALL {
+                        ui.radio_value(&mut sync.removal, removal, removal.label());
+                    }
+                    ui.add_enabled_ui(sync.removal == SyncRemoval::Archive, |ui| {
+                        let mut archive = sync.archive_dir.to_string_lossy().into_owned();
+                        if ui.add(egui::TextEdit::singleline(&mut archive).desired_width(120.0)).on_hover_text("Archive folder, inside the output folder.").changed() {
+                            sync.archive_dir = archive.into();
+                        }
+                    });
+                });
+            });
+
+            ui.collapsing("Media server", |ui| {
+                let server = &mut self.config.media_server;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut server.enabled, "Write media server playlist")
+                        .on_hover_text("An extra .m3u8 whose paths point at the files as your media server sees them.");
+                    egui::ComboBox::from_label("Server")
+                        .selected_text(server.server.label())
+                        .show_ui(ui, |ui| {
+                            for kind in MediaServer::ALL {
+                                ui.selectable_value(&mut server.server, kind, kind.label());
+                            }
+                        })
+                        .response
+                        .on_hover_text("Navidrome and Jellyfin import playlists found in the library; Plex doesn't, so import the file there yourself.");
+                });
+                ui.add_enabled_ui(server.enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        match server.local_root.as_os_str().is_empty() {
+                            true => ui.label("Local folder: the output folder"),
+                            false => ui.label(format!("Local folder: {}", server.local_root.display())),
+                        };
+                        if ui.add_enabled(!self.is_running, egui::Button::new("Choose")).clicked() {
+                            if let Some(dir) = FileDialog::new().pick_folder() {
+                                server.local_root = dir;
+                            }
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Same folder on the server:");
+                        ui.text_edit_singleline(&mut server.server_root)
+                            .on_hover_text("e.g. /music for a Docker volume, or D:\\Music on a Windows server.");
+                    });
+                    ui.horizontal(|ui| {
+                        match &server.playlist_dir {
+                            Some(dir) => ui.label(format!("Playlist folder: {}", dir.display())),
+                            None => ui.label("Playlist folder: the output folder"),
+                        };
+                        if ui.add_enabled(!self.is_running, egui::Button::new("Choose Playlist Folder")).clicked() {
+                            if let Some(dir) = FileDialog::new().pick_folder() {
+                                server.playlist_dir = Some(dir);
+                            }
+                        }
+                        if server.playlist_dir.is_some() && ui.add_enabled(!self.is_running, egui::Button::new("Clear")).clicked() {
+                            server.playlist_dir = None;
+                        }
+                    });
+                });
+            });
+
+            ui.collapsing("Device", |ui| {
+                let device = &mut self.config.device;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut device.enabled, "Copy to device after the run")
+                        .on_hover_text("Copy the playlist to a portable player or USB stick. Files already on it are skipped.");
+                    match device.mount.as_os_str().is_empty() {
+                        true => ui.label("No device chosen"),
+                        false => ui.label(device.mount.display().to_string()),
+                    };
+                    if ui.add_enabled(!self.is_running, egui::Button::new("Choose Device")).clicked() {
+                        if let Some(dir) = FileDialog::new().pick_folder() {
+                            device.mount = dir;
+                        }
+                    }
+                });
+                ui.add_enabled_ui(device.enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Music folder:");
+                        let mut folder = device.folder.to_string_lossy().into_owned();
+                        if ui.add(egui::TextEdit::singleline(&mut folder).desired_width(120.0)).changed() {
+                            device.folder = folder.into();
+                        }
+                        egui::ComboBox::from_label("Names")
+                            .selected_text(device.naming_profile.label())
+                            .show_ui(ui, |ui| {
+                                for profile in NamingProfile::ALL {
+                                    ui.selectable_value(&mut device.naming_profile, profile, profile.label());
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        let format_label = |f: OutputFormat| if f == OutputFormat::Original { "Keep format" } else { f.label() };
+                        egui::ComboBox::from_label("Device format")
+                            .selected_text(format_label(device.format))
+                            .show_ui(ui, |ui| {
+                                for format in OutputFormat::ALL {
+                                    ui.selectable_value(&mut device.format, format, format_label(format));
+                                }
+                            });
+                        if device.format.has_bitrate() {
+                            ui.label("kbit/s:");
+                            ui.add(egui::DragValue::new(&mut device.bitrate_kbps).clamp_range(32..=320));
+                        }
+                        egui::ComboBox::from_label("Playlist")
+                            .selected_text(device.playlist_format.label())
+                            .show_ui(ui, |ui| {
+                                for format in PlaylistFormat::ALL {
+                                    ui.selectable_value(&mut device.playlist_format, format, format.label());
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Size budget (MB):");
+                        ui.add(egui::DragValue::new(&mut device.size_budget_mb).speed(10.0))
+                            .on_hover_text("0 = no limit. Tracks are copied in playlist order.");
+                        for policy in BudgetPolicy::ALL {
+                            ui.radio_value(&mut device.budget_policy, policy, policy.label());
+                        }
+                    });
+                });
+            });
+
+            ui.collapsing("Local library", |ui| {
+                ui.label("Tracks already in these folders are taken from disk instead of being downloaded.");
+                let mut remove = None;
+                for (i, dir) in self.config.library_dirs.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(dir.display().to_string());
+                        if ui.add_enabled(!self.is_running, egui::Button::new("Remove")).clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    self.config.library_dirs.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(!self.is_running, egui::Button::new("Add Library Folder")).clicked() {
+                        if let Some(dir) = FileDialog::new().pick_folder() {
+                            if !self.config.library_dirs.contains(&dir) {
+                                self.config.library_dirs.push(dir);
+                            }
+                        }
+                    }
+                    egui::ComboBox::from_label("Library mode")
+                        .selected_text(self.config.library_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in LibraryMode::ALL {
+                                ui.selectable_value(&mut self.config.library_mode, mode, mode.label());
+                            }
+                        })
+                        .response
+                        .on_hover_text("Copy, transcode or link matched library files into the output folder.");
+                });
+            });
+
+            ui.collapsing("Cover art", |ui| {
+                let cover = &mut self.config.cover_art;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut cover.embed, "Embed cover art")
+                        .on_hover_text("Embed the video thumbnail (or your own image) as the front cover.");
+                    ui.checkbox(&mut cover.write_folder_cover, "Write cover.jpg")
+                        .on_hover_text("Also save the cover as cover.jpg in each output folder.");
+                    ui.label("Max size (px):");
+                    ui.add(egui::DragValue::new(&mut cover.max_size).speed(10).clamp_range(100..=3000))
+                        .on_hover_text("Covers are cropped to a square and scaled down to this size.");
+                });
+                ui.horizontal(|ui| {
+                    match &cover.image_dir {
+                        Some(dir) => ui.label(format!("Image folder: {}", dir.display())),
+                        None => ui.label("Image folder: none (thumbnails only)"),
+                    };
+                    if ui.add_enabled(!self.is_running, egui::Button::new("Choose Image Folder")).clicked() {
+                        if let Some(dir) = FileDialog::new().pick_folder() {
+                            cover.image_dir = Some(dir);
+                        }
+                    }
+                    if cover.image_dir.is_some() && ui.add_enabled(!self.is_running, egui::Button::new("Clear")).clicked() {
+                        cover.image_dir = None;
+                    }
+                })
+                .response
+                .on_hover_text("Images named 'Artist - Album', 'Album', 'Artist - Title' or 'Title' are used instead of thumbnails.");
+            });
+
+            ui.collapsing("Verification", |ui| {
+                let verify = &mut self.config.verify;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut verify.enabled, "Verify downloads")
+                        .on_hover_text("Check codec, length and bitrate; unreadable or truncated files are retried with the next search.");
+                    ui.checkbox(&mut verify.decode_check, "Full decode check")
+                        .on_hover_text("Decode every file with ffmpeg to catch corrupt audio. Slower.");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Length tolerance (s):");
+                    ui.add(egui::DragValue::new(&mut verify.duration_tolerance_secs).clamp_range(0..=600))
+                        .on_hover_text("Files whose length differs more than this from the CSV's track length are marked suspect.");
+                    ui.label("Min bitrate (kbit/s):");
+                    ui.add(egui::DragValue::new(&mut verify.min_bitrate_kbps).clamp_range(0..=320))
+                        .on_hover_text("Lossy files below this bitrate are marked suspect. 0 disables the check.");
+                });
+            });
+
+            ui.collapsing("Loudness", |ui| {
+                let loudness = &mut self.config.loudness;
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Mode")
+                        .selected_text(loudness.mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in LoudnessMode::ALL {
+                                ui.selectable_value(&mut loudness.mode, mode, mode.label());
+                            }
+                        })
+                        .response
+                        .on_hover_text("ReplayGain only adds tags; normalizing re-encodes the audio to the target level.");
+                });
+                ui.add_enabled_ui(loudness.mode == LoudnessMode::Normalize, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Target (LUFS):");
+                        ui.add(egui::DragValue::new(&mut loudness.target_lufs).speed(0.5).clamp_range(-40.0..=-5.0))
+                            .on_hover_text("-14 matches most streaming services; -23 is the EBU broadcast level.");
+                        ui.label("True peak (dBTP):");
+                        ui.add(egui::DragValue::new(&mut loudness.true_peak_db).speed(0.1).clamp_range(-9.0..=0.0));
+                    });
+                });
+            });
+
+            ui.collapsing("Trimming", |ui| {
+                let trim = &mut self.config.trim;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut trim.silence, "Trim silence")
+                        .on_hover_text("Cut silence at the start and end of each track (re-encodes the audio).");
+                    ui.checkbox(&mut trim.sponsorblock, "Remove non-music sections")
+                        .on_hover_text("Use SponsorBlock to cut intros, skits and outros from music videos.");
+                });
+                ui.add_enabled_ui(trim.silence, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Silence below (dB):");
+                        ui.add(egui::DragValue::new(&mut trim.silence_threshold_db).speed(1.0).clamp_range(-90.0..=-20.0));
+                        ui.label("Min length (s):");
+                        ui.add(egui::DragValue::new(&mut trim.min_silence_secs).speed(0.1).clamp_range(0.1..=30.0));
+                    });
+                });
+            });
+
+            ui.collapsing("Retries & timeouts", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Retries per query:");
+                    ui.add(egui::DragValue::new(&mut self.config.retry.max_retries).clamp_range(0..=10))
+                        .on_hover_text("Extra attempts when YouTube rate-limits us or the network drops.");
+                    ui.label("Initial backoff (s):");
+                    let mut secs = self.config.retry.initial_backoff_ms as f64 / 1000.0;
+                    if ui.add(egui::DragValue::new(&mut secs).speed(0.5).clamp_range(0.0..=600.0)).changed() {
+                        self.config.retry.initial_backoff_ms = (secs * 1000.0) as u64;
+                    }
+                    ui.label("Multiplier:");
+                    ui.add(egui::DragValue::new(&mut self.config.retry.multiplier).speed(0.1).clamp_range(1.0..=10.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Overall timeout (s):");
+                    ui.add(egui::DragValue::new(&mut self.config.download_timeout_secs).clamp_range(0..=86_400))
+                        .on_hover_text("Kill yt-dlp/ffmpeg after this long and retry. 0 disables the limit.");
+                    ui.label("Stall timeout (s):");
+                    ui.add(egui::DragValue::new(&mut self.config.stall_timeout_secs).clamp_range(0..=3_600))
+                        .on_hover_text("Kill yt-dlp/ffmpeg if it prints nothing for this long. 0 disables the limit.");
+                });
+            });
+
+            ui.collapsing("Show current settings", |ui| {
+                ui.monospace(format!("{:#?}", self.config));
+            });
+
+            ui.separator();
+            ui.heading("Step 3: Convert");
+
+            let can_convert = !self.is_running && self.csv_path.is_some() && self.settings.gui.output_dir.is_some() && !self.tracks.is_empty();
+
+            let convert_btn = ui.add_enabled(
+                can_convert,
+                egui::Button::new("Convert Playlist")
+                    .fill(if can_convert { egui::Color32::DARK_GREEN } else { egui::Color32::GRAY })
+            ).on_hover_text("Start downloading and tagging tracks.");
+
+            if convert_btn.clicked() {
+                self.confirm_dialog_open = true;
+            }
+
+            if !can_convert {
+                ui.label(egui::RichText::new("Please select a CSV and output folder to enable conversion.").color(egui::Color32::YELLOW));
+            }
+
+            // Progress and cancel
+            let (curr, total) = self.progress;
+            if self.is_running {
+                let elapsed = self.start_time.map(|t| t.elapsed()).unwrap_or(Duration::ZERO);
+                let percent = curr as f32 / total.max(1) as f32;
+                let eta = if curr > 0 {
+                    let avg = elapsed / curr as u32;
+                    avg * (total as u32 - curr as u32)
+                } else {
+                    Duration::ZERO
+                };
+                ui.add(egui::ProgressBar::new(percent)
+                    .show_percentage()
+                    .desired_width(400.0)
+                    .text(format!("{} / {} (ETA: {:?})", curr, total, eta)));
+                if let Some(track) = &self.track_progress {
+                    ui.add(egui::ProgressBar::new(track.percent.unwrap_or(0.0) / 100.0)
+                        .desired_width(400.0)
+                        .text(format!("{}: {}", track.title, track.describe())));
+                }
+                if ui.button("Cancel").clicked() {
+                    self.cancel_requested.store(true, Ordering::Relaxed);
+                    self.status = "Cancelling...".into();
+                }
+                ui.spinner();
+            }
+            let report = self.settings.gui.output_dir.as_ref().map(|dir| dir.join(REPORT_HTML)).filter(|p| p.exists());
+            if let Some(report) = report.filter(|_| !self.is_running) {
+                if ui.button("Open report").on_hover_text("Summary of the last run in this folder; failed.csv next to it can be loaded as input.").clicked() {
+                    if let Err(e) = open_path(&report) {
+                        self.last_error = Some(format!("Failed to open {}: {e}", report.display()));
+                    }
+                }
+            }
+
+            // Log window
+            ui.separator();
+            ui.collapsing("Log", |ui| {
+                ui.horizontal(|ui| {
+                    let levels = [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug];
+                    let shown = self.log_filter;
+                    egui::ComboBox::from_label("Show")
+                        .selected_text(self.log_filter.as_str())
+                        .show_ui(ui, |ui| {
+                            for level in levels {
+                                ui.selectable_value(&mut self.log_filter, level, level.as_str());
+                            }
+                        });
+                    if self.log_filter != shown {
+                        logging::set_panel_level(self.log_filter);
+                    }
+                    ui.add(egui::TextEdit::singleline(&mut self.log_search).hint_text("Search").desired_width(160.0));
+                    let file_level = self.config.log_level;
+                    egui::ComboBox::from_label("Log file")
+                        .selected_text(file_level.as_str())
+                        .show_ui(ui, |ui| {
+                            for level in levels {
+                                ui.selectable_value(&mut self.config.log_level, level, level.as_str());
+                            }
+                        })
+                        .response
+                        .on_hover_text("Debug also records every yt-dlp/ffmpeg command and its error output.");
+                    if self.config.log_level != file_level {
+                        logging::set_level(self.config.log_level);
+                    }
+                    if let Some(dir) = logging::log_dir() {
+                        if ui.button("Open log folder").clicked() {
+                            if let Err(e) = open_path(&dir) {
+                                self.last_error = Some(format!("Failed to open {}: {e}", dir.display()));
+                            }
+                        }
+                    }
+                });
+                egui::ScrollArea::vertical().max_height(240.0).stick_to_bottom(true).show(ui, |ui| {
+                    for entry in logging::recent(self.log_filter, &self.log_search, 500) {
+                        let color = match entry.level {
+                            log::Level::Error => Color32::RED,
+                            log::Level::Warn => Color32::YELLOW,
+                            log::Level::Info => ui.visuals().text_color(),
+                            _ => Color32::GRAY,
+                        };
+                        ui.colored_label(color, format!("{} {}", entry.time.format("%H:%M:%S"), entry.message));
+                    }
+                });
+            });
+
+            // Confirm dialog logic
+            if self.confirm_dialog_open {
+                let mut dialog_open = true;
+                let mut should_close_dialog = false;
+                egui::Window::new("Confirm Conversion")
+                    .collapsible(false)
+                    .resizable(false)
+                    .open(&mut dialog_open)
+                    .show(ctx, |ui| {
+                        ui.label("Are you sure you want to start playlist conversion?");
+                        if ui.button("Yes, start").clicked() {
+                            should_close_dialog = true;
+                            if let (Some(_csv), Some(out_dir)) = (&self.csv_path, &self.settings.gui.output_dir) {
+                                let config = self.config.clone();
+                                let out_dir = out_dir.clone();
+                                let tracks = self.tracks.clone();
+                                let yt_dlp_path = self.settings.gui.yt_dlp_path.clone();
+                                let ffmpeg_path = self.settings.gui.ffmpeg_path.clone();
+                                let cancel = Arc::clone(&self.cancel_requested);
+                                let (tx, rx) = mpsc::channel();
+
+                                self.is_running = true;
+                                self.last_error = None;
+                                self.status = "Starting conversion...".into();
+                                self.progress = (0, tracks.len().max(1));
+                                self.track_progress = None;
+                                self.start_time = Some(Instant::now());
+                                cancel.store(false, Ordering::Relaxed);
+                                self.events = Some(rx);
+
+                                thread::spawn(move || {
+                                    // Outcome and errors arrive as events; the return value is not needed
+                                    let _ = convert_playlist(&tracks, &config, &yt_dlp_path, &ffmpeg_path, &out_dir, &tx, &cancel);
+                                });
+                            } else {
+                                self.last_error = Some("Please select a CSV and output folder.".into());
+                            }
+                        }
+                        if ui.button("Cancel").clicked() {
+                            should_close_dialog = true;
+                        }
+                    });
+                if should_close_dialog || !dialog_open {
+                    self.confirm_dialog_open = false;
+                }
+            }
+
+            if self.confirm_delete_open {
+                let mut dialog_open = true;
+                let mut should_close_dialog = false;
+                egui::Window::new("Delete Profile")
+                    .collapsible(false)
+                    .resizable(false)
+                    .open(&mut dialog_open)
+                    .show(ctx, |ui| {
+                        ui.label(format!("Delete profile \"{}\"? This can't be undone.", self.profile));
+                        if ui.button("Yes, delete").clicked() {
+                            should_close_dialog = true;
+                            self.delete_profile();
+                        }
+                        if ui.button("Cancel").clicked() {
+                            should_close_dialog = true;
+                        }
+                    });
+                if should_close_dialog || !dialog_open {
+                    self.confirm_delete_open = false;
+                }
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Save Settings").on_hover_text("Save your configuration in the selected profile for next time.").clicked() {
+                    self.save_settings();
+                }
+            });
+
+            ctx.request_repaint_after(std::time::Duration::from_millis(150));
+        });
+
+        // About dialog
+        if self.show_about {
+            let mut show_about_open = true;
+            egui::Window::new("About Spotify2Media Rust Port")
+                .open(&mut show_about_open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("Spotify2Media Rust Port");
+                    ui.label("By fentbuscoding.");
+                    ui.label("Powered by eframe/egui.");
+                    ui.label("Downloads and tags music from Spotify playlists using yt-dlp & ffmpeg.");
+                    ui.label("Project: github.com/fentbuscoding/spotify2media-rust");
+                });
+            self.show_about = show_about_open;
+        }
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.save_gui_state();
+    }
 }
\ No newline at end of file
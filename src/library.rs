@@ -0,0 +1,223 @@
+use crate::csvparse::TrackInfo;
+use lofty::{Accessor, TaggedFileExt};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File extensions considered audio when indexing a local library.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "flac", "ogg", "opus", "wav", "aiff", "aif", "wv", "ape", "mpc"];
+
+/// A single audio file found in the local library.
+#[derive(Clone, Debug)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+}
+
+/// Index of local audio files, keyed by normalized title for fast lookups.
+#[derive(Debug, Default)]
+pub struct LocalLibrary {
+    entries: Vec<LibraryEntry>,
+    by_title: HashMap<String, Vec<usize>>,
+}
+
+impl LocalLibrary {
+    /// Recursively scan `dirs` and read tags of every audio file found.
+    /// Files without usable tags fall back to an `Artist - Title` file name.
+    pub fn index(dirs: &[PathBuf]) -> Self {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        for dir in dirs {
+            collect_audio_files(dir, &mut visited, &mut files);
+        }
+
+        Self::from_entries(files.par_iter().filter_map(|p| read_entry(p)).collect())
+    }
+
+    fn from_entries(entries: Vec<LibraryEntry>) -> Self {
+        let mut by_title: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            by_title.entry(normalize_title(&entry.title)).or_default().push(i);
+        }
+        Self { entries, by_title }
+    }
+
+    /// Find the best local file for a track: same title, overlapping artist, album match preferred.
+    /// Version notes such as `(Live)` or `- Remix` are part of the title, so a live track
+    /// is never served from the studio recording.
+    pub fn find(&self, track: &TrackInfo) -> Option<&LibraryEntry> {
+        let candidates = self.by_title.get(&normalize_title(&track.title))?;
+        let wanted_artists = split_artists(&track.artist);
+        let wanted_album = normalize(&track.album);
+
+        candidates
+            .iter()
+            .map(|&i| &self.entries[i])
+            .filter(|e| {
+                let have = split_artists(&e.artist);
+                wanted_artists.is_empty() || wanted_artists.iter().any(|a| have.contains(a))
+            })
+            .max_by_key(|e| !wanted_album.is_empty() && normalize(&e.album) == wanted_album)
+    }
+}
+
+/// Key under which two tracks count as the same song: the whole title and the artists,
+/// ignoring only case, spacing and punctuation, so `Song (Live)` is not `Song`.
+pub fn match_key(track: &TrackInfo) -> String {
+    format!("{}|{}", normalize(&track.title), split_artists(&track.artist).join(","))
+}
+
+/// Symlinked folders are followed, but every real folder is read only once, so a link
+/// pointing back up the tree can't recurse forever.
+fn collect_audio_files(dir: &Path, visited: &mut HashSet<PathBuf>, out: &mut Vec<PathBuf>) {
+    if !fs::canonicalize(dir).is_ok_and(|real| visited.insert(real)) {
+        return;
+    }
+    let Ok(read_dir) = fs::read_dir(dir) else { return };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_audio_files(&path, visited, out);
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+            .unwrap_or(false)
+        {
+            out.push(path);
+        }
+    }
+}
+
+fn read_entry(path: &Path) -> Option<LibraryEntry> {
+    let tag_fields = lofty::read_from_path(path).ok().and_then(|tagged| {
+        let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+        Some((
+            tag.title().map(|s| s.to_string()).unwrap_or_default(),
+            tag.artist().map(|s| s.to_string()).unwrap_or_default(),
+            tag.album().map(|s| s.to_string()).unwrap_or_default(),
+        ))
+    });
+
+    let (title, artist, album) = match tag_fields {
+        Some((title, artist, album)) if !title.trim().is_empty() => (title, artist, album),
+        _ => {
+            let stem = path.file_stem()?.to_string_lossy();
+            match stem.split_once(" - ") {
+                Some((artist, title)) => (title.to_string(), artist.to_string(), String::new()),
+                None => (stem.to_string(), String::new(), String::new()),
+            }
+        }
+    };
+
+    Some(LibraryEntry { path: path.to_path_buf(), title, artist, album })
+}
+
+/// Lowercase and keep only alphanumerics separated by single spaces.
+fn normalize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_lowercase().next().unwrap_or(c) } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether a bracketed title part credits other artists, e.g. `feat. X`. `(with Y)` is
+/// left alone, as it just as often names a version such as `(With Strings)`.
+fn is_credit(part: &str) -> bool {
+    let part = part.trim_start().to_ascii_lowercase();
+    ["feat.", "feat ", "ft.", "ft ", "featuring "].iter().any(|f| part.starts_with(f))
+}
+
+/// Normalized title without featured-artist credits, which Spotify puts in the title
+/// and file tags often put in the artist, e.g. `Song (feat. X)`. Version notes such as
+/// `(Live)` or `- Remix` are kept.
+fn normalize_title(title: &str) -> String {
+    let mut kept = String::with_capacity(title.len());
+    let mut rest = title;
+    while let Some(open) = rest.find(['(', '[']) {
+        let close = if rest[open..].starts_with('(') { ')' } else { ']' };
+        let Some(len) = rest[open..].find(close) else { break };
+        kept.push_str(&rest[..open]);
+        if !is_credit(&rest[open + 1..open + len]) {
+            kept.push_str(&rest[open..=open + len]);
+        }
+        rest = &rest[open + len + 1..];
+    }
+    kept.push_str(rest);
+    // Unbracketed credits run to the end of the title; ASCII lowercase keeps the offsets valid
+    let lower = kept.to_ascii_lowercase();
+    if let Some(idx) = [" feat. ", " ft. ", " featuring "].iter().filter_map(|f| lower.find(f)).min() {
+        kept.truncate(idx);
+    }
+    normalize(&kept)
+}
+
+fn split_artists(artist: &str) -> Vec<String> {
+    artist
+        .split([',', '&', ';', '/'])
+        .flat_map(|a| a.split(" feat. "))
+        .flat_map(|a| a.split(" ft. "))
+        .map(normalize)
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::track;
+
+    fn entry(title: &str, artist: &str, album: &str) -> LibraryEntry {
+        let path = PathBuf::from(format!("{artist} - {title} ({album}).mp3"));
+        LibraryEntry { path, title: title.into(), artist: artist.into(), album: album.into() }
+    }
+
+    #[test]
+    fn matches_full_title_and_overlapping_artist() {
+        let library = LocalLibrary::from_entries(vec![
+            entry("Song", "Artist", "Single"),
+            entry("Song", "Artist", "Album"),
+            entry("Song (Live)", "Artist", "Live at Home"),
+            entry("Other", "Someone Else", ""),
+        ]);
+        let found = |title: &str, artist: &str, album: &str| library.find(&track(title, artist, album)).map(|e| e.album.as_str());
+
+        assert_eq!(found("Song", "Artist", "Album"), Some("Album"));
+        assert!(found("song!", "Guest & Artist", "").is_some());
+        assert_eq!(found("Song (feat. Guest)", "Artist", "Album"), Some("Album"));
+        assert_eq!(found("Song (Live)", "Artist", ""), Some("Live at Home"));
+        assert_eq!(found("Song - Remix", "Artist", ""), None);
+        assert_eq!(found("Song (Acoustic)", "Artist", ""), None);
+        assert_eq!(found("Song (With Strings)", "Artist", ""), None);
+        assert_eq!(found("Other", "Artist", ""), None);
+    }
+
+    #[test]
+    fn drops_only_featured_artist_credits() {
+        assert_eq!(normalize_title("Song [ft. A] (Radio Edit)"), "song radio edit");
+        assert_eq!(normalize_title("Song feat. A & B"), "song");
+        assert_eq!(normalize_title("Song (With Strings)"), "song with strings");
+        assert_eq!(normalize_title("Song - Remastered 2011"), "song remastered 2011");
+        assert_eq!(normalize_title("Ä (Live"), "ä live");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn survives_symlink_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("Artist - Song.mp3"), "not audio").unwrap();
+        std::os::unix::fs::symlink(dir.path(), sub.join("loop")).unwrap();
+
+        let library = LocalLibrary::index(&[dir.path().to_path_buf()]);
+
+        assert_eq!(library.entries.len(), 1);
+        assert!(library.find(&track("Song", "Artist", "")).is_some());
+    }
+}
@@ -0,0 +1,277 @@
+use chrono::{DateTime, Local};
+use directories::ProjectDirs;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// The log file is rotated once it grows past this size.
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+/// Rotated files kept next to the current one (`spotify2media.log.1` is the newest).
+const KEEP_ROTATED: usize = 3;
+/// Records kept in memory for the GUI log panel.
+const MEMORY_ENTRIES: usize = 5000;
+
+pub const LOG_FILE: &str = "spotify2media.log";
+
+/// One log record as shown in the GUI and written to the file.
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    /// The conversion run the record belongs to, if one was going on.
+    pub run: Option<String>,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// Whether the entry passes the log panel's filters: at least as severe as `level`
+    /// and containing `search`, ignoring case.
+    pub fn matches(&self, level: LevelFilter, search: &str) -> bool {
+        self.level <= level && (search.is_empty() || self.message.to_lowercase().contains(&search.to_lowercase()))
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<5}", self.time.format("%Y-%m-%dT%H:%M:%S%.3f%:z"), self.level)?;
+        if let Some(run) = &self.run {
+            write!(f, " [run {run}]")?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+/// Append-only file that is renamed to `.1`, `.2`, ... once it gets too big.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, size, max_bytes })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let _ = fs::remove_file(self.rotated(KEEP_ROTATED));
+        for n in (1..KEEP_ROTATED).rev() {
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+/// Sends records to the rotating log file and keeps the latest ones for the GUI.
+///
+/// Each sink has its own level; the global `log` max level is the more verbose of the two.
+struct Logger {
+    file: Mutex<Option<RotatingFile>>,
+    file_level: Mutex<LevelFilter>,
+    entries: Mutex<VecDeque<LogEntry>>,
+    panel_level: Mutex<LevelFilter>,
+    run: Mutex<Option<String>>,
+}
+
+impl Logger {
+    /// The more verbose of the file and panel levels.
+    fn max_level(&self) -> LevelFilter {
+        (*self.file_level.lock().unwrap()).max(*self.panel_level.lock().unwrap())
+    }
+
+    fn update_max_level(&self) {
+        log::set_max_level(self.max_level());
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let entry = LogEntry {
+            time: Local::now(),
+            level: record.level(),
+            run: self.run.lock().unwrap().clone(),
+            message: record.args().to_string(),
+        };
+        if entry.level <= *self.file_level.lock().unwrap() {
+            if let Some(file) = self.file.lock().unwrap().as_mut() {
+                // Nowhere left to report a failing log file to
+                let _ = file.write_line(&entry.to_string());
+            }
+        }
+        if entry.level > *self.panel_level.lock().unwrap() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == MEMORY_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Folder holding the log files, in the platform's app data directory.
+pub fn log_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "spotify2media").map(|dirs| dirs.data_dir().join("logs"))
+}
+
+/// Install the logger, writing `level` and up to the file and keeping Info and up for
+/// the GUI. Records still reach the GUI if the log file can't be opened; the returned
+/// error says why it couldn't.
+pub fn init(level: LevelFilter) -> Result<PathBuf, String> {
+    let path = log_dir().map(|dir| dir.join(LOG_FILE)).ok_or("no data directory for the log file")?;
+    let file = RotatingFile::open(&path, MAX_LOG_BYTES);
+    let logger = LOGGER.get_or_init(|| Logger {
+        file: Mutex::new(None),
+        file_level: Mutex::new(level),
+        entries: Mutex::new(VecDeque::new()),
+        panel_level: Mutex::new(LevelFilter::Info),
+        run: Mutex::new(None),
+    });
+    let _ = log::set_logger(logger);
+    logger.update_max_level();
+    let file = file.map_err(|e| format!("cannot open log file {}: {e}", path.display()))?;
+    *logger.file.lock().unwrap() = Some(file);
+    Ok(path)
+}
+
+/// Set the level written to the log file.
+pub fn set_level(level: LevelFilter) {
+    if let Some(logger) = LOGGER.get() {
+        *logger.file_level.lock().unwrap() = level;
+        logger.update_max_level();
+    }
+}
+
+/// Set the level kept in memory for the GUI log panel.
+pub fn set_panel_level(level: LevelFilter) {
+    if let Some(logger) = LOGGER.get() {
+        *logger.panel_level.lock().unwrap() = level;
+        logger.update_max_level();
+    }
+}
+
+/// Tags log records with a run ID until it is dropped.
+pub struct RunTag {
+    pub id: String,
+}
+
+impl Drop for RunTag {
+    fn drop(&mut self) {
+        if let Some(logger) = LOGGER.get() {
+            *logger.run.lock().unwrap() = None;
+        }
+    }
+}
+
+/// Tag the following records with a new run ID, until the returned tag is dropped.
+pub fn start_run() -> RunTag {
+    let id = format!("{:08x}", Local::now().timestamp_millis() as u32 ^ std::process::id().rotate_left(16));
+    if let Some(logger) = LOGGER.get() {
+        *logger.run.lock().unwrap() = Some(id.clone());
+    }
+    RunTag { id }
+}
+
+/// The latest `limit` in-memory entries passing the filters, oldest first.
+pub fn recent(level: LevelFilter, search: &str, limit: usize) -> Vec<LogEntry> {
+    let Some(logger) = LOGGER.get() else { return Vec::new() };
+    let entries = logger.entries.lock().unwrap();
+    let mut found: Vec<LogEntry> = entries.iter().rev().filter(|e| e.matches(level, search)).take(limit).cloned().collect();
+    found.reverse();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_and_keeps_a_few_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOG_FILE);
+        let mut file = RotatingFile::open(&path, 100).unwrap();
+        for n in 0..20 {
+            file.write_line(&format!("line {n:02} {}", "x".repeat(30))).unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        assert!(current.starts_with("line 18") && current.contains("line 19"));
+        assert!(fs::read_to_string(dir.path().join(format!("{LOG_FILE}.1"))).unwrap().starts_with("line 16"));
+        assert!(dir.path().join(format!("{LOG_FILE}.3")).exists());
+        assert!(!dir.path().join(format!("{LOG_FILE}.4")).exists());
+    }
+
+    #[test]
+    fn filters_by_level_and_text() {
+        let entry = LogEntry { time: Local::now(), level: Level::Debug, run: Some("1a2b".into()), message: "Running yt-dlp".into() };
+        assert!(entry.matches(LevelFilter::Debug, "YT-DLP"));
+        assert!(!entry.matches(LevelFilter::Info, ""));
+        assert!(!entry.matches(LevelFilter::Trace, "ffmpeg"));
+        assert!(entry.to_string().ends_with(" DEBUG [run 1a2b] Running yt-dlp"));
+    }
+
+    #[test]
+    fn file_and_panel_keep_their_own_levels() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOG_FILE);
+        let logger = Logger {
+            file: Mutex::new(Some(RotatingFile::open(&path, MAX_LOG_BYTES).unwrap())),
+            file_level: Mutex::new(LevelFilter::Info),
+            entries: Mutex::new(VecDeque::new()),
+            panel_level: Mutex::new(LevelFilter::Debug),
+            run: Mutex::new(None),
+        };
+        assert_eq!(logger.max_level(), LevelFilter::Debug);
+
+        logger.log(&Record::builder().level(Level::Debug).args(format_args!("yt-dlp command")).build());
+        logger.log(&Record::builder().level(Level::Info).args(format_args!("Track done")).build());
+
+        let messages: Vec<_> = logger.entries.lock().unwrap().iter().map(|e| e.message.clone()).collect();
+        assert_eq!(messages, ["yt-dlp command", "Track done"]);
+        let file = fs::read_to_string(&path).unwrap();
+        assert!(file.contains("Track done") && !file.contains("yt-dlp command"));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::audio::reencode_in_place;
+use crate::config::LoudnessConfig;
+use crate::spotify2media::Tools;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// ReplayGain 2.0 reference level.
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+/// EBU R128 measurement of one file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// True peak in dBTP.
+    pub true_peak: f64,
+    /// Loudness range in LU.
+    pub range: f64,
+    threshold: f64,
+    offset: f64,
+}
+
+impl Loudness {
+    /// ReplayGain track gain in dB.
+    pub fn gain(&self) -> f64 {
+        REPLAYGAIN_REFERENCE_LUFS - self.integrated
+    }
+
+    /// True peak as a linear sample value, as ReplayGain stores it.
+    pub fn peak(&self) -> f64 {
+        10f64.powf(self.true_peak / 20.0)
+    }
+}
+
+/// The block `loudnorm=print_format=json` prints at the end of ffmpeg's stderr.
+#[derive(Deserialize)]
+struct LoudnormStats {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+fn parse_stats(stderr: &str) -> Result<Loudness> {
+    let start = stderr.rfind('{').context("no loudnorm statistics in ffmpeg output")?;
+    let end = stderr[start..].find('}').context("truncated loudnorm statistics")? + start;
+    let stats: LoudnormStats = serde_json::from_str(&stderr[start..=end]).context("invalid loudnorm statistics")?;
+    // Silence is reported as "-inf"
+    let value = |v: &str| v.trim().parse::<f64>().ok().filter(|v| v.is_finite()).unwrap_or(-70.0);
+    Ok(Loudness {
+        integrated: value(&stats.input_i),
+        true_peak: value(&stats.input_tp),
+        range: value(&stats.input_lra),
+        threshold: value(&stats.input_thresh),
+        offset: value(&stats.target_offset),
+    })
+}
+
+fn loudnorm_filter(config: &LoudnessConfig) -> String {
+    format!("loudnorm=I={}:TP={}:LRA=11", config.target_lufs, config.true_peak_db)
+}
+
+/// Measure the loudness of `path` with ffmpeg's `loudnorm` filter.
+pub fn measure(tools: &Tools, path: &Path, config: &LoudnessConfig) -> Result<Loudness> {
+    let args: Vec<OsString> = vec![
+        "-hide_banner".into(),
+        "-nostats".into(),
+        "-i".into(),
+        path.into(),
+        "-af".into(),
+        format!("{}:print_format=json", loudnorm_filter(config)).into(),
+        "-f".into(),
+        "null".into(),
+        "-".into(),
+    ];
+    let output = tools.runner.run(tools.ffmpeg, &args).context("Failed to start ffmpeg")?;
+    if !output.success {
+        anyhow::bail!("ffmpeg loudness analysis failed: {}", output.stderr.trim());
+    }
+    parse_stats(&output.stderr)
+}
+
+/// Re-encode `path` in place so it plays at `config.target_lufs`, using the first-pass `measured` values.
+pub fn normalize(tools: &Tools, path: &Path, measured: &Loudness, config: &LoudnessConfig, bitrate_kbps: u32) -> Result<()> {
+    let sample_rate = crate::verify::probe(path).ok().and_then(|i| i.sample_rate).unwrap_or(44_100);
+    let filter = format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        loudnorm_filter(config),
+        measured.integrated,
+        measured.true_peak,
+        measured.range,
+        measured.threshold,
+        measured.offset
+    );
+    let options: Vec<OsString> = vec![
+        "-af".into(),
+        filter.into(),
+        // loudnorm works at 192 kHz internally; keep the original rate
+        "-ar".into(),
+        sample_rate.to_string().into(),
+    ];
+    reencode_in_place(tools, path, options, bitrate_kbps).context("normalization failed")
+}
+
+/// ReplayGain album loudness: the duration-weighted power mean of the tracks' loudness.
+pub fn album_loudness(tracks: &[(Loudness, f64)]) -> Option<Loudness> {
+    let total: f64 = tracks.iter().map(|(_, secs)| secs.max(1.0)).sum();
+    if tracks.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let energy: f64 =
+        tracks.iter().map(|(l, secs)| 10f64.powf(l.integrated / 10.0) * secs.max(1.0)).sum::<f64>() / total;
+    let peak = tracks.iter().map(|(l, _)| l.true_peak).fold(f64::NEG_INFINITY, f64::max);
+    Some(Loudness { integrated: 10.0 * energy.log10(), true_peak: peak, range: 0.0, threshold: 0.0, offset: 0.0 })
+}
+
+/// `REPLAYGAIN_*` fields for a track and, if known, its album.
+pub fn replaygain_fields(track: &Loudness, album: Option<&Loudness>) -> BTreeMap<&'static str, String> {
+    let mut fields = BTreeMap::new();
+    fields.insert("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", track.gain()));
+    fields.insert("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", track.peak()));
+    if let Some(album) = album {
+        fields.insert("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album.gain()));
+        fields.insert("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album.peak()));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loudness(integrated: f64, true_peak: f64) -> Loudness {
+        Loudness { integrated, true_peak, range: 5.0, threshold: -30.0, offset: 0.0 }
+    }
+
+    #[test]
+    fn parses_loudnorm_json() {
+        let stderr = "Input #0, mp3\n[Parsed_loudnorm_0 @ 0x5581]\n{\n\t\"input_i\" : \"-27.61\",\n\t\"input_tp\" : \"-4.47\",\n\t\"input_lra\" : \"18.06\",\n\t\"input_thresh\" : \"-39.20\",\n\t\"output_i\" : \"-16.58\",\n\t\"target_offset\" : \"0.58\"\n}\n";
+        let l = parse_stats(stderr).unwrap();
+        assert_eq!((l.integrated, l.true_peak, l.range), (-27.61, -4.47, 18.06));
+        assert!((l.gain() - 9.61).abs() < 1e-9);
+        assert!(parse_stats("no json here").is_err());
+    }
+
+    #[test]
+    fn album_gain_is_weighted_power_mean() {
+        let album = album_loudness(&[(loudness(-10.0, -1.0), 100.0), (loudness(-10.0, -3.0), 300.0)]).unwrap();
+        assert!((album.integrated + 10.0).abs() < 1e-9);
+        assert_eq!(album.true_peak, -1.0);
+        // A quiet track barely moves the album level
+        let album = album_loudness(&[(loudness(-8.0, 0.0), 200.0), (loudness(-30.0, 0.0), 200.0)]).unwrap();
+        assert!(album.integrated > -11.1 && album.integrated < -10.9, "{}", album.integrated);
+
+        let fields = replaygain_fields(&loudness(-12.0, 0.0), Some(&album));
+        assert_eq!(fields["REPLAYGAIN_TRACK_GAIN"], "-6.00 dB");
+        assert_eq!(fields["REPLAYGAIN_TRACK_PEAK"], "1.000000");
+        assert!(fields.contains_key("REPLAYGAIN_ALBUM_GAIN"));
+    }
+}
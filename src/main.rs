@@ -1,47 +1,91 @@
-mod gui;
-mod config;
-mod csvparse;
-mod playlist;
-mod audio;
-mod spotify2media;
-
-use eframe::NativeOptions;
-use gui::Spotify2MediaApp;
-
-fn main() {
-    // Print version info at startup
-    println!("Spotify2Media (Rust Port) v{}", env!("CARGO_PKG_VERSION"));
-
-    // Set a panic hook for better diagnostics
-    std::panic::set_hook(Box::new(|info| {
-        eprintln!("Application panicked: {info}");
-        #[cfg(target_os = "windows")]
-        {
-            use std::io::Write;
-            let _ = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open("panic.log")
-                .and_then(|mut f| writeln!(f, "Panic: {info}"));
-        }
-    }));
-
-    let options = NativeOptions::default();
-
-    // Try to run the GUI app, show error in stderr and a message box if it fails
-    if let Err(e) = eframe::run_native(
-        "Spotify2Media (Rust Port)",
-        options,
-        Box::new(|_cc| Box::new(Spotify2MediaApp::default())),
-    ) {
-        eprintln!("Application error: {e}");
-        #[cfg(target_os = "windows")]
-        {
-            use rfd::MessageDialog;
-            MessageDialog::new()
-                .set_title("Spotify2Media Error")
-                .set_description(&format!("Application error: {e}"))
-                .show();
-        }
-    }
-}
+mod gui;
+mod cli;
+mod config;
+mod csvparse;
+mod playlist;
+mod plan;
+mod progress;
+mod report;
+mod audio;
+mod artwork;
+mod device;
+mod djexport;
+mod events;
+mod failure;
+mod library;
+mod logging;
+mod loudness;
+mod mediaserver;
+mod naming;
+mod spotify2media;
+mod subprocess;
+mod sync;
+mod trim;
+mod verify;
+
+#[cfg(test)]
+mod test_support;
+
+use config::{settings_path, Settings};
+use eframe::NativeOptions;
+use gui::Spotify2MediaApp;
+
+fn main() {
+    // The GUI and CLI apply the configured level once they have loaded the settings
+    let log_file = logging::init(log::LevelFilter::Info);
+    log::info!("Spotify2Media (Rust Port) v{}", env!("CARGO_PKG_VERSION"));
+    match log_file {
+        Ok(path) => log::info!("Logging to {}", path.display()),
+        Err(e) => eprintln!("Not logging to a file: {e}"),
+    }
+
+    // Set a panic hook for better diagnostics
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("Application panicked: {info}");
+        log::error!("Application panicked: {info}");
+        log::logger().flush();
+        #[cfg(target_os = "windows")]
+        {
+            use std::io::Write;
+            let _ = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open("panic.log")
+                .and_then(|mut f| writeln!(f, "Panic: {info}"));
+        }
+    }));
+
+    // Any command line arguments select the headless CLI instead of the GUI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args) {
+            log::error!("{e}");
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut options = NativeOptions::default();
+    if let Some(size) = Settings::load(&settings_path()).ok().and_then(|s| s.gui.window_size) {
+        options.viewport = options.viewport.with_inner_size(size);
+    }
+
+    // Try to run the GUI app, show error in stderr and a message box if it fails
+    if let Err(e) = eframe::run_native(
+        "Spotify2Media (Rust Port)",
+        options,
+        Box::new(|cc| Box::new(Spotify2MediaApp::new(cc))),
+    ) {
+        eprintln!("Application error: {e}");
+        log::error!("Application error: {e}");
+        #[cfg(target_os = "windows")]
+        {
+            use rfd::MessageDialog;
+            MessageDialog::new()
+                .set_title("Spotify2Media Error")
+                .set_description(&format!("Application error: {e}"))
+                .show();
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::config::{AppConfig, MediaServer, MediaServerConfig, PlaylistPaths};
+use crate::naming::sanitize_component;
+use crate::playlist::entries;
+use crate::spotify2media::TrackResult;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Translate a local file path into the path the media server sees, using the
+/// `local_root` -> `server_root` mapping. Returns `None` for files outside `local_root`.
+pub fn server_path(file: &Path, local_root: &Path, server_root: &str) -> Option<String> {
+    let relative = file.strip_prefix(local_root).ok()?;
+    // Windows servers (`D:\Music`) get backslashes, everything else forward slashes
+    let separator = if server_root.contains('\\') && !server_root.contains('/') { "\\" } else { "/" };
+    let mut path = server_root.trim_end_matches(['/', '\\']).to_string();
+    for part in relative.iter() {
+        path.push_str(separator);
+        path.push_str(&part.to_string_lossy());
+    }
+    Some(path)
+}
+
+/// The local folder the server's root is mapped from, as an absolute path.
+fn local_root(server: &MediaServerConfig, output_dir: &Path) -> PathBuf {
+    let root = match server.local_root.as_os_str().is_empty() {
+        true => output_dir.to_path_buf(),
+        false => server.local_root.clone(),
+    };
+    std::path::absolute(&root).unwrap_or(root)
+}
+
+/// Check the mapping before a run starts, so a typo doesn't surface only after every download.
+pub fn check_mapping(server: &MediaServerConfig, output_dir: &Path) -> Result<()> {
+    if server.server_root.trim().is_empty() {
+        bail!("media server export needs the server's path to the music folder");
+    }
+    let output_dir = std::path::absolute(output_dir).unwrap_or_else(|_| output_dir.to_path_buf());
+    let root = local_root(server, &output_dir);
+    if !output_dir.starts_with(&root) {
+        bail!("output folder {:?} is not inside the media server folder {:?}", output_dir, root);
+    }
+    Ok(())
+}
+
+/// Write the media-server playlist for `results` and return its path.
+///
+/// The file is an extended M3U with every entry rewritten to the server's library root.
+/// It is named `name.m3u8`, or `name (server).m3u8` if a regular playlist already took that name.
+pub fn write_server_playlist(
+    output_dir: &Path,
+    name: &str,
+    results: &[TrackResult],
+    config: &AppConfig,
+    taken: &[PathBuf],
+) -> Result<PathBuf> {
+    let server = &config.media_server;
+    let local_root = local_root(server, output_dir);
+    let dir = server.playlist_dir.clone().unwrap_or_else(|| output_dir.to_path_buf());
+    let file_name = sanitize_component(name, config.naming_profile);
+    let mut path = dir.join(format!("{file_name}.m3u8"));
+    if taken.contains(&path) {
+        path = dir.join(format!("{file_name} (server).m3u8"));
+    }
+
+    let mut txt = String::from("#EXTM3U\n");
+    if server.server == MediaServer::Navidrome {
+        txt.push_str(&format!("#PLAYLIST:{}\n", config.naming_profile.text(name)));
+    }
+    for e in entries(&path, results, PlaylistPaths::Absolute) {
+        let Some(location) = server_path(&e.absolute, &local_root, &server.server_root) else {
+            bail!("{:?} is outside the media server folder {:?}", e.absolute, local_root);
+        };
+        // Plex ignores #EXTINF, the others show it until their scan has read the tags
+        txt.push_str(&format!("#EXTINF:{},{}\n", e.duration_secs(), e.display_title(config.naming_profile)));
+        txt.push_str(&format!("{location}\n"));
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    fs::write(&path, txt).with_context(|| format!("Failed to write playlist {:?}", path))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_output_outside_mapped_folder() {
+        let mut server = MediaServerConfig { server_root: "/music".into(), ..MediaServerConfig::default() };
+        assert!(check_mapping(&server, Path::new("/home/me/Music/Mix")).is_ok());
+        server.local_root = "/home/me/Music".into();
+        assert!(check_mapping(&server, Path::new("/home/me/Music/Mix")).is_ok());
+        assert!(check_mapping(&server, Path::new("/tmp/Mix")).is_err());
+        server.server_root.clear();
+        assert!(check_mapping(&server, Path::new("/home/me/Music/Mix")).is_err());
+    }
+
+    #[test]
+    fn maps_local_paths_to_server_root() {
+        let local = Path::new("/home/me/Music");
+        let file = local.join("Mix").join("01 - Song.mp3");
+        assert_eq!(server_path(&file, local, "/music/").as_deref(), Some("/music/Mix/01 - Song.mp3"));
+        assert_eq!(server_path(&file, local, r"D:\Media\Music").as_deref(), Some(r"D:\Media\Music\Mix\01 - Song.mp3"));
+        assert_eq!(server_path(Path::new("/tmp/other.mp3"), local, "/music"), None);
+    }
+}
@@ -0,0 +1,413 @@
+use crate::config::NamingProfile;
+use crate::csvparse::TrackInfo;
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Used when the config does not set `file_name_template`.
+pub const DEFAULT_TEMPLATE: &str = "{artist} - {title}";
+
+/// Longest file or folder name we produce. Filesystems allow 255 bytes (Linux) or
+/// UTF-16 units (Windows, FAT32); the rest is kept free for the extension and a ` (n)` suffix.
+const MAX_COMPONENT_LEN: usize = 200;
+
+/// Windows' `MAX_PATH` without the terminating NUL, minus room for a ` (nn)` collision suffix.
+const MAX_WINDOWS_PATH: usize = 259 - 5;
+
+/// Folders are never shortened below this many characters to fit the path limit.
+const MIN_COMPONENT_LEN: usize = 8;
+
+/// Characters Windows (and FAT32/exFAT) does not allow in file names.
+const WINDOWS_RESERVED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Device names Windows reserves regardless of extension (`CON.mp3` is still `CON`).
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl NamingProfile {
+    fn windows_rules(self) -> bool {
+        self != NamingProfile::Linux
+    }
+
+    /// Whether `a.mp3` and `A.mp3` are the same file on the target.
+    fn case_insensitive(self) -> bool {
+        self.windows_rules()
+    }
+
+    /// Length of `name` in the unit the target filesystem limits.
+    fn len(self, name: &str) -> usize {
+        if self.windows_rules() {
+            name.encode_utf16().count()
+        } else {
+            name.len()
+        }
+    }
+
+    fn max_path(self) -> Option<usize> {
+        self.windows_rules().then_some(MAX_WINDOWS_PATH)
+    }
+
+    /// Text as it may appear inside the target's files (e.g. playlist titles):
+    /// transliterated for [`NamingProfile::Ascii`], unchanged otherwise.
+    pub fn text(self, text: &str) -> String {
+        match self {
+            NamingProfile::Ascii => deunicode::deunicode(text),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// A `TrackInfo` value a template can refer to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+    Year,
+    Date,
+    Genre,
+    Isrc,
+    /// One-based position in the input playlist.
+    Index,
+}
+
+impl Field {
+    const NAMES: [(&'static str, Field); 13] = [
+        ("title", Field::Title),
+        ("artist", Field::Artist),
+        ("album", Field::Album),
+        ("album_artist", Field::AlbumArtist),
+        ("track", Field::Track),
+        ("track_total", Field::TrackTotal),
+        ("disc", Field::Disc),
+        ("disc_total", Field::DiscTotal),
+        ("year", Field::Year),
+        ("date", Field::Date),
+        ("genre", Field::Genre),
+        ("isrc", Field::Isrc),
+        ("index", Field::Index),
+    ];
+
+    fn value(self, track: &TrackInfo, index: usize) -> String {
+        let or = |value: &str, fallback: &str| if value.trim().is_empty() { fallback.to_string() } else { value.to_string() };
+        let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+        match self {
+            Field::Title => or(&track.title, "Unknown Title"),
+            Field::Artist => or(&track.artist, "Unknown Artist"),
+            Field::Album => or(&track.album, "Unknown Album"),
+            Field::AlbumArtist => or(&track.album_artist, &or(&track.artist, "Unknown Artist")),
+            Field::Track => number(track.track_number),
+            Field::TrackTotal => number(track.track_total),
+            Field::Disc => number(track.disc_number),
+            Field::DiscTotal => number(track.disc_total),
+            Field::Year => track.release_date.chars().take(4).collect(),
+            Field::Date => track.release_date.clone(),
+            Field::Genre => track.genres.first().cloned().unwrap_or_default(),
+            Field::Isrc => track.isrc.clone(),
+            Field::Index => (index + 1).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Piece {
+    Text(String),
+    /// `{name}` or `{name:0N}`; numbers are zero-padded to `width` digits.
+    Field { field: Field, width: usize },
+}
+
+/// Output path template such as `{album_artist}/{album}/{track:02} - {title}`.
+///
+/// `/` separates folders. Values are sanitized before they are inserted, so an
+/// artist like `AC/DC` never creates a folder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTemplate {
+    pieces: Vec<Piece>,
+    profile: NamingProfile,
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                bail!("unmatched '}}' in file name template {template:?}");
+            }
+            if open > 0 {
+                pieces.push(Piece::Text(rest[..open].to_string()));
+            }
+            let Some(close) = rest[open..].find('}').map(|c| open + c) else {
+                bail!("unclosed '{{' in file name template {template:?}");
+            };
+            let (name, spec) = rest[open + 1..close].split_once(':').unwrap_or((&rest[open + 1..close], ""));
+            let Some(&(_, field)) = Field::NAMES.iter().find(|(n, _)| *n == name.trim()) else {
+                let known: Vec<_> = Field::NAMES.iter().map(|(n, _)| *n).collect();
+                bail!("unknown field {{{name}}} in file name template; use one of {}", known.join(", "));
+            };
+            let width = match spec {
+                "" => 0,
+                spec => match spec.parse() {
+                    Ok(width) => width,
+                    Err(_) => bail!("invalid width {spec:?} for {{{name}}}; write e.g. {{{name}:02}}"),
+                },
+            };
+            pieces.push(Piece::Field { field, width });
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            pieces.push(Piece::Text(rest.to_string()));
+        }
+        if !pieces.iter().any(|p| matches!(p, Piece::Field { .. })) {
+            bail!("file name template {template:?} uses no fields, so every track would get the same name");
+        }
+        Ok(Self { pieces, profile: NamingProfile::default() })
+    }
+
+    /// Produce names valid on `profile`'s filesystem.
+    pub fn with_profile(mut self, profile: NamingProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Relative output path for `track` without an extension, one sanitized component per folder.
+    pub fn render(&self, track: &TrackInfo, index: usize) -> PathBuf {
+        let mut text = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(t) => text.push_str(t),
+                Piece::Field { field, width } => {
+                    let value = field.value(track, index);
+                    let value = if value.is_empty() { value } else { format!("{value:0>width$}") };
+                    text.push_str(&value.replace(['/', '\\'], "_"));
+                }
+            }
+        }
+        text.split('/').filter(|c| !c.trim().is_empty()).map(|c| sanitize_component(c, self.profile)).collect()
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+/// Make one file or folder name valid on `profile`'s filesystem: transliterate or replace
+/// characters it can't store, collapse whitespace, drop separators and dots left dangling by
+/// empty fields (e.g. `" - Title"`), avoid reserved device names and cut the name to
+/// [`MAX_COMPONENT_LEN`].
+pub fn sanitize_component(name: &str, profile: NamingProfile) -> String {
+    let name = profile.text(name);
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '/' => '_',
+            c if c.is_control() => '_',
+            c if profile.windows_rules() && WINDOWS_RESERVED_CHARS.contains(&c) => '_',
+            c if profile == NamingProfile::Fat32 && c.len_utf16() > 1 => '_',
+            c => c,
+        })
+        .collect();
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut name = truncate(collapsed.trim_matches([' ', '-', '.']), MAX_COMPONENT_LEN, profile);
+    if name.is_empty() {
+        name = "Unknown".to_string();
+    }
+    let device = name.split('.').next().unwrap_or_default();
+    if profile.windows_rules() && WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(device)) {
+        name.insert(device.len(), '_');
+    }
+    name
+}
+
+/// Cut `name` to at most `max` length units, on a character boundary and without trailing separators.
+fn truncate(name: &str, max: usize, profile: NamingProfile) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        out.push(c);
+        if profile.len(&out) > max {
+            out.pop();
+            break;
+        }
+    }
+    out.trim_end_matches([' ', '-', '.']).to_string()
+}
+
+/// Shorten the longest components of `relative` until `output_dir/relative.ext` fits the
+/// profile's path limit (or nothing can be shortened further).
+fn fit_path_limit(output_dir: &Path, relative: &Path, ext: &str, profile: NamingProfile) -> PathBuf {
+    let Some(max) = profile.max_path() else { return relative.to_path_buf() };
+    let mut parts: Vec<String> = relative.iter().map(|c| c.to_string_lossy().into_owned()).collect();
+    let length = |parts: &[String]| {
+        let base = profile.len(&output_dir.to_string_lossy()) + ext.len() + 1;
+        base + parts.iter().map(|p| profile.len(p) + 1).sum::<usize>()
+    };
+    while length(&parts) > max {
+        let excess = length(&parts) - max;
+        let Some(longest) = parts.iter_mut().max_by_key(|p| profile.len(p)).filter(|p| profile.len(p) > MIN_COMPONENT_LEN)
+        else {
+            break;
+        };
+        let target = profile.len(longest).saturating_sub(excess).max(MIN_COMPONENT_LEN);
+        *longest = truncate(longest, target, profile);
+        if longest.is_empty() {
+            *longest = "_".to_string();
+            break;
+        }
+    }
+    parts.iter().collect()
+}
+
+/// Return `dir/file_name`, adding a ` (n)` suffix if that path already exists on disk
+/// or was handed out earlier in this run. With `fold_case`, names differing only in case
+/// count as the same file.
+pub fn unique_path(dir: &Path, file_name: &str, taken: &mut HashSet<PathBuf>, fold_case: bool) -> PathBuf {
+    let key = |p: &Path| if fold_case { PathBuf::from(p.to_string_lossy().to_lowercase()) } else { p.to_path_buf() };
+    // The output may be written on a case-sensitive disk before it is copied to the target
+    let existing: HashSet<PathBuf> = match fold_case {
+        true => fs::read_dir(dir).into_iter().flatten().filter_map(|e| e.ok()).map(|e| key(&e.path())).collect(),
+        false => HashSet::new(),
+    };
+    let is_free = |p: &PathBuf, taken: &HashSet<PathBuf>| {
+        let key = key(p);
+        !p.exists() && !existing.contains(&key) && !taken.contains(&key)
+    };
+    let candidate = dir.join(file_name);
+    let path = if is_free(&candidate, taken) {
+        candidate
+    } else {
+        let name = Path::new(file_name);
+        let stem = name.file_stem().and_then(|s| s.to_str()).unwrap_or("track");
+        let ext = name.extension().and_then(|e| e.to_str());
+        (1..)
+            .map(|n| match ext {
+                Some(ext) => dir.join(format!("{stem} ({n}).{ext}")),
+                None => dir.join(format!("{stem} ({n})")),
+            })
+            .find(|p| is_free(p, taken))
+            .unwrap()
+    };
+    taken.insert(key(&path));
+    path
+}
+
+/// Return `dir/stem` without an extension, for files whose extension is only known once
+/// they exist. Adds a ` (n)` suffix while any file on disk or path handed out earlier in
+/// this run has that stem, whatever its extension.
+pub fn unique_stem(dir: &Path, stem: &str, taken: &mut HashSet<PathBuf>, fold_case: bool) -> PathBuf {
+    let fold = |p: &Path| if fold_case { p.to_string_lossy().to_lowercase() } else { p.to_string_lossy().into_owned() };
+    let on_disk = fs::read_dir(dir).into_iter().flatten().filter_map(|e| e.ok()).map(|e| fold(&e.path()));
+    let names: Vec<String> = on_disk.chain(taken.iter().map(|p| fold(p))).collect();
+    let is_free = |candidate: &Path| {
+        let candidate = fold(candidate);
+        !names.iter().any(|name| name.strip_prefix(candidate.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')))
+    };
+    let path = std::iter::once(dir.join(stem))
+        .chain((1..).map(|n| dir.join(format!("{stem} ({n})"))))
+        .find(|p| is_free(p))
+        .unwrap();
+    taken.insert(PathBuf::from(fold(&path)));
+    path
+}
+
+/// Final location for `track` under `output_dir`: the rendered template plus `ext`, within
+/// the profile's path limit, made unique against files on disk and paths already handed out.
+/// An empty `ext` gives a path without extension, unique by stem (see [`unique_stem`]).
+pub fn output_path(
+    template: &NameTemplate,
+    track: &TrackInfo,
+    index: usize,
+    ext: &str,
+    output_dir: &Path,
+    taken: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let profile = template.profile;
+    let relative = fit_path_limit(output_dir, &template.render(track, index), ext, profile);
+    let dir = relative.parent().map(|p| output_dir.join(p)).unwrap_or_else(|| output_dir.to_path_buf());
+    let stem = relative.file_name().and_then(|n| n.to_str()).unwrap_or("track");
+    if ext.is_empty() {
+        return unique_stem(&dir, stem, taken, profile.case_insensitive());
+    }
+    unique_path(&dir, &format!("{stem}.{ext}"), taken, profile.case_insensitive())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::track;
+
+    #[test]
+    fn renders_folders_padding_and_fallbacks() {
+        let template = NameTemplate::parse("{album_artist}/{album}/{track:02} - {title}").unwrap();
+        let mut song = track("Who Made Who?", "AC/DC", "");
+        song.track_number = Some(3);
+        assert_eq!(template.render(&song, 0), Path::new("AC_DC/Unknown Album/03 - Who Made Who_"));
+
+        song.track_number = None;
+        song.album = "...Live. ".into();
+        assert_eq!(template.render(&song, 0), Path::new("AC_DC/Live/Who Made Who_"));
+
+        let long = track(&"x".repeat(300), "Artist", "");
+        let name = NameTemplate::parse("{index:03} {title}").unwrap().render(&long, 6);
+        let name = name.to_str().unwrap();
+        assert!(name.starts_with("007 xxx"));
+        assert_eq!(name.len(), MAX_COMPONENT_LEN);
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(NameTemplate::parse("{artist} - {name}").unwrap_err().to_string().contains("unknown field {name}"));
+        assert!(NameTemplate::parse("{title").is_err());
+        assert!(NameTemplate::parse("{track:ab}").is_err());
+        assert!(NameTemplate::parse("song").is_err());
+    }
+
+    #[test]
+    fn profiles_follow_target_filesystem_rules() {
+        let clean = |name: &str, profile| sanitize_component(name, profile);
+        assert_eq!(clean("What? Now: 1/2 *live*", NamingProfile::Linux), "What? Now: 1_2 *live*");
+        assert_eq!(clean("What? Now: 1/2 *live*", NamingProfile::Windows), "What_ Now_ 1_2 _live_");
+        assert_eq!(clean("con", NamingProfile::Windows), "con_");
+        assert_eq!(clean("Nul.remix", NamingProfile::Fat32), "Nul_.remix");
+        assert_eq!(clean("CON", NamingProfile::Linux), "CON");
+        assert_eq!(clean("Party 🎉 Time...", NamingProfile::Fat32), "Party _ Time");
+        assert_eq!(clean("Björk – Jóga", NamingProfile::Ascii), "Bjork - Joga");
+        assert_eq!(clean("坂本龍一", NamingProfile::Ascii), "Ban Ben Long Yi");
+
+        // Windows paths stay under MAX_PATH by shortening the longest names
+        let template = NameTemplate::parse("{album}/{title}").unwrap().with_profile(NamingProfile::Windows);
+        let out = Path::new("/music/out");
+        let long = track(&"t".repeat(190), "Artist", &"a".repeat(190));
+        let path = output_path(&template, &long, 0, "mp3", out, &mut HashSet::new());
+        assert!(path.to_str().unwrap().encode_utf16().count() <= MAX_WINDOWS_PATH);
+        assert_eq!(path.parent().unwrap(), out.join("a".repeat(190)));
+        let linux = template.clone().with_profile(NamingProfile::Linux);
+        assert_eq!(output_path(&linux, &long, 0, "mp3", out, &mut HashSet::new()).to_str().unwrap().len(), 10 + 191 + 195);
+    }
+
+    #[test]
+    fn collisions_get_a_numbered_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Artist - Song.mp3"), b"").unwrap();
+        let template = NameTemplate::default();
+        let mut taken = HashSet::new();
+        let song = track("Song", "Artist", "");
+        let first = output_path(&template, &song, 0, "mp3", dir.path(), &mut taken);
+        let second = output_path(&template, &track("SONG", "artist", ""), 1, "mp3", dir.path(), &mut taken);
+        assert_eq!(first, dir.path().join("Artist - Song (1).mp3"));
+        // Windows and FAT32 don't tell names apart by case
+        assert_eq!(second, dir.path().join("artist - SONG (2).mp3"));
+        let linux = NameTemplate::default().with_profile(NamingProfile::Linux);
+        let third = output_path(&linux, &track("SONG", "artist", ""), 2, "mp3", dir.path(), &mut taken);
+        assert_eq!(third, dir.path().join("artist - SONG.mp3"));
+    }
+}
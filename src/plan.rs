@@ -0,0 +1,206 @@
+use crate::config::{AppConfig, LibraryMode};
+use crate::csvparse::TrackInfo;
+use crate::library::{match_key, LocalLibrary};
+use crate::naming::{output_path, NameTemplate};
+use crate::spotify2media::{library_destination, search_queries, yt_dlp_args, Tools};
+use crate::subprocess::display_command;
+use crate::sync::track_key;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What the engine intends to do with one input track.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// Search with each query in turn until yt-dlp produces a file.
+    Download {
+        queries: Vec<String>,
+        commands: Vec<String>,
+        output: PathBuf,
+    },
+    /// Take the file from the local library.
+    Library {
+        source: PathBuf,
+        mode: LibraryMode,
+        output: PathBuf,
+    },
+    /// Sync: the file from an earlier run is still there and stays as it is.
+    Keep { output: PathBuf },
+    /// Leave the track out of this run.
+    Skip { reason: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedTrack {
+    /// Zero-based position in the input list.
+    pub index: usize,
+    pub track: TrackInfo,
+    #[serde(flatten)]
+    pub action: PlannedAction,
+}
+
+fn is_instrumental(track: &TrackInfo) -> bool {
+    let title = track.title.to_lowercase();
+    title.contains("instrumental") || title.contains("karaoke")
+}
+
+/// Decide, without touching the network or writing audio, what to do with every track.
+/// Tracks in `kept` (by [`track_key`]) keep their files from an earlier run, the first
+/// occurrence of a track the first file and so on.
+/// Fails only if the file name template is invalid.
+pub fn plan_playlist(
+    tools: &Tools,
+    tracks: &[TrackInfo],
+    config: &AppConfig,
+    output_dir: &Path,
+    kept: &HashMap<String, Vec<PathBuf>>,
+) -> Result<Vec<PlannedTrack>> {
+    let naming = NameTemplate::parse(&config.file_name_template)?.with_profile(config.naming_profile);
+    let library = if config.library_dirs.is_empty() {
+        LocalLibrary::default()
+    } else {
+        LocalLibrary::index(&config.library_dirs)
+    };
+    let template = output_dir.join("yt2media_<timestamp>_<n>_%(title)s.%(ext)s");
+    // Original keeps whatever yt-dlp downloads, so the extension is added once the file exists
+    let ext = config.output_format.extension().unwrap_or("");
+
+    let mut first_seen: HashMap<String, usize> = HashMap::new();
+    let mut kept_used: HashMap<String, usize> = HashMap::new();
+    let mut taken = HashSet::new();
+    let mut plan = Vec::with_capacity(tracks.len());
+    for (index, track) in tracks.iter().enumerate() {
+        let key = match_key(track);
+        let action = if config.exclude_instrumentals && is_instrumental(track) {
+            PlannedAction::Skip { reason: "instrumental".into() }
+        } else if let Some(first) = first_seen.get(&key).filter(|_| config.skip_duplicates) {
+            PlannedAction::Skip { reason: format!("duplicate of track {}", first + 1) }
+        } else if let Some(output) = kept.get(&track_key(track)).and_then(|files| {
+            let used = kept_used.entry(track_key(track)).or_default();
+            *used += 1;
+            files.get(*used - 1)
+        }) {
+            PlannedAction::Keep { output: output.clone() }
+        } else if let Some(entry) = library.find(track) {
+            PlannedAction::Library {
+                source: entry.path.clone(),
+                mode: config.library_mode,
+                output: library_destination(&entry.path, track, index, &naming, config, output_dir, &mut taken),
+            }
+        } else {
+            let queries = search_queries(track).to_vec();
+            let commands = queries
+                .iter()
+                .map(|q| display_command(tools.yt_dlp, &yt_dlp_args(tools.ffmpeg, q, &template, config)))
+                .collect();
+            let output = output_path(&naming, track, index, ext, output_dir, &mut taken);
+            PlannedAction::Download { queries, commands, output }
+        };
+        if !matches!(action, PlannedAction::Skip { .. }) {
+            first_seen.entry(key).or_insert(index);
+        }
+        plan.push(PlannedTrack { index, track: track.clone(), action });
+    }
+    Ok(plan)
+}
+
+/// Write `plan.json` and `plan.csv` into `output_dir` and return the JSON path.
+pub fn write_plan(plan: &[PlannedTrack], output_dir: &Path) -> Result<PathBuf> {
+    let json_path = output_dir.join("plan.json");
+    let json = serde_json::to_string_pretty(plan)?;
+    fs::write(&json_path, json).with_context(|| format!("Failed to write {:?}", json_path))?;
+
+    let csv_path = output_dir.join("plan.csv");
+    let mut wtr = csv::Writer::from_path(&csv_path).with_context(|| format!("Failed to write {:?}", csv_path))?;
+    wtr.write_record(["index", "title", "artist", "album", "action", "detail", "output"])?;
+    for p in plan {
+        let (action, detail, output) = match &p.action {
+            PlannedAction::Download { queries, output, .. } => {
+                ("download", queries.join(" | "), output.display().to_string())
+            }
+            PlannedAction::Library { source, mode, output } => (
+                "library",
+                format!("{} ({})", source.display(), mode.label()),
+                output.display().to_string(),
+            ),
+            PlannedAction::Keep { output } => ("keep", "unchanged since the last run".into(), output.display().to_string()),
+            PlannedAction::Skip { reason } => ("skip", reason.clone(), String::new()),
+        };
+        let index = (p.index + 1).to_string();
+        wtr.write_record([index.as_str(), &p.track.title, &p.track.artist, &p.track.album, action, &detail, &output])?;
+    }
+    wtr.flush()?;
+    Ok(json_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputFormat;
+    use crate::subprocess::SystemRunner;
+    use crate::test_support::track;
+
+    fn plan(tracks: &[TrackInfo], config: &AppConfig) -> Vec<PlannedTrack> {
+        let runner = SystemRunner::default();
+        let tools = Tools::new(&runner, None, None);
+        plan_playlist(&tools, tracks, config, Path::new("out"), &HashMap::new()).unwrap()
+    }
+
+    fn skipped(plan: &[PlannedTrack]) -> Vec<(usize, &str)> {
+        plan.iter()
+            .filter_map(|p| match &p.action {
+                PlannedAction::Skip { reason } => Some((p.index, reason.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_every_version_of_a_song() {
+        let tracks = [
+            track("Song", "Artist", ""),
+            track("Song (Live)", "Artist", ""),
+            track("Song - Remix", "Artist", ""),
+            track("Song (Acoustic)", "Artist", ""),
+            track("Song", "Someone Else", ""),
+        ];
+
+        let plan = plan(&tracks, &AppConfig::default());
+
+        assert!(skipped(&plan).is_empty());
+        assert!(plan.iter().all(|p| matches!(p.action, PlannedAction::Download { .. })));
+    }
+
+    #[test]
+    fn skips_repeats_of_the_same_song() {
+        let tracks = [track("Song (Live)", "Artist", ""), track("song  (live)", "artist", ""), track("Song", "Artist", "")];
+
+        assert_eq!(skipped(&plan(&tracks, &AppConfig::default())), [(1, "duplicate of track 1")]);
+        let config = AppConfig { skip_duplicates: false, ..AppConfig::default() };
+        assert!(skipped(&plan(&tracks, &config)).is_empty());
+    }
+
+    #[test]
+    fn original_format_avoids_files_with_any_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Artist - Song.m4a"), "audio").unwrap();
+        let config = AppConfig { output_format: OutputFormat::Original, skip_duplicates: false, ..AppConfig::default() };
+        let runner = SystemRunner::default();
+        let tools = Tools::new(&runner, None, None);
+        let tracks = [track("Song", "Artist", ""), track("Song", "Artist", "")];
+
+        let plan = plan_playlist(&tools, &tracks, &config, dir.path(), &HashMap::new()).unwrap();
+
+        let outputs: Vec<_> = plan
+            .iter()
+            .map(|p| match &p.action {
+                PlannedAction::Download { output, .. } => output.clone(),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(outputs, [dir.path().join("Artist - Song (1)"), dir.path().join("Artist - Song (2)")]);
+    }
+}
@@ -1,54 +1,427 @@
-use crate::config::AppConfig;
-use crate::csvparse::TrackInfo;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-
-/// Wrapper for playlist conversion with improved error handling and optional logging.
-///
-/// # Arguments
-/// * `tracks` - List of tracks to process.
-/// * `config` - Application configuration.
-/// * `yt_dlp_path` - Path to yt-dlp executable.
-/// * `ffmpeg_path` - Path to ffmpeg executable.
-/// * `output_dir` - Directory to save output files.
-/// * `progress_callback` - Callback for progress updates.
-/// * `log` - Optional log for GUI or CLI output.
-///
-/// # Returns
-/// * `Ok(())` on success, or `Err(String)` with a detailed error message.
-pub fn convert_playlist(
-    tracks: &[TrackInfo],
-    config: &AppConfig,
-    yt_dlp_path: &Path,
-    ffmpeg_path: &Path,
-    output_dir: &Path,
-    progress_callback: impl Fn(usize, usize, &str),
-    log: Option<&Arc<Mutex<Vec<String>>>>,
-) -> Result<(), String> {
-    match crate::spotify2media::convert_playlist(
-        tracks,
-        config,
-        Some(yt_dlp_path),
-        Some(ffmpeg_path),
-        output_dir,
-        |i, total, track| {
-            progress_callback(i, total, track);
-            if let Some(log) = log {
-                log.lock().unwrap().push(format!("Progress: {}/{} - {}", i + 1, total, track));
-            }
-        },
-    ) {
-        Ok(_) => {
-            if let Some(log) = log {
-                log.lock().unwrap().push("Playlist conversion finished successfully.".to_string());
-            }
-            Ok(())
-        }
-        Err(e) => {
-            if let Some(log) = log {
-                log.lock().unwrap().push(format!("Error during playlist conversion: {e}"));
-            }
-            Err(format!("Playlist conversion failed: {e}"))
-        }
-    }
+use crate::config::{AppConfig, NamingProfile, PlaylistFormat, PlaylistPaths};
+use crate::csvparse::TrackInfo;
+use crate::djexport;
+use crate::events::ConversionEvent;
+use crate::naming::sanitize_component;
+use crate::spotify2media::{ConversionReport, Tools, TrackResult, TrackSource};
+use crate::subprocess::SystemRunner;
+use crate::verify::MediaInfo;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+
+/// Wrapper for playlist conversion with improved error handling.
+///
+/// # Arguments
+/// * `tracks` - List of tracks to process.
+/// * `config` - Application configuration.
+/// * `yt_dlp_path` - Path to yt-dlp executable.
+/// * `ffmpeg_path` - Path to ffmpeg executable.
+/// * `output_dir` - Directory to save output files.
+/// * `events` - Receives every [`ConversionEvent`]; a fatal error is sent as `Failed`.
+/// * `cancel` - Set to stop the run before the next track.
+///
+/// # Returns
+/// * `Ok(ConversionReport)` on success, or `Err(String)` with a detailed error message.
+pub fn convert_playlist(
+    tracks: &[TrackInfo],
+    config: &AppConfig,
+    yt_dlp_path: &Path,
+    ffmpeg_path: &Path,
+    output_dir: &Path,
+    events: &Sender<ConversionEvent>,
+    cancel: &AtomicBool,
+) -> Result<ConversionReport, String> {
+    let runner = SystemRunner::from_config(config);
+    crate::spotify2media::convert_playlist(
+        &Tools::new(&runner, Some(yt_dlp_path), Some(ffmpeg_path)),
+        tracks,
+        config,
+        output_dir,
+        events,
+        cancel,
+    )
+    .map_err(|e| {
+        let _ = events.send(ConversionEvent::Failed { error: format!("{e:#}") });
+        format!("Playlist conversion failed: {e}")
+    })
+}
+
+/// A converted track as a playlist refers to it.
+pub struct Entry<'a> {
+    pub result: &'a TrackResult,
+    /// Path written into the playlist (relative or absolute per [`PlaylistPaths`]).
+    pub location: PathBuf,
+    /// Absolute path, for formats that only accept those.
+    pub absolute: PathBuf,
+    /// Length from the CSV, or read from the file if the CSV had none.
+    pub duration_ms: Option<u64>,
+    /// Bitrate and sample rate as read from the file, if it could be read.
+    pub media: Option<MediaInfo>,
+}
+
+impl Entry<'_> {
+    pub fn track(&self) -> &TrackInfo {
+        &self.result.track
+    }
+
+    /// `Artist - Title`, as shown by players that have no separate fields.
+    pub fn display_title(&self, profile: NamingProfile) -> String {
+        profile.text(&format!("{} - {}", self.track().artist, self.track().title))
+    }
+
+    /// Whole seconds, or -1 if unknown, as M3U and PLS expect.
+    pub fn duration_secs(&self) -> i64 {
+        self.duration_ms.map(|ms| (ms / 1000) as i64).unwrap_or(-1)
+    }
+}
+
+/// Entries for a playlist written to `path`.
+pub fn entries<'a>(path: &Path, results: &'a [TrackResult], paths: PlaylistPaths) -> Vec<Entry<'a>> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    results
+        .iter()
+        .map(|r| {
+            let absolute = std::path::absolute(&r.output).unwrap_or_else(|_| r.output.clone());
+            let location = match (paths, r.output.strip_prefix(base)) {
+                (PlaylistPaths::Relative, Ok(relative)) => relative.to_path_buf(),
+                _ => absolute.clone(),
+            };
+            let duration_ms = r.track.duration_ms.or_else(|| r.media.as_ref().map(|i| i.duration.as_millis() as u64));
+            Entry { result: r, location, absolute, duration_ms, media: r.media.clone() }
+        })
+        .collect()
+}
+
+/// Write every playlist format selected in `config` into `dir`, named `name.<ext>`,
+/// and return the files written.
+pub fn write_playlists(dir: &Path, name: &str, results: &[TrackResult], config: &AppConfig) -> Result<Vec<PathBuf>> {
+    let profile = config.naming_profile;
+    let title = profile.text(name);
+    let file_name = sanitize_component(name, profile);
+    let mut written = Vec::new();
+    for &format in &config.playlist_formats {
+        let path = dir.join(format!("{file_name}.{}", format.extension()));
+        let entries = entries(&path, results, config.playlist_paths);
+        let text = match format {
+            PlaylistFormat::M3u | PlaylistFormat::M3u8 => m3u(&entries, profile),
+            PlaylistFormat::Pls => pls(&entries, profile),
+            PlaylistFormat::Xspf => xspf(&title, &entries),
+            PlaylistFormat::Wpl => wpl(&title, &entries),
+            PlaylistFormat::ItunesXml => itunes_xml(&title, dir, &entries),
+            PlaylistFormat::Rekordbox => djexport::rekordbox_xml(&title, &entries),
+            PlaylistFormat::Traktor => djexport::traktor_nml(&title, &entries),
+        };
+        let bytes = match format {
+            PlaylistFormat::M3u => system_codepage(&text),
+            _ => text.into_bytes(),
+        };
+        fs::write(&path, bytes).with_context(|| format!("Failed to write playlist {:?}", path))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// `text` as legacy `.m3u` readers expect it: in the ANSI codepage on Windows, where
+/// characters it lacks become `?`, and as UTF-8 elsewhere, which those systems use anyway.
+#[cfg(windows)]
+fn system_codepage(text: &str) -> Vec<u8> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn WideCharToMultiByte(
+            code_page: u32,
+            flags: u32,
+            wide: *const u16,
+            wide_len: i32,
+            out: *mut u8,
+            out_len: i32,
+            default_char: *const u8,
+            used_default: *mut i32,
+        ) -> i32;
+    }
+    const CP_ACP: u32 = 0;
+    let wide: Vec<u16> = text.encode_utf16().collect();
+    if wide.is_empty() {
+        return Vec::new();
+    }
+    let len = wide.len() as i32;
+    // SAFETY: `wide` holds `len` units and `out` the size the first call asked for
+    unsafe {
+        let size = WideCharToMultiByte(CP_ACP, 0, wide.as_ptr(), len, std::ptr::null_mut(), 0, std::ptr::null(), std::ptr::null_mut());
+        let mut out = vec![0u8; size.max(0) as usize];
+        let written = WideCharToMultiByte(CP_ACP, 0, wide.as_ptr(), len, out.as_mut_ptr(), size, std::ptr::null(), std::ptr::null_mut());
+        out.truncate(written.max(0) as usize);
+        out
+    }
+}
+
+#[cfg(not(windows))]
+fn system_codepage(text: &str) -> Vec<u8> {
+    text.as_bytes().to_vec()
+}
+
+/// Extended M3U; titles are written as `profile` allows (plain ASCII for [`NamingProfile::Ascii`]).
+fn m3u(entries: &[Entry], profile: NamingProfile) -> String {
+    let mut txt = String::from("#EXTM3U\n");
+    for e in entries {
+        txt.push_str(&format!("#EXTINF:{},{}\n", e.duration_secs(), e.display_title(profile)));
+        txt.push_str(&format!("{}\n", e.location.display()));
+    }
+    txt
+}
+
+fn pls(entries: &[Entry], profile: NamingProfile) -> String {
+    let mut txt = String::from("[playlist]\n");
+    for (n, e) in entries.iter().enumerate() {
+        let n = n + 1;
+        txt.push_str(&format!("File{n}={}\n", e.location.display()));
+        txt.push_str(&format!("Title{n}={}\n", e.display_title(profile)));
+        txt.push_str(&format!("Length{n}={}\n", e.duration_secs()));
+    }
+    txt.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    txt
+}
+
+fn xspf(title: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    xml.push_str(&format!("  <title>{}</title>\n  <trackList>\n", xml_escape(title)));
+    for e in entries {
+        let t = e.track();
+        // Relative locations are URI references resolved against the playlist's own location
+        let location = match e.location.is_absolute() {
+            true => file_url(&e.location),
+            false => url_encode_path(&e.location),
+        };
+        xml.push_str("    <track>\n");
+        let mut field = |name: &str, value: &str| {
+            if !value.is_empty() {
+                xml.push_str(&format!("      <{name}>{}</{name}>\n", xml_escape(value)));
+            }
+        };
+        field("location", &location);
+        if !t.spotify_uri.is_empty() {
+            field("identifier", &t.spotify_uri);
+        }
+        if !t.isrc.is_empty() {
+            field("identifier", &format!("urn:isrc:{}", t.isrc));
+        }
+        field("title", &t.title);
+        field("creator", &t.artist);
+        field("album", &t.album);
+        field("trackNum", &t.track_number.map(|n| n.to_string()).unwrap_or_default());
+        field("duration", &e.duration_ms.map(|ms| ms.to_string()).unwrap_or_default());
+        if let TrackSource::YouTube { url: Some(url), .. } = &e.result.source {
+            field("info", url);
+        }
+        xml.push_str("    </track>\n");
+    }
+    xml.push_str("  </trackList>\n</playlist>\n");
+    xml
+}
+
+fn wpl(title: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from("<?wpl version=\"1.0\"?>\n<smil>\n  <head>\n");
+    xml.push_str("    <meta name=\"Generator\" content=\"spotify2media\"/>\n");
+    xml.push_str(&format!("    <meta name=\"ItemCount\" content=\"{}\"/>\n", entries.len()));
+    xml.push_str(&format!("    <title>{}</title>\n  </head>\n  <body>\n    <seq>\n", xml_escape(title)));
+    for e in entries {
+        // Windows Media Player expects Windows separators in relative paths
+        let src = match e.location.is_absolute() {
+            true => e.location.display().to_string(),
+            false => e.location.display().to_string().replace('/', "\\"),
+        };
+        xml.push_str(&format!("      <media src=\"{}\"/>\n", xml_escape(&src)));
+    }
+    xml.push_str("    </seq>\n  </body>\n</smil>\n");
+    xml
+}
+
+/// iTunes library XML (a property list) holding the tracks and a single playlist.
+/// iTunes only understands absolute `file://localhost/` locations, so
+/// [`PlaylistPaths::Relative`] does not apply here.
+fn itunes_xml(title: &str, dir: &Path, entries: &[Entry]) -> String {
+    let string = |key: &str, value: &str| format!("\t\t\t<key>{key}</key><string>{}</string>\n", xml_escape(value));
+    let integer = |key: &str, value: u64| format!("\t\t\t<key>{key}</key><integer>{value}</integer>\n");
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple Computer//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \t<key>Major Version</key><integer>1</integer>\n\
+         \t<key>Minor Version</key><integer>1</integer>\n",
+    );
+    xml.push_str(&format!("\t<key>Application Version</key><string>spotify2media {}</string>\n", env!("CARGO_PKG_VERSION")));
+    let music_folder = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    xml.push_str(&format!("\t<key>Music Folder</key><string>{}/</string>\n", xml_escape(&itunes_url(&music_folder))));
+    xml.push_str("\t<key>Tracks</key>\n\t<dict>\n");
+    for (n, e) in entries.iter().enumerate() {
+        let t = e.track();
+        let id = n as u64 + 1;
+        xml.push_str(&format!("\t\t<key>{id}</key>\n\t\t<dict>\n"));
+        xml.push_str(&integer("Track ID", id));
+        xml.push_str(&string("Name", &t.title));
+        for (key, value) in [("Artist", &t.artist), ("Album Artist", &t.album_artist), ("Album", &t.album)] {
+            if !value.is_empty() {
+                xml.push_str(&string(key, value));
+            }
+        }
+        if let Some(genre) = t.genres.first() {
+            xml.push_str(&string("Genre", genre));
+        }
+        let numbers = [
+            ("Disc Number", t.disc_number),
+            ("Disc Count", t.disc_total),
+            ("Track Number", t.track_number),
+            ("Track Count", t.track_total),
+            ("Year", t.release_date.get(..4).and_then(|y| y.parse().ok())),
+        ];
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                xml.push_str(&integer(key, value.into()));
+            }
+        }
+        if let Some(ms) = e.duration_ms {
+            xml.push_str(&integer("Total Time", ms));
+        }
+        xml.push_str(&string("Location", &itunes_url(&e.absolute)));
+        xml.push_str("\t\t</dict>\n");
+    }
+    xml.push_str("\t</dict>\n\t<key>Playlists</key>\n\t<array>\n\t\t<dict>\n");
+    xml.push_str(&format!("\t\t\t<key>Name</key><string>{}</string>\n", xml_escape(title)));
+    xml.push_str("\t\t\t<key>Playlist ID</key><integer>1</integer>\n\t\t\t<key>All Items</key><true/>\n");
+    xml.push_str("\t\t\t<key>Playlist Items</key>\n\t\t\t<array>\n");
+    for id in 1..=entries.len() {
+        xml.push_str(&format!("\t\t\t\t<dict><key>Track ID</key><integer>{id}</integer></dict>\n"));
+    }
+    xml.push_str("\t\t\t</array>\n\t\t</dict>\n\t</array>\n</dict>\n</plist>\n");
+    xml
+}
+
+/// Escape text for XML element content and attribute values.
+pub fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode a path for use in a URL, with `/` as the separator on every platform.
+pub fn url_encode_path(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let mut out = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(b as char),
+            b => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// `file:///` URL for an absolute path (`file:///C:/...` on Windows).
+pub fn file_url(path: &Path) -> String {
+    let encoded = url_encode_path(path).replacen("%3A", ":", 1);
+    match encoded.starts_with('/') {
+        true => format!("file://{encoded}"),
+        false => format!("file:///{encoded}"),
+    }
+}
+
+/// The `file://localhost/...` form iTunes and Rekordbox write.
+pub fn itunes_url(path: &Path) -> String {
+    file_url(path).replacen("file://", "file://localhost", 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify2media::TrackSource;
+    use crate::test_support::{track, write_mp3};
+
+    fn result(dir: &Path, title: &str, artist: &str) -> TrackResult {
+        let output = dir.join(format!("{artist} - {title}.mp3"));
+        write_mp3(&output);
+        let mut track = track(title, artist, "Album & Co");
+        track.duration_ms = Some(215_000);
+        track.track_number = Some(2);
+        track.isrc = "USRC17607839".into();
+        TrackResult {
+            index: 0,
+            track,
+            source: TrackSource::YouTube { query: title.into(), url: Some("https://www.youtube.com/watch?v=abc".into()), title: None },
+            media: crate::verify::probe(&output).ok(),
+            output,
+            suspect: None,
+            loudness: None,
+            cuts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn writes_every_selected_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = [result(dir.path(), "Café <Live>", "Artist"), result(dir.path(), "Other", "Band")];
+        let config = AppConfig { playlist_formats: PlaylistFormat::ALL.to_vec(), ..AppConfig::default() };
+
+        let written = write_playlists(dir.path(), "Road Trip", &results, &config).unwrap();
+
+        let read = |ext: &str| fs::read_to_string(dir.path().join(format!("Road Trip.{ext}"))).unwrap();
+        assert_eq!(written.len(), PlaylistFormat::ALL.len());
+        assert_eq!(read("m3u"), read("m3u8"));
+        assert!(read("m3u8").contains("#EXTINF:215,Artist - Café <Live>\nArtist - Café <Live>.mp3\n"));
+        let pls = read("pls");
+        assert!(pls.contains("File2=Band - Other.mp3\nTitle2=Band - Other\nLength2=215\n"));
+        assert!(pls.ends_with("NumberOfEntries=2\nVersion=2\n"));
+        let xspf = read("xspf");
+        assert!(xspf.contains("<location>Artist%20-%20Caf%C3%A9%20%3CLive%3E.mp3</location>"));
+        assert!(xspf.contains("<title>Café &lt;Live&gt;</title>"));
+        assert!(xspf.contains("<album>Album &amp; Co</album>") && xspf.contains("<duration>215000</duration>"));
+        assert!(xspf.contains("<identifier>urn:isrc:USRC17607839</identifier>"));
+        assert!(read("wpl").contains("<media src=\"Band - Other.mp3\"/>"));
+        let itunes = read("itunes.xml");
+        let location = file_url(&dir.path().join("Band - Other.mp3")).replacen("file://", "file://localhost", 1);
+        assert!(itunes.contains(&format!("<key>Location</key><string>{location}</string>")));
+        assert!(itunes.contains("<key>Total Time</key><integer>215000</integer>"));
+        assert!(itunes.contains("<dict><key>Track ID</key><integer>2</integer></dict>"));
+        let rekordbox = read("rekordbox.xml");
+        assert!(rekordbox.contains("<COLLECTION Entries=\"2\">"));
+        assert!(rekordbox.contains(" Name=\"Café &lt;Live&gt;\" Artist=\"Artist\" Album=\"Album &amp; Co\""));
+        assert!(rekordbox.contains(&format!(" Location=\"{location}\"/>")));
+        assert!(rekordbox.contains("<NODE Name=\"Road Trip\" Type=\"1\" KeyType=\"0\" Entries=\"2\">\n        <TRACK Key=\"1\"/>"));
+        let nml = read("nml");
+        assert!(nml.contains("<COLLECTION ENTRIES=\"2\">"));
+        assert!(nml.contains("FILE=\"Band - Other.mp3\""));
+        assert!(nml.contains("<INFO PLAYTIME=\"215\" PLAYTIME_FLOAT=\"215.000000\" BITRATE=\"128000\""));
+        assert!(nml.contains("<NODE TYPE=\"PLAYLIST\" NAME=\"Road Trip\">\n<PLAYLIST ENTRIES=\"2\" TYPE=\"LIST\""));
+        assert!(nml.contains("<ENTRY><PRIMARYKEY TYPE=\"TRACK\" KEY=\""));
+        assert!(nml.contains("/:Band - Other.mp3\"></PRIMARYKEY></ENTRY>"));
+    }
+
+    #[test]
+    fn absolute_paths_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = [result(dir.path(), "Song", "Artist")];
+        let config = AppConfig {
+            playlist_formats: vec![PlaylistFormat::M3u8, PlaylistFormat::Xspf],
+            playlist_paths: PlaylistPaths::Absolute,
+            ..AppConfig::default()
+        };
+
+        write_playlists(dir.path(), "Mix", &results, &config).unwrap();
+
+        let m3u = fs::read_to_string(dir.path().join("Mix.m3u8")).unwrap();
+        assert!(m3u.contains(&format!("\n{}\n", results[0].output.display())));
+        let xspf = fs::read_to_string(dir.path().join("Mix.xspf")).unwrap();
+        assert!(xspf.contains(&format!("<location>{}</location>", file_url(&results[0].output))));
+        assert!(file_url(Path::new("/music/a b.mp3")) == "file:///music/a%20b.mp3");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,142 @@
+/// Prefix of the lines produced by [`PROGRESS_TEMPLATE`], so they can't be confused with other output.
+const PROGRESS_PREFIX: &str = "[s2m-progress]";
+
+/// yt-dlp `--progress-template` producing one machine-readable line per update.
+/// Unknown values are printed by yt-dlp as `NA`.
+pub const PROGRESS_TEMPLATE: &str = "download:[s2m-progress] %(progress.downloaded_bytes)s \
+%(progress.total_bytes,progress.total_bytes_estimate)s %(progress.speed)s %(progress.eta)s";
+
+/// What the engine is doing for the current track.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrackPhase {
+    #[default]
+    Searching,
+    Downloading,
+    Converting,
+    /// Cutting silence.
+    Trimming,
+    /// Measuring or normalizing loudness.
+    Normalizing,
+    Tagging,
+    /// Copying or transcoding to a portable device.
+    Copying,
+}
+
+impl TrackPhase {
+    pub fn label(self) -> &'static str {
+        match self {
+            TrackPhase::Searching => "Searching",
+            TrackPhase::Downloading => "Downloading",
+            TrackPhase::Converting => "Converting",
+            TrackPhase::Trimming => "Trimming",
+            TrackPhase::Normalizing => "Normalizing",
+            TrackPhase::Tagging => "Tagging",
+            TrackPhase::Copying => "Copying to device",
+        }
+    }
+}
+
+/// Progress of the track currently being processed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackProgress {
+    /// Zero-based index of the track in the input list.
+    pub index: usize,
+    pub total: usize,
+    pub title: String,
+    pub phase: TrackPhase,
+    pub downloaded_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    /// Download progress of this track, 0-100.
+    pub percent: Option<f32>,
+    /// Bytes per second.
+    pub speed: Option<f64>,
+    /// Estimated seconds until the download finishes.
+    pub eta: Option<u64>,
+    /// yt-dlp runs started for this track so far, including retries.
+    pub attempt: u32,
+}
+
+impl TrackProgress {
+    pub fn new(index: usize, total: usize, title: &str) -> Self {
+        Self { index, total, title: title.to_string(), ..Self::default() }
+    }
+
+    /// Apply one line of yt-dlp output; returns `true` if anything changed.
+    pub fn update_from_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix(PROGRESS_PREFIX) {
+            let mut fields = rest.split_whitespace().map(|f| f.parse::<f64>().ok());
+            let mut next = || fields.next().flatten();
+            let (downloaded, total, speed, eta) = (next(), next(), next(), next());
+            self.phase = TrackPhase::Downloading;
+            self.downloaded_bytes = downloaded.map(|b| b as u64);
+            self.total_bytes = total.map(|b| b as u64);
+            self.percent = match (downloaded, total) {
+                (Some(d), Some(t)) if t > 0.0 => Some((d / t * 100.0).clamp(0.0, 100.0) as f32),
+                _ => None,
+            };
+            self.speed = speed;
+            self.eta = eta.map(|e| e as u64);
+            return true;
+        }
+        let phase = if line.starts_with("[download] Destination:") {
+            TrackPhase::Downloading
+        } else if line.starts_with("[ExtractAudio]") {
+            TrackPhase::Converting
+        } else {
+            return false;
+        };
+        let changed = self.phase != phase;
+        self.phase = phase;
+        changed
+    }
+
+    /// Short human-readable description, e.g. `Downloading 42% (1.2 MiB/s, ETA 0:31)`.
+    pub fn describe(&self) -> String {
+        let mut text = self.phase.label().to_string();
+        if self.phase == TrackPhase::Downloading {
+            if let Some(p) = self.percent {
+                text.push_str(&format!(" {p:.0}%"));
+            }
+            let mut details = Vec::new();
+            if let Some(speed) = self.speed {
+                details.push(format!("{:.1} MiB/s", speed / (1024.0 * 1024.0)));
+            }
+            if let Some(eta) = self.eta {
+                details.push(format!("ETA {}:{:02}", eta / 60, eta % 60));
+            }
+            if !details.is_empty() {
+                text.push_str(&format!(" ({})", details.join(", ")));
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_template_lines() {
+        let mut p = TrackProgress::new(0, 1, "Song");
+        assert!(p.update_from_line("[s2m-progress] 1048576 4194304 2097152.5 2"));
+        assert_eq!(p.phase, TrackPhase::Downloading);
+        assert_eq!(p.downloaded_bytes, Some(1_048_576));
+        assert_eq!(p.total_bytes, Some(4_194_304));
+        assert_eq!(p.percent, Some(25.0));
+        assert_eq!(p.eta, Some(2));
+        assert_eq!(p.describe(), "Downloading 25% (2.0 MiB/s, ETA 0:02)");
+    }
+
+    #[test]
+    fn unknown_values_and_phase_lines() {
+        let mut p = TrackProgress::new(0, 1, "Song");
+        assert!(p.update_from_line("[s2m-progress] 1000 NA NA NA"));
+        assert_eq!(p.percent, None);
+        assert_eq!(p.describe(), "Downloading");
+        assert!(p.update_from_line("[ExtractAudio] Destination: song.mp3"));
+        assert_eq!(p.phase, TrackPhase::Converting);
+        assert!(!p.update_from_line("[youtube] Extracting URL"));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::csvparse::TrackInfo;
+use crate::failure::FailureKind;
+use crate::plan::PlannedAction;
+use crate::playlist::xml_escape;
+use crate::spotify2media::{search_queries, ConversionReport, TrackFailure, TrackResult, TrackSource};
+use crate::trim::Cut;
+use crate::verify::format_duration;
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Files written into the output folder after every run.
+pub const REPORT_JSON: &str = "report.json";
+pub const REPORT_HTML: &str = "report.html";
+/// Failed and skipped tracks, with Exportify column names so the file can be used as input.
+pub const RETRY_CSV: &str = "failed.csv";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Converted,
+    /// Sync kept the file of an earlier run.
+    Unchanged,
+    Failed,
+    Skipped,
+    /// The run was cancelled before the track's turn.
+    NotProcessed,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Converted => "converted",
+            Status::Unchanged => "unchanged",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+            Status::NotProcessed => "not processed",
+        }
+    }
+}
+
+/// One input track in the report.
+#[derive(Debug, Serialize)]
+pub struct ReportEntry<'a> {
+    /// 1-based position in the playlist.
+    pub position: usize,
+    pub status: Status,
+    pub track: &'a TrackInfo,
+    /// `youtube`, `library` or `previous`.
+    pub source: Option<&'static str>,
+    pub query: Option<&'a str>,
+    pub video_title: Option<&'a str>,
+    pub video_url: Option<&'a str>,
+    pub library_file: Option<&'a Path>,
+    pub output: Option<&'a Path>,
+    pub duration_secs: Option<f64>,
+    pub expected_secs: Option<f64>,
+    /// 0-100; see [`confidence`].
+    pub confidence: Option<u8>,
+    pub suspect: Option<&'a str>,
+    pub cuts: Vec<String>,
+    pub failure: Option<FailureKind>,
+    pub attempts: Option<u32>,
+    /// Why the track was skipped or failed.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RunReport<'a> {
+    run_id: &'a str,
+    generated: String,
+    output_dir: &'a Path,
+    summary: String,
+    cancelled: bool,
+    /// Steps after the tracks that failed, such as the device export.
+    problems: &'a [String],
+    tracks: Vec<ReportEntry<'a>>,
+}
+
+/// Rough 0-100 score of how likely the file is the right recording: the search query
+/// variant that found it, how far its length is from Spotify's (after adding back
+/// what was cut), and whether verification had doubts. Library files score 100;
+/// files kept by sync have no score.
+pub fn confidence(result: &TrackResult, duration: Option<Duration>) -> Option<u8> {
+    let mut score = match &result.source {
+        TrackSource::Library(_) => return Some(100),
+        TrackSource::Previous => return None,
+        TrackSource::YouTube { query, .. } => match search_queries(&result.track).iter().position(|q| q == query) {
+            Some(0) => 95.0,
+            Some(1) => 85.0,
+            _ => 70.0,
+        },
+    };
+    if let (Some(expected), Some(actual)) = (result.track.duration_ms, duration) {
+        let cut: f64 = result.cuts.iter().map(Cut::length).sum();
+        let off = (actual.as_secs_f64() + cut - expected as f64 / 1000.0).abs();
+        score -= (off * 2.0).min(50.0);
+    }
+    if result.suspect.is_some() {
+        score = f64::min(score, 40.0);
+    }
+    Some(score.round().clamp(0.0, 100.0) as u8)
+}
+
+/// One entry per planned track, in playlist order.
+pub fn entries(report: &ConversionReport) -> Vec<ReportEntry<'_>> {
+    let results: HashMap<usize, &TrackResult> = report.results.iter().map(|r| (r.index, r)).collect();
+    let failures: HashMap<usize, &TrackFailure> = report.failures.iter().map(|f| (f.index, f)).collect();
+    report
+        .plan
+        .iter()
+        .map(|planned| {
+            let mut entry = ReportEntry {
+                position: planned.index + 1,
+                status: Status::NotProcessed,
+                track: &planned.track,
+                source: None,
+                query: None,
+                video_title: None,
+                video_url: None,
+                library_file: None,
+                output: None,
+                duration_secs: None,
+                expected_secs: planned.track.duration_ms.map(|ms| ms as f64 / 1000.0),
+                confidence: None,
+                suspect: None,
+                cuts: Vec::new(),
+                failure: None,
+                attempts: None,
+                reason: None,
+            };
+            if let Some(r) = results.get(&planned.index) {
+                let duration = r.media.as_ref().map(|info| info.duration);
+                entry.status = Status::Converted;
+                match &r.source {
+                    TrackSource::YouTube { query, url, title } => {
+                        entry.source = Some("youtube");
+                        entry.query = Some(query);
+                        entry.video_url = url.as_deref();
+                        entry.video_title = title.as_deref();
+                    }
+                    TrackSource::Library(file) => {
+                        entry.source = Some("library");
+                        entry.library_file = Some(file);
+                    }
+                    TrackSource::Previous => {
+                        entry.source = Some("previous");
+                        entry.status = Status::Unchanged;
+                    }
+                }
+                entry.output = Some(&r.output);
+                entry.duration_secs = duration.map(|d| d.as_secs_f64());
+                entry.confidence = confidence(r, duration);
+                entry.suspect = r.suspect.as_deref();
+                entry.cuts = r.cuts.iter().map(Cut::to_string).collect();
+            } else if let Some(f) = failures.get(&planned.index) {
+                entry.status = Status::Failed;
+                entry.failure = Some(f.kind);
+                entry.attempts = Some(f.attempts);
+                entry.reason = Some(f.message.clone());
+            } else if let PlannedAction::Skip { reason } = &planned.action {
+                entry.status = Status::Skipped;
+                entry.reason = Some(reason.clone());
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Exportify-style CSV of the tracks that still need attention; `Status` and `Reason`
+/// are extra columns the CSV reader ignores.
+fn write_retry_csv(path: &Path, entries: &[ReportEntry]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path).with_context(|| format!("Failed to write {:?}", path))?;
+    wtr.write_record([
+        "Track URI",
+        "Track Name",
+        "Artist Name(s)",
+        "Album Name",
+        "Album Artist Name(s)",
+        "Album Release Date",
+        "Disc Number",
+        "Track Number",
+        "Track Duration (ms)",
+        "ISRC",
+        "Artist Genres",
+        "Status",
+        "Reason",
+    ])?;
+    let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+    for e in entries.iter().filter(|e| matches!(e.status, Status::Failed | Status::Skipped | Status::NotProcessed)) {
+        let t = e.track;
+        wtr.write_record([
+            t.spotify_uri.as_str(),
+            &t.title,
+            &t.artist,
+            &t.album,
+            &t.album_artist,
+            &t.release_date,
+            &number(t.disc_number),
+            &number(t.track_number),
+            &t.duration_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+            &t.isrc,
+            &t.genres.join(","),
+            e.status.label(),
+            e.reason.as_deref().unwrap_or(""),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;width:100%}th,td{border-bottom:1px solid #ddd;padding:4px 8px;text-align:left;vertical-align:top}\
+th{background:#f4f4f4}td.num{text-align:right;white-space:nowrap}small{color:#666}\
+.converted{color:#1a7f37}.unchanged{color:#57606a}.failed{color:#cf222e}.skipped,.not-processed{color:#9a6700}";
+
+fn html(report: &ConversionReport, entries: &[ReportEntry], title: &str) -> String {
+    let esc = xml_escape;
+    let secs = |s: Option<f64>| s.map(|s| format_duration(Duration::from_secs_f64(s))).unwrap_or_else(|| "-".into());
+    let mut rows = String::new();
+    for e in entries {
+        let t = e.track;
+        let source = match (e.video_url, e.video_title, e.library_file) {
+            (Some(url), title, _) => format!("<a href=\"{}\">{}</a>", esc(url), esc(title.unwrap_or(url))),
+            (None, Some(title), _) => esc(title),
+            (None, None, Some(file)) => format!("library: {}", esc(&file.display().to_string())),
+            _ => String::new(),
+        };
+        let mut notes: Vec<String> = e.reason.iter().cloned().collect();
+        notes.extend(e.suspect.map(|s| format!("suspect: {s}")));
+        notes.extend(e.cuts.iter().map(|c| format!("cut {c}")));
+        rows.push_str(&format!(
+            "<tr><td class=\"num\">{}</td><td>{}<br><small>{}{}</small></td><td class=\"{}\">{}</td><td>{}</td>\
+             <td class=\"num\">{} / {}</td><td class=\"num\">{}</td><td>{}</td></tr>\n",
+            e.position,
+            esc(&t.title),
+            esc(&t.artist),
+            if t.album.is_empty() { String::new() } else { format!(" &middot; {}", esc(&t.album)) },
+            e.status.label().replace(' ', "-"),
+            e.status.label(),
+            source,
+            secs(e.duration_secs),
+            secs(e.expected_secs),
+            e.confidence.map(|c| format!("{c}%")).unwrap_or_else(|| "-".into()),
+            esc(&notes.join("; ")),
+        ));
+    }
+    let problems: String = report.problems.iter().map(|p| format!("<li class=\"failed\">{}</li>", esc(p))).collect();
+    let problems = if problems.is_empty() { problems } else { format!("<ul>{problems}</ul>\n") };
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>{STYLE}</style></head><body>\n\
+         <h1>{title}</h1>\n<p>{summary}</p>\n{problems}<p><small>Generated {generated} by spotify2media {version}</small></p>\n\
+         <table><tr><th>#</th><th>Track</th><th>Status</th><th>Matched video</th><th>Length / expected</th>\
+         <th>Confidence</th><th>Notes</th></tr>\n{rows}</table>\n</body></html>\n",
+        title = esc(title),
+        summary = esc(&report.summary()),
+        generated = Local::now().format("%Y-%m-%d %H:%M"),
+        version = env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Write the JSON report, the retry CSV and the HTML summary into `output_dir` and
+/// return the path of the HTML summary.
+pub fn write_report(report: &ConversionReport, output_dir: &Path) -> Result<PathBuf> {
+    let entries = entries(report);
+    let title = output_dir.file_name().and_then(|n| n.to_str()).unwrap_or("playlist");
+
+    let json = RunReport {
+        run_id: &report.run_id,
+        generated: Utc::now().to_rfc3339(),
+        output_dir,
+        summary: report.summary(),
+        cancelled: report.cancelled,
+        problems: &report.problems,
+        tracks: entries,
+    };
+    let json_path = output_dir.join(REPORT_JSON);
+    fs::write(&json_path, serde_json::to_string_pretty(&json)?).with_context(|| format!("Failed to write {:?}", json_path))?;
+    write_retry_csv(&output_dir.join(RETRY_CSV), &json.tracks)?;
+    let html_path = output_dir.join(REPORT_HTML);
+    fs::write(&html_path, html(report, &json.tracks, title)).with_context(|| format!("Failed to write {:?}", html_path))?;
+    Ok(html_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::track;
+
+    #[test]
+    fn scores_query_variant_length_and_doubts() {
+        let mut song = track("Song", "Artist", "");
+        song.duration_ms = Some(200_000);
+        let queries = search_queries(&song);
+        let mut result = TrackResult {
+            index: 0,
+            track: song,
+            source: TrackSource::YouTube { query: queries[0].clone(), url: None, title: None },
+            output: PathBuf::new(),
+            suspect: None,
+            loudness: None,
+            cuts: Vec::new(),
+            media: None,
+        };
+        assert_eq!(confidence(&result, Some(Duration::from_secs(200))), Some(95));
+        assert_eq!(confidence(&result, Some(Duration::from_secs(190))), Some(75));
+        result.source = TrackSource::YouTube { query: queries[2].clone(), url: None, title: None };
+        assert_eq!(confidence(&result, None), Some(70));
+        result.suspect = Some("too short".into());
+        assert_eq!(confidence(&result, None), Some(40));
+        result.source = TrackSource::Previous;
+        assert_eq!(confidence(&result, None), None);
+    }
+}
@@ -1,161 +1,1657 @@
-use crate::config::AppConfig;
-use crate::audio::{set_mp3_tags, set_m4a_tags, is_valid_mp3};
-use crate::csvparse::TrackInfo;
-use std::path::{Path, PathBuf};
-use std::fs;
-use std::process::Command;
-use anyhow::{Result, Context};
-use chrono::Utc;
-use std::sync::{Arc, Mutex};
-
-/// Improved CSV parser (if you don't use the one from csvparse.rs, otherwise remove this)
-pub fn parse_csv(csv_path: &Path) -> Result<Vec<TrackInfo>> {
-    let mut rdr = csv::Reader::from_path(csv_path)?;
-    let mut tracks = Vec::new();
-    for result in rdr.records() {
-        let record = result?;
-        let title = record.get(0).unwrap_or("").trim().to_string();
-        let artist = record.get(1).unwrap_or("").trim().to_string();
-        let album = record.get(2).unwrap_or("").trim().to_string();
-        if title.is_empty() && artist.is_empty() {
-            continue;
-        }
-        tracks.push(TrackInfo { title, artist, album });
-    }
-    Ok(tracks)
-}
-
-/// Run yt-dlp and return the path to the downloaded audio file.
-/// Logs all output and errors to the provided log (if any).
-pub fn run_yt_dlp(
-    yt_dlp_path: Option<&Path>,
-    ffmpeg_path: Option<&Path>,
-    query: &str,
-    output_dir: &Path,
-    as_mp3: bool,
-    log: Option<&Arc<Mutex<Vec<String>>>>,
-) -> Result<PathBuf> {
-    let yt_dlp_path = yt_dlp_path.unwrap_or_else(|| Path::new("yt-dlp"));
-    let ffmpeg_path = ffmpeg_path.unwrap_or_else(|| Path::new("ffmpeg"));
-    let timestamp = Utc::now().timestamp_millis();
-    let output_template = output_dir.join(format!("yt2media_{}_%(title)s.%(ext)s", timestamp));
-    let output_template_str = output_template.to_string_lossy();
-
-    let mut cmd = Command::new(yt_dlp_path);
-    cmd.arg("-x")
-        .arg("--audio-format")
-        .arg(if as_mp3 { "mp3" } else { "m4a" })
-        .arg("--audio-quality")
-        .arg("0")
-        .arg("--ffmpeg-location")
-        .arg(ffmpeg_path)
-        .arg("-o")
-        .arg(&*output_template_str)
-        .arg(format!("ytsearch1:{}", query));
-
-    if let Some(log) = log {
-        log.lock().unwrap().push(format!("Running: {:?}", cmd));
-    }
-
-    let output = cmd.output().context("Failed to start yt-dlp")?;
-    if let Some(log) = log {
-        log.lock().unwrap().push(format!("yt-dlp stdout: {}", String::from_utf8_lossy(&output.stdout)));
-        log.lock().unwrap().push(format!("yt-dlp stderr: {}", String::from_utf8_lossy(&output.stderr)));
-    }
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("yt-dlp failed: {}", String::from_utf8_lossy(&output.stderr)));
-    }
-
-    // List all files in output_dir for debugging
-    if let Some(log) = log {
-        let files: Vec<_> = fs::read_dir(output_dir)?
-            .filter_map(|e| e.ok().map(|e| e.path()))
-            .collect();
-        log.lock().unwrap().push(format!("Files in output dir: {:?}", files));
-    }
-
-    // Find the downloaded file (accept any extension, prefer mp3/m4a)
-    let mut found: Option<PathBuf> = None;
-    let mut fallback: Option<PathBuf> = None;
-    for entry in fs::read_dir(output_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        let fname = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-        if fname.starts_with(&format!("yt2media_{}", timestamp)) {
-            match path.extension().and_then(|e| e.to_str()) {
-                Some("mp3") | Some("m4a") => {
-                    found = Some(path.clone());
-                    break;
-                }
-                Some(_) => {
-                    fallback = Some(path.clone());
-                }
-                None => {}
-            }
-        }
-    }
-    found.or(fallback).ok_or_else(|| anyhow::anyhow!("yt-dlp did not produce an output file"))
-}
-
-/// Main playlist conversion logic
-pub fn convert_playlist(
-    tracks: &[TrackInfo],
-    config: &AppConfig,
-    yt_dlp_path: Option<&Path>,
-    ffmpeg_path: Option<&Path>,
-    output_dir: &Path,
-    progress_cb: impl Fn(usize, usize, &str),
-) -> Result<()> {
-    fs::create_dir_all(output_dir)?;
-    for (i, track) in tracks.iter().enumerate() {
-        // Prefer official artist topic channel, fallback to plain search if needed
-        let queries = [
-            format!("{} {} topic", track.title, track.artist),
-            format!("{} {} official audio", track.title, track.artist),
-            format!("{} {}", track.title, track.artist),
-        ];
-        let mut last_err = None;
-        let mut out_file = None;
-        for query in &queries {
-            progress_cb(i, tracks.len(), &track.title);
-            match run_yt_dlp(
-                yt_dlp_path,
-                ffmpeg_path,
-                query,
-                output_dir,
-                config.transcode_mp3,
-                None,
-            ) {
-                Ok(path) => {
-                    out_file = Some(path);
-                    break;
-                }
-                Err(e) => {
-                    last_err = Some(e);
-                }
-            }
-        }
-        let out_file = out_file.ok_or_else(|| {
-            anyhow::anyhow!(
-                "yt-dlp failed for '{} - {}': {:?}",
-                track.title,
-                track.artist,
-                last_err
-            )
-        })?;
-
-        // Set tags if possible
-        if config.transcode_mp3 && out_file.extension().unwrap_or_default() == "mp3" {
-            if is_valid_mp3(&out_file) {
-                set_mp3_tags(&out_file, &track.title, &track.artist, &track.album)
-                    .with_context(|| format!("Failed to save tags to {:?}", out_file))?;
-            } else {
-                return Err(anyhow::anyhow!("Downloaded file is not a valid MP3: {:?}", out_file));
-            }
-        } else if out_file.extension().unwrap_or_default() == "m4a" {
-            set_m4a_tags(&out_file, &track.title, &track.artist, &track.album)
-                .with_context(|| format!("Failed to save tags to {:?}", out_file))?;
-        }
-    }
-    Ok(())
+use crate::config::{AppConfig, LibraryMode, LoudnessMode, OutputFormat};
+use crate::artwork::{apply_cover, is_image};
+use crate::audio::{set_custom_fields, write_tags};
+use crate::csvparse::TrackInfo;
+use crate::device::{check_device, export_to_device, DeviceReport};
+use crate::logging;
+use crate::loudness::{self, album_loudness, replaygain_fields, Loudness};
+use crate::playlist::write_playlists;
+use crate::trim::{self, sponsorblock_cuts, Cut, SponsorSegment, SPONSORBLOCK_CATEGORY};
+use crate::events::ConversionEvent;
+use crate::failure::{classify, FailureKind, YtDlpError};
+use crate::progress::{TrackPhase, TrackProgress, PROGRESS_TEMPLATE};
+use crate::report::write_report;
+use crate::mediaserver::{check_mapping, write_server_playlist};
+use crate::naming::{output_path, unique_path, NameTemplate};
+use crate::plan::{plan_playlist, write_plan, PlannedAction, PlannedTrack};
+use crate::subprocess::{CommandRunner, ToolNotStarted, ToolTimeout};
+use crate::sync::{remove_files, SyncState};
+use crate::verify::{probe, verify, MediaInfo, Verdict};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs;
+use anyhow::{Result, Context};
+use chrono::Utc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Extensions left behind by an interrupted or in-progress yt-dlp download.
+const PARTIAL_EXTENSIONS: &[&str] = &["part", "ytdl", "temp", "tmp"];
+
+/// Containers yt-dlp produces for the supported output formats.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "opus", "ogg", "flac", "webm", "aac"];
+
+/// Suffix of the metadata file written by `--write-info-json`.
+const INFO_JSON_SUFFIX: &str = ".info.json";
+
+static DOWNLOAD_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// External tools used by the engine and the runner that starts them.
+#[derive(Clone, Copy)]
+pub struct Tools<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub yt_dlp: &'a Path,
+    pub ffmpeg: &'a Path,
+}
+
+impl<'a> Tools<'a> {
+    /// Missing paths fall back to `yt-dlp`/`ffmpeg` on the `PATH`.
+    pub fn new(runner: &'a dyn CommandRunner, yt_dlp: Option<&'a Path>, ffmpeg: Option<&'a Path>) -> Self {
+        Self {
+            runner,
+            yt_dlp: yt_dlp.unwrap_or_else(|| Path::new("yt-dlp")),
+            ffmpeg: ffmpeg.unwrap_or_else(|| Path::new("ffmpeg")),
+        }
+    }
+}
+
+/// Search queries tried in order for a track.
+/// Prefer official artist topic channel, fallback to plain search if needed.
+pub fn search_queries(track: &TrackInfo) -> [String; 3] {
+    [
+        format!("{} {} topic", track.title, track.artist),
+        format!("{} {} official audio", track.title, track.artist),
+        format!("{} {}", track.title, track.artist),
+    ]
+}
+
+/// Build the yt-dlp argument list for one search query.
+pub fn yt_dlp_args(ffmpeg_path: &Path, query: &str, output_template: &Path, config: &AppConfig) -> Vec<OsString> {
+    // yt-dlp takes either a VBR level (0 = best) or a bitrate such as `192K`
+    let audio_quality = if config.output_format.has_bitrate() && config.bitrate_kbps > 0 {
+        format!("{}K", config.bitrate_kbps)
+    } else {
+        "0".to_string()
+    };
+    let mut args: Vec<OsString> = vec![
+        "-x".into(),
+        "--audio-format".into(),
+        config.output_format.yt_dlp_format().into(),
+        "--audio-quality".into(),
+        audio_quality.into(),
+        "--newline".into(),
+        "--write-info-json".into(),
+    ];
+    if config.cover_art.embed {
+        args.push("--write-thumbnail".into());
+    }
+    if config.trim.sponsorblock {
+        args.extend(["--sponsorblock-remove".into(), SPONSORBLOCK_CATEGORY.into()]);
+    }
+    args.extend([
+        "--progress-template".into(),
+        PROGRESS_TEMPLATE.into(),
+        "--ffmpeg-location".into(),
+        ffmpeg_path.into(),
+        "-o".into(),
+        output_template.into(),
+        format!("ytsearch1:{}", query).into(),
+    ]);
+    args
+}
+
+/// Audio file produced by yt-dlp and what it reported about the video.
+#[derive(Clone, Debug)]
+pub struct Downloaded {
+    pub path: PathBuf,
+    /// Page of the video the audio was taken from.
+    pub source_url: Option<String>,
+    pub video_title: Option<String>,
+    /// Video thumbnail written by `--write-thumbnail`.
+    pub thumbnail: Option<PathBuf>,
+    /// Why verification flagged the file, if it did.
+    pub suspect: Option<String>,
+    /// Sections yt-dlp already removed (SponsorBlock).
+    pub cuts: Vec<Cut>,
+}
+
+/// The fields of yt-dlp's `.info.json` the engine uses.
+#[derive(Deserialize)]
+struct VideoInfo {
+    title: Option<String>,
+    webpage_url: Option<String>,
+    /// Present when `--sponsorblock-remove` found segments.
+    #[serde(default)]
+    sponsorblock_chapters: Vec<SponsorSegment>,
+}
+
+/// Read and remove the `.info.json` yt-dlp wrote for `prefix`, if any.
+fn take_video_info(output_dir: &Path, prefix: &str) -> Option<VideoInfo> {
+    let path = fs::read_dir(output_dir).ok()?.filter_map(|e| e.ok().map(|e| e.path())).find(|p| {
+        p.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(prefix) && n.ends_with(INFO_JSON_SUFFIX))
+    })?;
+    let info = fs::read(&path).ok().and_then(|data| serde_json::from_slice(&data).ok());
+    let _ = fs::remove_file(&path);
+    info
+}
+
+/// Run yt-dlp and return the downloaded audio file.
+/// Each stdout line is passed to `on_line` as it arrives (for progress parsing).
+pub fn run_yt_dlp(
+    tools: &Tools,
+    query: &str,
+    output_dir: &Path,
+    config: &AppConfig,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<Downloaded> {
+    let timestamp = Utc::now().timestamp_millis();
+    // The counter keeps prefixes unique when several downloads start within the same millisecond.
+    let seq = DOWNLOAD_SEQ.fetch_add(1, Ordering::Relaxed);
+    let prefix = format!("yt2media_{}_{}_", timestamp, seq);
+    let output_template = output_dir.join(format!("{}%(title)s.%(ext)s", prefix));
+
+    let args = yt_dlp_args(tools.ffmpeg, query, &output_template, config);
+
+    let output = match tools.runner.run_streaming(tools.yt_dlp, &args, on_line) {
+        Ok(output) => output,
+        Err(e) => {
+            if let Some(timeout) = e.downcast_ref::<ToolTimeout>() {
+                return Err(YtDlpError {
+                    kind: FailureKind::TimedOut,
+                    message: format!("yt-dlp {timeout}; process killed"),
+                }
+                .into());
+            }
+            return Err(e.context("Failed to start yt-dlp"));
+        }
+    };
+    if !output.success {
+        return Err(YtDlpError {
+            kind: classify(&output.stderr),
+            message: format!("yt-dlp failed: {}", output.stderr.trim()),
+        }
+        .into());
+    }
+
+    let info = take_video_info(output_dir, &prefix);
+    let cuts = info.as_ref().map(|i| sponsorblock_cuts(&i.sponsorblock_chapters)).unwrap_or_default();
+    Ok(Downloaded {
+        path: find_downloaded_file(output_dir, &prefix)?,
+        video_title: info.as_ref().and_then(|i| i.title.clone()),
+        source_url: info.and_then(|i| i.webpage_url),
+        thumbnail: find_thumbnail(output_dir, &prefix),
+        suspect: None,
+        cuts,
+    })
+}
+
+/// Find the thumbnail yt-dlp wrote for `prefix`, if any.
+fn find_thumbnail(output_dir: &Path, prefix: &str) -> Option<PathBuf> {
+    fs::read_dir(output_dir).ok()?.filter_map(|e| e.ok().map(|e| e.path())).find(|p| {
+        is_image(p) && p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(prefix))
+    })
+}
+
+/// Find the file yt-dlp wrote for `prefix`: prefer known audio containers, accept
+/// any other finished file, and ignore sidecars and leftovers of an interrupted download.
+fn find_downloaded_file(output_dir: &Path, prefix: &str) -> Result<PathBuf> {
+    let mut found: Option<PathBuf> = None;
+    let mut fallback: Option<PathBuf> = None;
+    for entry in fs::read_dir(output_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let fname = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if fname.starts_with(prefix) {
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(ext) if AUDIO_EXTENSIONS.contains(&ext) => {
+                    found = Some(path.clone());
+                    break;
+                }
+                Some(ext) if PARTIAL_EXTENSIONS.contains(&ext) => {}
+                _ if fname.ends_with(INFO_JSON_SUFFIX) || is_image(&path) => {}
+                Some(_) => {
+                    fallback = Some(path.clone());
+                }
+                None => {}
+            }
+        }
+    }
+    found.or(fallback).ok_or_else(|| {
+        YtDlpError {
+            kind: FailureKind::NoOutput,
+            message: "yt-dlp did not produce an output file".into(),
+        }
+        .into()
+    })
+}
+
+/// Where the audio for a converted track came from.
+#[derive(Clone, Debug)]
+pub enum TrackSource {
+    /// Taken from the local library file at this path.
+    Library(PathBuf),
+    /// Downloaded by yt-dlp using this search query, from the video at `url`.
+    YouTube { query: String, url: Option<String>, title: Option<String> },
+    /// Sync: the file an earlier run wrote, left unchanged.
+    Previous,
+}
+
+/// Outcome of converting a single track.
+#[derive(Clone, Debug)]
+pub struct TrackResult {
+    /// Zero-based index of the track in the input list.
+    pub index: usize,
+    pub track: TrackInfo,
+    pub source: TrackSource,
+    pub output: PathBuf,
+    /// Verification passed with doubts (e.g. unexpected length); the reason is kept here.
+    pub suspect: Option<String>,
+    /// Measured for ReplayGain; album gain is written once the whole run is done.
+    pub loudness: Option<Loudness>,
+    /// Sections removed from the audio (silence, SponsorBlock segments).
+    pub cuts: Vec<Cut>,
+    /// Properties of the finished file, read once for every playlist and report that needs them.
+    pub media: Option<MediaInfo>,
+}
+
+/// A track that could not be converted.
+#[derive(Clone, Debug)]
+pub struct TrackFailure {
+    /// Zero-based index of the track in the input list.
+    pub index: usize,
+    pub track: TrackInfo,
+    pub kind: FailureKind,
+    pub message: String,
+    /// Number of yt-dlp runs made for this track, including retries.
+    pub attempts: u32,
+}
+
+/// Summary of a playlist conversion run.
+#[derive(Clone, Debug, Default)]
+pub struct ConversionReport {
+    /// What the engine decided to do with every input track.
+    pub plan: Vec<PlannedTrack>,
+    pub results: Vec<TrackResult>,
+    pub failures: Vec<TrackFailure>,
+    /// Set when `config.dry_run` was on: `plan` was written to disk and nothing was converted.
+    pub dry_run: bool,
+    /// Where the dry-run plan was written.
+    pub plan_file: Option<PathBuf>,
+    /// The run was stopped by the user before all tracks were processed.
+    pub cancelled: bool,
+    /// Playlists written after the run, one per selected format.
+    pub playlist_files: Vec<PathBuf>,
+    /// The run synced the folder with an earlier one.
+    pub synced: bool,
+    /// Sync: files of tracks no longer in the playlist and where they were archived
+    /// (`None` = deleted). In a dry run, the files that would be removed.
+    pub removed: Vec<(PathBuf, Option<PathBuf>)>,
+    /// What was copied to the portable device, if that was enabled.
+    pub device: Option<DeviceReport>,
+    /// HTML summary of the run; the JSON report and the retry CSV are next to it.
+    pub report_file: Option<PathBuf>,
+    /// Tags this run's records in the log file.
+    pub run_id: String,
+    /// Steps after the tracks were converted that failed, e.g. the device export.
+    pub problems: Vec<String>,
+}
+
+impl ConversionReport {
+    pub fn skipped(&self) -> usize {
+        self.plan.iter().filter(|p| matches!(p.action, PlannedAction::Skip { .. })).count()
+    }
+
+    pub fn library_hits(&self) -> usize {
+        self.results.iter().filter(|r| matches!(r.source, TrackSource::Library(_))).count()
+    }
+
+    pub fn downloaded(&self) -> usize {
+        self.results.iter().filter(|r| matches!(r.source, TrackSource::YouTube { .. })).count()
+    }
+
+    /// Tracks left as they were by sync.
+    pub fn unchanged(&self) -> usize {
+        self.results.iter().filter(|r| matches!(r.source, TrackSource::Previous)).count()
+    }
+
+    /// Converted tracks that verification flagged as suspect.
+    pub fn suspects(&self) -> usize {
+        self.results.iter().filter(|r| r.suspect.is_some()).count()
+    }
+
+    /// One-line summary for status bars and logs.
+    pub fn summary(&self) -> String {
+        let mut summary = self.counts();
+        if self.synced {
+            let removed = if self.dry_run { "to remove" } else { "removed" };
+            summary.push_str(&format!(" Sync: {} unchanged, {} {removed}.", self.unchanged(), self.removed.len()));
+        }
+        if !self.problems.is_empty() {
+            summary.push_str(&format!(" {} step(s) after converting failed; see the log.", self.problems.len()));
+        }
+        summary
+    }
+
+    fn counts(&self) -> String {
+        if self.dry_run {
+            let planned = |f: fn(&PlannedAction) -> bool| self.plan.iter().filter(|p| f(&p.action)).count();
+            format!(
+                "Dry run: {} to download, {} from library, {} skipped.",
+                planned(|a| matches!(a, PlannedAction::Download { .. })),
+                planned(|a| matches!(a, PlannedAction::Library { .. })),
+                self.skipped()
+            )
+        } else {
+            format!(
+                "{} tracks ({} from library, {} downloaded, {} suspect, {} failed, {} skipped).",
+                self.results.len(),
+                self.library_hits(),
+                self.downloaded(),
+                self.suspects(),
+                self.failures.len(),
+                self.skipped()
+            )
+        }
+    }
+}
+
+/// Where a library file will be placed in `output_dir` for the configured mode:
+/// named by the template, keeping the source's extension unless it gets transcoded.
+pub fn library_destination(
+    src: &Path,
+    track: &TrackInfo,
+    index: usize,
+    template: &NameTemplate,
+    config: &AppConfig,
+    output_dir: &Path,
+    taken: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let src_ext = src.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let target_ext = config.output_format.extension().unwrap_or(&src_ext);
+    let ext = if config.library_mode == LibraryMode::Transcode { target_ext } else { &src_ext };
+    output_path(template, track, index, ext, output_dir, taken)
+}
+
+/// Move a finished download from its temporary yt-dlp name to the `planned` path,
+/// keeping the extension yt-dlp actually produced. Plans for [`OutputFormat::Original`]
+/// have no extension yet, so it is appended rather than replaced.
+fn move_to_final_path(downloaded: &Path, planned: &Path, format: OutputFormat) -> Result<PathBuf> {
+    let ext = downloaded.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let mut target = match format.extension() {
+        Some(_) => planned.with_extension(ext),
+        None => {
+            let mut name = planned.as_os_str().to_owned();
+            name.push(format!(".{ext}"));
+            PathBuf::from(name)
+        }
+    };
+    if target.exists() {
+        // Someone created the file after the plan was made
+        let name = target.file_name().and_then(|n| n.to_str()).unwrap_or("track").to_string();
+        let dir = target.parent().unwrap_or(Path::new("")).to_path_buf();
+        target = unique_path(&dir, &name, &mut HashSet::new(), false);
+    }
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    fs::rename(downloaded, &target).with_context(|| format!("Failed to move {:?} to {:?}", downloaded, target))?;
+    Ok(target)
+}
+
+/// Link `src` to `dst`: hard link first, then symlink, then a plain copy.
+fn link_file(src: &Path, dst: &Path) -> Result<()> {
+    if fs::hard_link(src, dst).is_ok() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    let linked = std::os::unix::fs::symlink(src, dst).is_ok();
+    #[cfg(windows)]
+    let linked = std::os::windows::fs::symlink_file(src, dst).is_ok();
+    #[cfg(not(any(unix, windows)))]
+    let linked = false;
+    if !linked {
+        fs::copy(src, dst).with_context(|| format!("Failed to copy {:?} to {:?}", src, dst))?;
+    }
+    Ok(())
+}
+
+/// Place a local library file at `dst` according to `config.library_mode`.
+/// Returns whether the output is a private copy that may be re-tagged.
+fn import_from_library(tools: &Tools, src: &Path, dst: &Path, config: &AppConfig) -> Result<bool> {
+    let same_format = src.extension().map(|e| e.to_ascii_lowercase()) == dst.extension().map(|e| e.to_ascii_lowercase());
+
+    match config.library_mode {
+        LibraryMode::Link => {
+            link_file(src, dst)?;
+            Ok(false)
+        }
+        LibraryMode::Transcode if !same_format => {
+            let mut args: Vec<OsString> =
+                vec!["-y".into(), "-i".into(), src.into(), "-vn".into(), "-map_metadata".into(), "0".into()];
+            args.extend(config.output_format.ffmpeg_codec_args(config.bitrate_kbps).into_iter().map(OsString::from));
+            args.push(dst.into());
+            let output = tools.runner.run(tools.ffmpeg, &args).context("Failed to start ffmpeg")?;
+            if !output.success {
+                return Err(anyhow::anyhow!("ffmpeg failed: {}", output.stderr));
+            }
+            Ok(true)
+        }
+        LibraryMode::Copy | LibraryMode::Transcode => {
+            fs::copy(src, dst).with_context(|| format!("Failed to copy {:?} to {:?}", src, dst))?;
+            Ok(true)
+        }
+    }
+}
+
+/// Write tags to an output file based on its container.
+fn tag_output(out_file: &Path, track: &TrackInfo, source_url: Option<&str>) -> Result<()> {
+    write_tags(out_file, track, source_url).with_context(|| format!("Failed to save tags to {:?}", out_file))
+}
+
+/// Embed the cover for a finished track if enabled.
+/// Artwork is optional, so problems are reported as warnings instead of failing the track.
+fn add_cover(
+    index: usize,
+    output: &Path,
+    track: &TrackInfo,
+    thumbnail: Option<&Path>,
+    config: &AppConfig,
+    events: &Sender<ConversionEvent>,
+) {
+    if !config.cover_art.embed {
+        return;
+    }
+    if let Err(e) = apply_cover(output, track, thumbnail, &config.cover_art) {
+        let _ = events.send(ConversionEvent::Warning { index, message: format!("No cover art: {e:#}") });
+    }
+}
+
+/// Trim stage for a finished file: cut leading/trailing silence if enabled.
+/// Returns what was removed; problems are reported as warnings and leave the file as it was.
+fn trim_output(tools: &Tools, output: &Path, config: &AppConfig, progress: &mut TrackProgress, control: RunControl) -> Vec<Cut> {
+    if !config.trim.silence {
+        return Vec::new();
+    }
+    progress.phase = TrackPhase::Trimming;
+    let _ = control.events.send(ConversionEvent::Progress(progress.clone()));
+    let cuts = trim::detect_silence(tools, output, &config.trim)
+        .and_then(|cuts| trim::trim_silence(tools, output, &cuts, config.bitrate_kbps).map(|_| cuts));
+    cuts.unwrap_or_else(|e| {
+        let message = format!("Silence not trimmed: {e:#}");
+        let _ = control.events.send(ConversionEvent::Warning { index: progress.index, message });
+        Vec::new()
+    })
+}
+
+/// Loudness stage for a finished file: measure it, and with [`LoudnessMode::Normalize`]
+/// re-encode it to the target level. Returns the measurement when ReplayGain tags are wanted.
+/// Problems are reported as warnings; the track is kept as it was.
+fn adjust_loudness(
+    tools: &Tools,
+    output: &Path,
+    config: &AppConfig,
+    progress: &mut TrackProgress,
+    control: RunControl,
+) -> Option<Loudness> {
+    let mode = config.loudness.mode;
+    if mode == LoudnessMode::Off {
+        return None;
+    }
+    progress.phase = TrackPhase::Normalizing;
+    let _ = control.events.send(ConversionEvent::Progress(progress.clone()));
+    let warn = |e: anyhow::Error| {
+        let message = format!("Loudness {}: {e:#}", if mode == LoudnessMode::Normalize { "normalization skipped" } else { "not measured" });
+        let _ = control.events.send(ConversionEvent::Warning { index: progress.index, message });
+    };
+    let measured = match loudness::measure(tools, output, &config.loudness) {
+        Ok(measured) => measured,
+        Err(e) => {
+            warn(e);
+            return None;
+        }
+    };
+    match mode {
+        LoudnessMode::Normalize => {
+            if let Err(e) = loudness::normalize(tools, output, &measured, &config.loudness, config.bitrate_kbps) {
+                warn(e);
+            }
+            None
+        }
+        _ => Some(measured),
+    }
+}
+
+/// Write ReplayGain track gain/peak for a tagged file.
+fn write_track_gain(index: usize, output: &Path, loudness: Option<&Loudness>, events: &Sender<ConversionEvent>) {
+    let Some(loudness) = loudness else { return };
+    if let Err(e) = set_custom_fields(output, &replaygain_fields(loudness, None)) {
+        let _ = events.send(ConversionEvent::Warning { index, message: format!("ReplayGain tags not written: {e:#}") });
+    }
+}
+
+/// Write ReplayGain album gain/peak to every measured track that has an album.
+fn write_album_gain(results: &[TrackResult], events: &Sender<ConversionEvent>) {
+    let mut albums: HashMap<(&str, &str), Vec<&TrackResult>> = HashMap::new();
+    for r in results.iter().filter(|r| r.loudness.is_some() && !r.track.album.is_empty()) {
+        let artist = if r.track.album_artist.is_empty() { &r.track.artist } else { &r.track.album_artist };
+        albums.entry((artist.as_str(), r.track.album.as_str())).or_default().push(r);
+    }
+    for tracks in albums.values() {
+        let measured: Vec<_> = tracks
+            .iter()
+            .filter_map(|r| {
+                let secs = r.media.as_ref().map(|i| i.duration.as_secs_f64()).unwrap_or(0.0);
+                r.loudness.map(|l| (l, secs))
+            })
+            .collect();
+        let Some(album) = album_loudness(&measured) else { continue };
+        let fields: BTreeMap<_, _> =
+            replaygain_fields(&album, Some(&album)).into_iter().filter(|(k, _)| k.contains("ALBUM")).collect();
+        for r in tracks {
+            if let Err(e) = set_custom_fields(&r.output, &fields) {
+                let message = format!("ReplayGain album tags not written: {e:#}");
+                let _ = events.send(ConversionEvent::Warning { index: r.index, message });
+            }
+        }
+    }
+}
+
+/// How a running conversion reports back to, and is stopped by, its caller.
+#[derive(Clone, Copy)]
+struct RunControl<'a> {
+    events: &'a Sender<ConversionEvent>,
+    cancel: &'a AtomicBool,
+}
+
+/// Sleep for `delay` in short steps; returns `false` if cancelled meanwhile.
+fn sleep_unless_cancelled(delay: Duration, cancel: &AtomicBool) -> bool {
+    let deadline = Instant::now() + delay;
+    while Instant::now() < deadline {
+        if cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep((deadline - Instant::now()).min(Duration::from_millis(100)));
+    }
+    !cancel.load(Ordering::Relaxed)
+}
+
+/// Check a fresh download; a failed file is deleted and reported as [`FailureKind::VerificationFailed`].
+fn verify_download(tools: &Tools, mut downloaded: Downloaded, expected: Option<Duration>, config: &AppConfig) -> Result<Downloaded> {
+    if !config.verify.enabled {
+        return Ok(downloaded);
+    }
+    match verify(tools, &downloaded.path, expected, &config.verify) {
+        Verdict::Ok => Ok(downloaded),
+        Verdict::Suspect(reason) => {
+            downloaded.suspect = Some(reason);
+            Ok(downloaded)
+        }
+        Verdict::Failed(reason) => {
+            let _ = fs::remove_file(&downloaded.path);
+            if let Some(thumbnail) = &downloaded.thumbnail {
+                let _ = fs::remove_file(thumbnail);
+            }
+            Err(YtDlpError { kind: FailureKind::VerificationFailed, message: format!("download rejected: {reason}") }.into())
+        }
+    }
+}
+
+/// Try each query in turn until one yields a file.
+///
+/// Transient failures (rate limiting, network, timeouts) retry the same query with
+/// exponential backoff; failures tied to the chosen video move on to the next query;
+/// anything else (broken extractor, ffmpeg) gives up on the track straight away.
+/// Files that fail verification count as a failure of that video and move on too.
+/// `progress.attempt` counts every yt-dlp run, including retries.
+fn download_track(
+    tools: &Tools,
+    queries: &[String],
+    expected: Option<Duration>,
+    config: &AppConfig,
+    output_dir: &Path,
+    progress: &mut TrackProgress,
+    control: RunControl,
+) -> Result<(Downloaded, String)> {
+    let RunControl { events, cancel } = control;
+    let mut last_err = None;
+    for query in queries {
+        let mut retry = 0;
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(anyhow::anyhow!("cancelled"));
+            }
+            progress.attempt += 1;
+            let mut on_line = |line: &str| {
+                if progress.update_from_line(line) {
+                    let _ = events.send(ConversionEvent::Progress(progress.clone()));
+                }
+            };
+            let downloaded = run_yt_dlp(tools, query, output_dir, config, &mut on_line)
+                .and_then(|d| verify_download(tools, d, expected, config));
+            let err = match downloaded {
+                Ok(downloaded) => return Ok((downloaded, query.clone())),
+                Err(e) => e,
+            };
+            // A tool that won't start fails every query the same way
+            if err.downcast_ref::<ToolNotStarted>().is_some() {
+                return Err(err);
+            }
+            let kind = err.downcast_ref::<YtDlpError>().map(|e| e.kind).unwrap_or(FailureKind::Other);
+            if kind.is_transient() && retry < config.retry.max_retries {
+                let delay = config.retry.backoff(retry);
+                let _ = events.send(ConversionEvent::Retrying {
+                    index: progress.index,
+                    query: query.clone(),
+                    attempt: progress.attempt + 1,
+                    delay,
+                    reason: err.to_string(),
+                });
+                if !sleep_unless_cancelled(delay, cancel) {
+                    return Err(anyhow::anyhow!("cancelled"));
+                }
+                retry += 1;
+                continue;
+            }
+            if !kind.try_next_query() {
+                return Err(err);
+            }
+            last_err = Some(err);
+            break;
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no search queries for track")))
+}
+
+/// Record a track that could not be converted; the run goes on with the next one.
+fn record_failure(failures: &mut Vec<TrackFailure>, events: &Sender<ConversionEvent>, failure: TrackFailure) {
+    let _ = events.send(ConversionEvent::TrackFailed { index: failure.index, failure: failure.clone() });
+    failures.push(failure);
+}
+
+/// Note a step after the tracks that failed. The converted files are fine, so the run
+/// goes on and still returns its report.
+fn record_problem(report: &mut ConversionReport, events: &Sender<ConversionEvent>, step: &str, error: anyhow::Error) {
+    let message = format!("{step} failed: {error:#}");
+    let _ = events.send(ConversionEvent::StepFailed { message: message.clone() });
+    report.problems.push(message);
+}
+
+/// Remember the files written so far and describe the run in the report files.
+fn save_state_and_report(report: &mut ConversionReport, previous: &SyncState, output_dir: &Path, events: &Sender<ConversionEvent>) {
+    if let Err(e) = previous.update(&report.results, output_dir).save(output_dir) {
+        record_problem(report, events, "Saving the sync state", e);
+    }
+    match write_report(report, output_dir) {
+        Ok(file) => report.report_file = Some(file),
+        Err(e) => record_problem(report, events, "Writing the run report", e),
+    }
+}
+
+/// Wrap up a run the user stopped: remember the files written so far and report on them.
+fn finish_cancelled(
+    mut report: ConversionReport,
+    previous: &SyncState,
+    output_dir: &Path,
+    completed: usize,
+    events: &Sender<ConversionEvent>,
+) -> Result<ConversionReport> {
+    report.cancelled = true;
+    save_state_and_report(&mut report, previous, output_dir, events);
+    let _ = events.send(ConversionEvent::Cancelled { completed });
+    Ok(report)
+}
+
+/// Main playlist conversion logic.
+///
+/// Builds a plan first (filters, duplicates, library matches), then carries it out.
+/// With `config.sync` the folder is first compared with the previous run's state: tracks
+/// still in the playlist keep their file and dropped tracks are archived or deleted.
+/// With `config.dry_run` the plan is written to `output_dir` and no audio is produced.
+/// All external tools are started through `tools.runner`. Progress is reported on
+/// `events`; setting `cancel` stops the run before the next track or retry.
+pub fn convert_playlist(
+    tools: &Tools,
+    tracks: &[TrackInfo],
+    config: &AppConfig,
+    output_dir: &Path,
+    events: &Sender<ConversionEvent>,
+    cancel: &AtomicBool,
+) -> Result<ConversionReport> {
+    // Held until the run returns, however it ends
+    let run = logging::start_run();
+    let run_id = run.id.clone();
+    log::info!("Run {run_id}: {} tracks into {}", tracks.len(), output_dir.display());
+    log::debug!("Settings: {}", serde_json::to_string(config).unwrap_or_default());
+    fs::create_dir_all(output_dir)?;
+    if config.media_server.enabled {
+        check_mapping(&config.media_server, output_dir)?;
+    }
+    if config.device.enabled && !config.dry_run {
+        check_device(&config.device)?;
+    }
+    let previous = SyncState::load(output_dir)?;
+    let mut report = ConversionReport { synced: config.sync.enabled, run_id, ..ConversionReport::default() };
+    let mut kept = HashMap::new();
+    if config.sync.enabled {
+        let diff = previous.diff(tracks, output_dir);
+        kept = diff.kept;
+        // Removed first, so new tracks can take over their names
+        report.removed = match config.dry_run {
+            true => diff.removed.into_iter().map(|file| (file, None)).collect(),
+            false => remove_files(&diff.removed, output_dir, &config.sync)?,
+        };
+    }
+    report.plan = plan_playlist(tools, tracks, config, output_dir, &kept)?;
+    let total = tracks.len();
+    let emit = |event| {
+        let _ = events.send(event);
+    };
+    emit(ConversionEvent::Started { total, dry_run: config.dry_run });
+    if !config.dry_run {
+        for (file, archived) in &report.removed {
+            emit(ConversionEvent::TrackRemoved { file: file.clone(), archived: archived.clone() });
+        }
+    }
+
+    if config.dry_run {
+        report.plan_file = Some(write_plan(&report.plan, output_dir)?);
+        report.dry_run = true;
+        emit(ConversionEvent::Finished(report.clone()));
+        return Ok(report);
+    }
+
+    for (i, planned) in report.plan.iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            return finish_cancelled(report, &previous, output_dir, i, events);
+        }
+        let track = &planned.track;
+        match &planned.action {
+            PlannedAction::Skip { reason } => {
+                emit(ConversionEvent::TrackSkipped { index: i, track: track.clone(), reason: reason.clone() });
+            }
+            PlannedAction::Keep { output } => {
+                let result = TrackResult {
+                    index: i,
+                    track: track.clone(),
+                    source: TrackSource::Previous,
+                    output: output.clone(),
+                    suspect: None,
+                    loudness: None,
+                    cuts: Vec::new(),
+                    media: probe(output).ok(),
+                };
+                emit(ConversionEvent::TrackSucceeded { index: i, result: result.clone() });
+                report.results.push(result);
+            }
+            PlannedAction::Library { source, output, .. } => {
+                emit(ConversionEvent::TrackStarted { index: i, total, track: track.clone() });
+                emit(ConversionEvent::CandidateChosen { index: i, source: TrackSource::Library(source.clone()) });
+                let mut progress = TrackProgress::new(i, total, &track.title);
+                progress.phase = TrackPhase::Converting;
+                emit(ConversionEvent::Progress(progress.clone()));
+                let imported = import_from_library(tools, source, output, config).and_then(|private| {
+                    if !private {
+                        return Ok((None, Vec::new()));
+                    }
+                    let control = RunControl { events, cancel };
+                    let cuts = trim_output(tools, output, config, &mut progress, control);
+                    let loudness = adjust_loudness(tools, output, config, &mut progress, control);
+                    progress.phase = TrackPhase::Tagging;
+                    emit(ConversionEvent::Progress(progress.clone()));
+                    tag_output(output, track, None)?;
+                    add_cover(i, output, track, None, config, events);
+                    write_track_gain(i, output, loudness.as_ref(), events);
+                    Ok((loudness, cuts))
+                });
+                let (loudness, cuts) = match imported {
+                    Ok(done) => done,
+                    Err(e) => {
+                        // Only the copy or link is removed, never the library file
+                        let _ = fs::remove_file(output);
+                        let message = format!("Could not use library file for '{} - {}': {:#}", track.title, track.artist, e);
+                        let failure = TrackFailure { index: i, track: track.clone(), kind: FailureKind::OutputFailed, message, attempts: 0 };
+                        record_failure(&mut report.failures, events, failure);
+                        continue;
+                    }
+                };
+                let result = TrackResult {
+                    index: i,
+                    track: track.clone(),
+                    source: TrackSource::Library(source.clone()),
+                    output: output.clone(),
+                    suspect: None,
+                    loudness,
+                    cuts,
+                    media: probe(output).ok(),
+                };
+                emit(ConversionEvent::TrackSucceeded { index: i, result: result.clone() });
+                report.results.push(result);
+            }
+            PlannedAction::Download { queries, output: planned_output, .. } => {
+                emit(ConversionEvent::TrackStarted { index: i, total, track: track.clone() });
+                let mut progress = TrackProgress::new(i, total, &track.title);
+                emit(ConversionEvent::Progress(progress.clone()));
+                let expected = track.duration_ms.map(Duration::from_millis);
+                let control = RunControl { events, cancel };
+                let downloaded = download_track(tools, queries, expected, config, output_dir, &mut progress, control);
+                if cancel.load(Ordering::Relaxed) && downloaded.is_err() {
+                    return finish_cancelled(report, &previous, output_dir, i, events);
+                }
+                match downloaded {
+                    Ok((downloaded, query)) => {
+                        let temp_file = downloaded.path;
+                        let source = TrackSource::YouTube {
+                            query,
+                            url: downloaded.source_url.clone(),
+                            title: downloaded.video_title.clone(),
+                        };
+                        emit(ConversionEvent::CandidateChosen { index: i, source: source.clone() });
+                        let mut cuts = downloaded.cuts;
+                        cuts.extend(trim_output(tools, &temp_file, config, &mut progress, control));
+                        let loudness = adjust_loudness(tools, &temp_file, config, &mut progress, control);
+                        // Set tags if possible
+                        progress.phase = TrackPhase::Tagging;
+                        emit(ConversionEvent::Progress(progress.clone()));
+                        let finished = tag_output(&temp_file, track, downloaded.source_url.as_deref())
+                            .and_then(|()| move_to_final_path(&temp_file, planned_output, config.output_format));
+                        let out_file = match finished {
+                            Ok(out_file) => out_file,
+                            Err(e) => {
+                                let _ = fs::remove_file(&temp_file);
+                                if let Some(thumbnail) = &downloaded.thumbnail {
+                                    let _ = fs::remove_file(thumbnail);
+                                }
+                                let message = format!("Could not finish '{} - {}': {:#}", track.title, track.artist, e);
+                                let failure = TrackFailure {
+                                    index: i,
+                                    track: track.clone(),
+                                    kind: FailureKind::OutputFailed,
+                                    message,
+                                    attempts: progress.attempt,
+                                };
+                                record_failure(&mut report.failures, events, failure);
+                                continue;
+                            }
+                        };
+                        add_cover(i, &out_file, track, downloaded.thumbnail.as_deref(), config, events);
+                        write_track_gain(i, &out_file, loudness.as_ref(), events);
+                        if let Some(thumbnail) = &downloaded.thumbnail {
+                            let _ = fs::remove_file(thumbnail);
+                        }
+                        let media = probe(&out_file).ok();
+                        let result = TrackResult {
+                            index: i,
+                            track: track.clone(),
+                            source,
+                            output: out_file,
+                            suspect: downloaded.suspect,
+                            loudness,
+                            cuts,
+                            media,
+                        };
+                        emit(ConversionEvent::TrackSucceeded { index: i, result: result.clone() });
+                        report.results.push(result);
+                    }
+                    Err(e) if e.downcast_ref::<ToolNotStarted>().is_some() => {
+                        // Every later track would fail the same way, so end the run with one error
+                        save_state_and_report(&mut report, &previous, output_dir, events);
+                        return Err(e.context("Cannot download tracks"));
+                    }
+                    Err(e) => {
+                        let kind = e.downcast_ref::<YtDlpError>().map(|e| e.kind).unwrap_or(FailureKind::Other);
+                        let failure = TrackFailure {
+                            index: i,
+                            track: track.clone(),
+                            kind,
+                            message: format!("yt-dlp failed for '{} - {}': {:#}", track.title, track.artist, e),
+                            attempts: progress.attempt,
+                        };
+                        record_failure(&mut report.failures, events, failure);
+                    }
+                }
+            }
+        }
+    }
+
+    // The tracks are done; from here on a failing step is noted instead of ending the run
+    if config.loudness.mode == LoudnessMode::ReplayGain {
+        write_album_gain(&report.results, events);
+    }
+    let name = output_dir.file_name().and_then(|n| n.to_str()).unwrap_or("playlist");
+    match write_playlists(output_dir, name, &report.results, config) {
+        Ok(files) => report.playlist_files = files,
+        Err(e) => record_problem(&mut report, events, "Writing the playlists", e),
+    }
+    if config.media_server.enabled {
+        match write_server_playlist(output_dir, name, &report.results, config, &report.playlist_files) {
+            Ok(file) => report.playlist_files.push(file),
+            Err(e) => record_problem(&mut report, events, "Writing the media server playlist", e),
+        }
+    }
+    if config.device.enabled {
+        match export_to_device(tools, &report.results, name, config, output_dir, events, cancel) {
+            Ok(device) => {
+                emit(ConversionEvent::DeviceExported(device.clone()));
+                report.device = Some(device);
+            }
+            Err(e) => record_problem(&mut report, events, "Copying to the device", e),
+        }
+    }
+    save_state_and_report(&mut report, &previous, output_dir, events);
+    emit(ConversionEvent::Finished(report.clone()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LoudnessMode, OutputFormat, PlaylistFormat, RetryPolicy};
+    use crate::trim::CutReason;
+    use std::time::Duration;
+    use crate::subprocess::{SystemRunner, ToolOutput};
+    use crate::test_support::{fake_tool, track, write_mp3, RecordingRunner};
+    use lofty::{Accessor, TaggedFileExt};
+
+    fn convert(runner: &dyn CommandRunner, tracks: &[TrackInfo], config: &AppConfig, out: &Path) -> Result<ConversionReport> {
+        convert_with_events(runner, tracks, config, out, &AtomicBool::new(false)).0
+    }
+
+    /// Run a conversion and collect every event it sent.
+    fn convert_with_events(
+        runner: &dyn CommandRunner,
+        tracks: &[TrackInfo],
+        config: &AppConfig,
+        out: &Path,
+        cancel: &AtomicBool,
+    ) -> (Result<ConversionReport>, Vec<ConversionEvent>) {
+        let tool = fake_tool();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let result = convert_playlist(&Tools::new(runner, Some(&tool), Some(&tool)), tracks, config, out, &tx, cancel);
+        drop(tx);
+        (result, rx.into_iter().collect())
+    }
+
+    #[test]
+    fn downloads_tags_and_writes_playlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("Road Trip");
+        let tracks = [track("Song One", "Artist A", "Album X"), track("Song Two", "Artist B", "Album Y")];
+
+        let report = convert(&SystemRunner::default(), &tracks, &AppConfig::default(), &out).unwrap();
+
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.downloaded(), 2);
+        for (r, t) in report.results.iter().zip(&tracks) {
+            assert_eq!(r.output.extension().unwrap(), "mp3");
+            let tagged = lofty::read_from_path(&r.output).unwrap();
+            let tag = tagged.primary_tag().unwrap();
+            assert_eq!(tag.title().as_deref(), Some(t.title.as_str()));
+            assert_eq!(tag.artist().as_deref(), Some(t.artist.as_str()));
+            assert_eq!(tag.album().as_deref(), Some(t.album.as_str()));
+        }
+
+        let m3u = fs::read_to_string(out.join("Road Trip.m3u")).unwrap();
+        let lines: Vec<_> = m3u.lines().collect();
+        assert_eq!(lines[0], "#EXTM3U");
+        assert_eq!(lines[1], "#EXTINF:1,Artist A - Song One");
+        assert_eq!(lines[2], report.results[0].output.file_name().unwrap().to_str().unwrap());
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn writes_run_report_and_retry_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("Road Trip");
+        let mut failing = track("FAIL <Song>", "Artist", "Album");
+        failing.spotify_uri = "spotify:track:abc".into();
+        failing.duration_ms = Some(215_000);
+        let tracks = [track("Song", "Artist", ""), failing, track("Song", "Artist", "")];
+
+        let report = convert(&SystemRunner::default(), &tracks, &AppConfig::default(), &out).unwrap();
+
+        assert_eq!(report.report_file, Some(out.join(crate::report::REPORT_HTML)));
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(out.join("report.json")).unwrap()).unwrap();
+        let entries = json["tracks"].as_array().unwrap();
+        let statuses: Vec<_> = entries.iter().map(|e| e["status"].as_str().unwrap()).collect();
+        assert_eq!(statuses, ["converted", "failed", "skipped"]);
+        assert_eq!(entries[0]["video_title"], "Song Artist topic (Official Audio)");
+        assert_eq!(entries[0]["confidence"], 95);
+        assert_eq!(entries[1]["failure"], "unavailable");
+
+        // The retry CSV reads back as input with the failed and skipped tracks
+        let retry = crate::csvparse::parse_csv(&out.join("failed.csv")).unwrap();
+        assert_eq!(retry.len(), 2);
+        assert_eq!((retry[0].title.as_str(), retry[0].album.as_str()), ("FAIL <Song>", "Album"));
+        assert_eq!((retry[0].spotify_uri.as_str(), retry[0].duration_ms), ("spotify:track:abc", Some(215_000)));
+
+        let html = fs::read_to_string(out.join("report.html")).unwrap();
+        assert!(html.contains("FAIL &lt;Song&gt;") && html.contains("duplicate of track 1"));
+        assert!(html.contains(">Song Artist topic (Official Audio)</a>"));
+    }
+
+    #[test]
+    fn names_outputs_from_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("Mix: Vol. 1");
+        let config = AppConfig {
+            file_name_template: "{album_artist}/{album}/{track:02} - {title}".into(),
+            skip_duplicates: false,
+            ..AppConfig::default()
+        };
+        let mut first = track("Song: One", "AC/DC", "Album X");
+        first.track_number = Some(1);
+        let second = track("Song: One", "AC/DC", "Album X");
+        let mut third = track("Song: One", "AC/DC", "Album X");
+        third.track_number = Some(1);
+
+        let report = convert(&SystemRunner::default(), &[first, second, third], &config, &out).unwrap();
+
+        let album = out.join("AC_DC").join("Album X");
+        let outputs: Vec<_> = report.results.iter().map(|r| r.output.clone()).collect();
+        assert_eq!(outputs, [album.join("01 - Song_ One.mp3"), album.join("Song_ One.mp3"), album.join("01 - Song_ One (1).mp3")]);
+        assert!(outputs.iter().all(|p| p.exists()));
+        assert!(!fs::read_dir(&out).unwrap().any(|e| e.unwrap().file_name().to_string_lossy().starts_with("yt2media_")));
+        // The playlist's own name follows the naming profile too
+        let m3u = fs::read_to_string(out.join("Mix_ Vol. 1.m3u")).unwrap();
+        assert!(m3u.contains(&format!("AC_DC{}Album X{}01 - Song_ One.mp3", std::path::MAIN_SEPARATOR, std::path::MAIN_SEPARATOR)));
+
+        let bad = AppConfig { file_name_template: "{artist}/{name}".into(), ..AppConfig::default() };
+        assert!(convert(&SystemRunner::default(), &[track("Song", "Artist", "")], &bad, &out).is_err());
+    }
+
+    #[test]
+    fn sync_downloads_additions_and_archives_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("Road Trip");
+        fs::create_dir_all(&out).unwrap();
+        write_mp3(&out.join("Artist - Old.mp3"));
+        let (old, kept, new) = (track("Old", "Artist", ""), track("Kept", "Artist", ""), track("New", "Artist", ""));
+        let first = convert(&SystemRunner::default(), &[old.clone(), kept.clone()], &AppConfig::default(), &out).unwrap();
+        let old_file = first.results[0].output.clone();
+        assert_eq!(old_file, out.join("Artist - Old (1).mp3"));
+
+        let mut config = AppConfig::default();
+        config.sync.enabled = true;
+        config.dry_run = true;
+        let plan = convert(&SystemRunner::default(), &[kept.clone(), new.clone()], &config, &out).unwrap();
+        assert_eq!(plan.removed, [(old_file.clone(), None)]);
+        assert!(matches!(&plan.plan[0].action, PlannedAction::Keep { output } if *output == first.results[1].output));
+        assert!(old_file.exists());
+
+        config.dry_run = false;
+        let report = convert(&SystemRunner::default(), &[kept, new], &config, &out).unwrap();
+
+        assert_eq!((report.unchanged(), report.downloaded()), (1, 1));
+        let archived = out.join("Removed").join("Artist - Old (1).mp3");
+        assert_eq!(report.removed, [(old_file.clone(), Some(archived.clone()))]);
+        assert!(!old_file.exists() && archived.exists());
+        // A file we didn't write is left alone even though its track is gone
+        assert!(out.join("Artist - Old.mp3").exists());
+        assert_eq!(report.results[0].output, first.results[1].output);
+        assert!(report.summary().ends_with("Sync: 1 unchanged, 1 removed."));
+        let m3u = fs::read_to_string(out.join("Road Trip.m3u")).unwrap();
+        assert!(m3u.contains("Artist - Kept.mp3") && m3u.contains("Artist - New.mp3") && !m3u.contains("Old"));
+    }
+
+    #[test]
+    fn writes_media_server_playlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("Road Trip");
+        let mut config = AppConfig { playlist_formats: vec![PlaylistFormat::M3u8], ..AppConfig::default() };
+        config.media_server.enabled = true;
+        config.media_server.local_root = dir.path().to_path_buf();
+        config.media_server.server_root = "/srv/music".into();
+
+        let report = convert(&SystemRunner::default(), &[track("Song", "Artist", "")], &config, &out).unwrap();
+
+        assert_eq!(report.playlist_files, [out.join("Road Trip.m3u8"), out.join("Road Trip (server).m3u8")]);
+        let server = fs::read_to_string(out.join("Road Trip (server).m3u8")).unwrap();
+        assert!(server.starts_with("#EXTM3U\n#PLAYLIST:Road Trip\n#EXTINF:1,Artist - Song\n"));
+        assert!(server.ends_with("\n/srv/music/Road Trip/Artist - Song.mp3\n"));
+
+        config.media_server.local_root = dir.path().join("elsewhere");
+        let err = convert(&SystemRunner::default(), &[track("Song", "Artist", "")], &config, &out).unwrap_err();
+        assert!(err.to_string().contains("not inside the media server folder"));
+    }
+
+    #[test]
+    fn writes_full_metadata_and_source_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut song = track("Song", "Artist", "Album");
+        song.album_artist = "Various Artists".into();
+        song.track_number = Some(3);
+        song.track_total = Some(12);
+        song.disc_number = Some(1);
+        song.disc_total = Some(2);
+        song.release_date = "2021-05-14".into();
+        song.genres = vec!["indie pop".into(), "dream pop".into()];
+        song.isrc = "USRC17607839".into();
+        song.spotify_uri = "spotify:track:abc".into();
+
+        let report = convert(&SystemRunner::default(), &[song], &AppConfig::default(), dir.path()).unwrap();
+
+        let result = &report.results[0];
+        let url = "https://www.youtube.com/watch?v=SongArtistt";
+        assert!(matches!(&result.source, TrackSource::YouTube { url: Some(u), .. } if u == url));
+        let mut file = fs::File::open(&result.output).unwrap();
+        let id3 = <lofty::mpeg::MpegFile as lofty::AudioFile>::read_from(&mut file, lofty::ParseOptions::new()).unwrap();
+        let tag = id3.id3v2().unwrap();
+        let text = |id: &str| tag.get_text(&lofty::id3::v2::FrameId::Valid(id.into())).map(str::to_string);
+        assert_eq!(text("TRCK").as_deref(), Some("3/12"));
+        assert_eq!(text("TPOS").as_deref(), Some("1/2"));
+        assert_eq!(text("TPE2").as_deref(), Some("Various Artists"));
+        assert_eq!(text("TDRC").as_deref(), Some("2021-05-14"));
+        assert_eq!(text("TSRC").as_deref(), Some("USRC17607839"));
+        assert_eq!(text("TCON").as_deref(), Some("indie pop\0dream pop"));
+        assert_eq!(tag.get_user_text(crate::audio::SPOTIFY_URI_KEY), Some("spotify:track:abc"));
+        assert_eq!(tag.comment().as_deref(), Some(url));
+        assert!(!fs::read_dir(dir.path()).unwrap().any(|e| e.unwrap().path().to_string_lossy().ends_with(".info.json")));
+    }
+
+    #[test]
+    fn writes_replaygain_or_normalizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.loudness.mode = LoudnessMode::ReplayGain;
+        let tracks = [track("Quiet", "Artist", "Album"), track("LOUD", "Artist", "Album")];
+
+        let report = convert(&SystemRunner::default(), &tracks, &config, dir.path()).unwrap();
+
+        let gains: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| {
+                let mut file = fs::File::open(&r.output).unwrap();
+                let mp3 = <lofty::mpeg::MpegFile as lofty::AudioFile>::read_from(&mut file, lofty::ParseOptions::new()).unwrap();
+                let tag = mp3.id3v2().unwrap();
+                let field = |key: &str| tag.get_user_text(key).unwrap().to_string();
+                (field("REPLAYGAIN_TRACK_GAIN"), field("REPLAYGAIN_ALBUM_GAIN"))
+            })
+            .collect();
+        assert_eq!(gains[0], ("2.00 dB".to_string(), "-7.26 dB".to_string()));
+        assert_eq!(gains[1], ("-10.00 dB".to_string(), "-7.26 dB".to_string()));
+
+        config.loudness.mode = LoudnessMode::Normalize;
+        let out = dir.path().join("normalized");
+        let report = convert(&SystemRunner::default(), &tracks[..1], &config, &out).unwrap();
+        assert!(report.results[0].loudness.is_none());
+        assert!(crate::verify::probe(&report.results[0].output).is_ok());
+        assert!(!fs::read_dir(&out).unwrap().any(|e| e.unwrap().path().to_string_lossy().contains(".processing.")));
+    }
+
+    #[test]
+    fn records_trimmed_silence_and_sponsorblock_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.trim.silence = true;
+        config.trim.sponsorblock = true;
+        let tracks = [track("SILENT SKIT", "Artist", ""), track("Song", "Artist", "")];
+
+        let (report, events) = convert_with_events(&SystemRunner::default(), &tracks, &config, dir.path(), &AtomicBool::new(false));
+        let report = report.unwrap();
+
+        let reasons: Vec<_> = report.results[0].cuts.iter().map(|c| c.reason.clone()).collect();
+        assert_eq!(
+            reasons,
+            [CutReason::SponsorBlock("music_offtopic".into()), CutReason::LeadingSilence, CutReason::TrailingSilence]
+        );
+        assert!(report.results[1].cuts.is_empty());
+        assert!(events.iter().filter_map(ConversionEvent::log_line).any(|l| l.contains("s: SponsorBlock music_offtopic 0:10-0:25, leading silence 0:00-0:00")));
+
+        let args = yt_dlp_args(Path::new("ffmpeg"), "q", Path::new("out"), &config);
+        assert!(args.windows(2).any(|w| w[0] == "--sponsorblock-remove" && w[1] == "music_offtopic"));
+    }
+
+    #[test]
+    fn verification_rejects_short_files_and_flags_suspects() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.verify.duration_tolerance_secs = 0;
+        // The stub writes about one second of audio
+        let mut wrong = track("Long", "Artist", "");
+        wrong.duration_ms = Some(240_000);
+        let mut close = track("Close", "Artist", "");
+        close.duration_ms = Some(2_000);
+
+        let report = convert(&SystemRunner::default(), &[wrong, close], &config, dir.path()).unwrap();
+
+        let failure = &report.failures[0];
+        assert_eq!(failure.kind, FailureKind::VerificationFailed);
+        assert_eq!(failure.attempts, 3);
+        assert!(failure.message.contains("truncated: 0:01 of expected 4:00"), "{}", failure.message);
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.results[0].suspect.as_deref(), Some("length 0:01 differs from expected 0:02"));
+        assert_eq!(report.suspects(), 1);
+        let leftovers: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert!(!leftovers.iter().any(|n| n.to_string_lossy().contains("Long")), "{leftovers:?}");
+    }
+
+    #[test]
+    fn falls_back_to_next_query_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = convert(&SystemRunner::default(), &[track("FLAKY", "Artist", "")], &AppConfig::default(), dir.path()).unwrap();
+
+        match &report.results[0].source {
+            TrackSource::YouTube { query, .. } => assert_eq!(query, "FLAKY Artist official audio"),
+            other => panic!("unexpected source {other:?}"),
+        }
+    }
+
+    #[test]
+    fn failing_track_reports_yt_dlp_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracks = [track("FAIL", "Artist", ""), track("Fine", "Artist", "")];
+        let report = convert(&SystemRunner::default(), &tracks, &AppConfig::default(), dir.path()).unwrap();
+
+        assert_eq!(report.downloaded(), 1, "one failure must not stop the run");
+        let failure = &report.failures[0];
+        assert_eq!(failure.kind, FailureKind::Unavailable);
+        assert_eq!(failure.attempts, 3);
+        assert!(failure.message.contains("yt-dlp failed for 'FAIL - Artist'"), "{}", failure.message);
+        assert!(failure.message.contains("Video unavailable"), "{}", failure.message);
+    }
+
+    #[test]
+    fn partial_download_is_not_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = convert(&SystemRunner::default(), &[track("PARTIAL", "Artist", "")], &AppConfig::default(), dir.path()).unwrap();
+
+        assert_eq!(report.failures[0].kind, FailureKind::NoOutput);
+        assert!(report.failures[0].message.contains("did not produce an output file"));
+        let leftovers = fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "part")
+            .count();
+        assert_eq!(leftovers, 3, "one .part file per query variant");
+    }
+
+    #[test]
+    fn missing_output_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = convert(&SystemRunner::default(), &[track("NOFILE", "Artist", "")], &AppConfig::default(), dir.path()).unwrap();
+
+        assert_eq!(report.failures[0].kind, FailureKind::NoOutput);
+        assert!(report.results.is_empty());
+    }
+
+    #[test]
+    fn runner_timeout_tries_every_variant_then_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| Err(anyhow::anyhow!("yt-dlp timed out")));
+
+        let report = convert(&runner, &[track("Slow", "Artist", "")], &AppConfig::default(), dir.path()).unwrap();
+
+        assert!(report.failures[0].message.contains("timed out"));
+        assert_eq!(runner.call_count(), 3);
+    }
+
+    fn fast_retries(max_retries: u32) -> AppConfig {
+        let retry = RetryPolicy { max_retries, initial_backoff_ms: 1, ..RetryPolicy::default() };
+        AppConfig { retry, ..AppConfig::default() }
+    }
+
+    fn failing_output(stderr: &str) -> Result<ToolOutput> {
+        Ok(ToolOutput { success: false, stderr: stderr.into(), ..ToolOutput::default() })
+    }
+
+    #[test]
+    fn rate_limit_is_retried_with_same_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = AtomicUsize::new(0);
+        let runner = RecordingRunner::new(move |args| {
+            if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                return failing_output("ERROR: HTTP Error 429: Too Many Requests");
+            }
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            SystemRunner::default().run(&fake_tool(), &args)
+        });
+
+        let report = convert(&runner, &[track("Song", "Artist", "")], &fast_retries(3), dir.path()).unwrap();
+
+        assert_eq!(report.downloaded(), 1);
+        let calls = runner.calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|args| args.last().unwrap() == "ytsearch1:Song Artist topic"));
+    }
+
+    #[test]
+    fn exhausted_rate_limit_fails_track_without_other_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| failing_output("ERROR: HTTP Error 429: Too Many Requests"));
+
+        let report = convert(&runner, &[track("Song", "Artist", "")], &fast_retries(2), dir.path()).unwrap();
+
+        assert_eq!(report.failures[0].kind, FailureKind::RateLimited);
+        assert_eq!(report.failures[0].attempts, 3);
+        assert_eq!(runner.call_count(), 3);
+    }
+
+    #[test]
+    fn broken_extractor_gives_up_immediately() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| failing_output("ERROR: Unable to extract uploader id; please report this issue"));
+
+        let report = convert(&runner, &[track("Song", "Artist", "")], &fast_retries(3), dir.path()).unwrap();
+
+        assert_eq!(report.failures[0].kind, FailureKind::ExtractorBroken);
+        assert_eq!(runner.call_count(), 1);
+    }
+
+    #[test]
+    fn original_format_appends_the_downloaded_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let downloaded = dir.path().join("yt2media_1_0_Song.m4a");
+        fs::write(&downloaded, "audio").unwrap();
+
+        let target = move_to_final_path(&downloaded, &dir.path().join("Mr. Brown - Song"), OutputFormat::Original).unwrap();
+
+        assert_eq!(target, dir.path().join("Mr. Brown - Song.m4a"));
+        assert!(target.exists() && !downloaded.exists());
+    }
+
+    #[test]
+    fn missing_yt_dlp_ends_the_run_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| {
+            let source = std::io::Error::new(std::io::ErrorKind::NotFound, "not found");
+            Err(ToolNotStarted { program: "yt-dlp".into(), source }.into())
+        });
+        let tracks = [track("Song One", "Artist", ""), track("Song Two", "Artist", "")];
+
+        let err = convert(&runner, &tracks, &fast_retries(3), dir.path()).unwrap_err();
+
+        assert!(format!("{err:#}").contains("Failed to start yt-dlp"));
+        assert_eq!(runner.call_count(), 1);
+    }
+
+    #[test]
+    fn library_tracks_skip_yt_dlp() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        write_mp3(&library.join("Artist A - Song One.mp3"));
+        let config = AppConfig { library_dirs: vec![library.clone()], ..AppConfig::default() };
+        let runner = RecordingRunner::new(|_| Ok(ToolOutput { success: false, ..ToolOutput::default() }));
+
+        let out = dir.path().join("out");
+        let report = convert(&runner, &[track("Song One", "Artist A", "Album X")], &config, &out).unwrap();
+
+        assert_eq!(runner.call_count(), 0);
+        assert_eq!(report.library_hits(), 1);
+        let result = &report.results[0];
+        assert!(matches!(&result.source, TrackSource::Library(p) if p == &library.join("Artist A - Song One.mp3")));
+        let tagged = lofty::read_from_path(&result.output).unwrap();
+        assert_eq!(tagged.primary_tag().unwrap().album().as_deref(), Some("Album X"));
+    }
+
+    #[test]
+    fn bad_library_file_fails_only_its_track() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        fs::write(library.join("Artist A - Broken.mp3"), "not audio").unwrap();
+        let config = AppConfig { library_dirs: vec![library.clone()], ..AppConfig::default() };
+        let out = dir.path().join("out");
+
+        let report = convert(&SystemRunner::default(), &[track("Broken", "Artist A", ""), track("Song", "Artist B", "")], &config, &out).unwrap();
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!((report.failures[0].index, report.failures[0].kind), (0, FailureKind::OutputFailed));
+        assert!(report.failures[0].message.contains("Failed to save tags"));
+        assert_eq!(report.results.len(), 1);
+        assert!(!out.join("Artist A - Broken.mp3").exists());
+        assert!(library.join("Artist A - Broken.mp3").exists());
+        assert_eq!(crate::csvparse::parse_csv(&out.join("failed.csv")).unwrap()[0].title, "Broken");
+    }
+
+    #[test]
+    fn unmovable_download_fails_only_its_track() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        fs::create_dir_all(&out).unwrap();
+        // A file where the template wants a folder
+        fs::write(out.join("Artist A"), "").unwrap();
+        let config = AppConfig { file_name_template: "{artist}/{title}".into(), ..AppConfig::default() };
+
+        let report = convert(&SystemRunner::default(), &[track("Song", "Artist A", ""), track("Song", "Artist B", "")], &config, &out).unwrap();
+
+        assert_eq!((report.failures.len(), report.failures[0].kind), (1, FailureKind::OutputFailed));
+        assert!(report.failures[0].message.contains("Failed to create"));
+        assert_eq!(report.results[0].output, out.join("Artist B").join("Song.mp3"));
+        let leftovers: Vec<_> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name()).filter(|n| n.to_string_lossy().starts_with("yt2media_")).collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+
+    #[test]
+    fn failed_device_export_still_reports_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, stick) = (dir.path().join("out"), dir.path().join("stick"));
+        fs::create_dir_all(&stick).unwrap();
+        // A file where the device folder should go
+        fs::write(stick.join("Music"), "").unwrap();
+        let mut config = AppConfig::default();
+        config.device.enabled = true;
+        config.device.mount = stick;
+
+        let (report, events) = convert_with_events(&SystemRunner::default(), &[track("Song", "Artist", "")], &config, &out, &AtomicBool::new(false));
+
+        let report = report.unwrap();
+        assert_eq!(report.results.len(), 1);
+        assert!(report.device.is_none());
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].starts_with("Copying to the device failed"));
+        assert!(report.summary().ends_with("1 step(s) after converting failed; see the log."));
+        assert!(events.iter().any(|e| matches!(e, ConversionEvent::StepFailed { .. })));
+        assert!(matches!(events.last(), Some(ConversionEvent::Finished(_))));
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(out.join("report.json")).unwrap()).unwrap();
+        assert_eq!(json["problems"][0].as_str(), Some(report.problems[0].as_str()));
+        assert!(out.join("out.m3u").exists());
+    }
+
+    #[test]
+    fn embeds_square_cover_from_thumbnail_or_user_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let covers = dir.path().join("covers");
+        fs::create_dir_all(&covers).unwrap();
+        image::RgbImage::from_pixel(50, 80, image::Rgb([0, 0, 0])).save(covers.join("Artist - Mine.png")).unwrap();
+        let mut config = AppConfig::default();
+        config.cover_art.max_size = 100;
+        config.cover_art.image_dir = Some(covers);
+        config.cover_art.write_folder_cover = true;
+        let out = dir.path().join("out");
+        let tracks = [track("Song", "Artist", "Theirs"), track("Other", "Artist", "Mine")];
+
+        let report = convert(&SystemRunner::default(), &tracks, &config, &out).unwrap();
+
+        let sizes: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| {
+                let tagged = lofty::read_from_path(&r.output).unwrap();
+                let picture = &tagged.primary_tag().unwrap().pictures()[0];
+                assert_eq!(picture.pic_type(), lofty::PictureType::CoverFront);
+                let cover = image::load_from_memory(picture.data()).unwrap();
+                (cover.width(), cover.height())
+            })
+            .collect();
+        assert_eq!(sizes, [(100, 100), (50, 50)]);
+        assert!(out.join("cover.jpg").exists());
+        assert!(!fs::read_dir(&out).unwrap().any(|e| e.unwrap().path().extension().unwrap() == "png"));
+    }
+
+    #[test]
+    fn yt_dlp_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| Ok(ToolOutput { success: false, ..ToolOutput::default() }));
+        let config = AppConfig { output_format: OutputFormat::M4a, bitrate_kbps: 192, ..AppConfig::default() };
+
+        let _ = convert(&runner, &[track("Song", "Artist", "")], &config, dir.path());
+
+        let calls = runner.calls.lock().unwrap();
+        let args = &calls[0];
+        assert_eq!(args[..5], ["-x", "--audio-format", "m4a", "--audio-quality", "192K"]);
+        assert_eq!(args.last().unwrap(), "ytsearch1:Song Artist topic");
+    }
+
+    #[test]
+    fn dry_run_writes_plan_without_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        write_mp3(&library.join("Artist A - Owned.mp3"));
+        let config = AppConfig {
+            dry_run: true,
+            exclude_instrumentals: true,
+            library_dirs: vec![library],
+            ..AppConfig::default()
+        };
+        let runner = RecordingRunner::new(|_| panic!("dry run must not start tools"));
+        let tracks = [
+            track("New Song", "Artist A", ""),
+            track("Owned", "Artist A", ""),
+            track("new song!", "artist a", ""),
+            track("New Song - Instrumental", "Artist A", ""),
+        ];
+
+        let out = dir.path().join("out");
+        let report = convert(&runner, &tracks, &config, &out).unwrap();
+
+        assert!(report.dry_run);
+        assert!(report.results.is_empty());
+        assert!(matches!(&report.plan[0].action, PlannedAction::Download { commands, .. }
+            if commands[0].ends_with("\"ytsearch1:New Song Artist A topic\"")));
+        assert!(matches!(&report.plan[1].action, PlannedAction::Library { output, .. } if output == &out.join("Artist A - Owned.mp3")));
+        assert!(matches!(&report.plan[2].action, PlannedAction::Skip { reason } if reason == "duplicate of track 1"));
+        assert!(matches!(&report.plan[3].action, PlannedAction::Skip { reason } if reason == "instrumental"));
+        assert_eq!(report.summary(), "Dry run: 1 to download, 1 from library, 2 skipped.");
+
+        let mut files: Vec<_> = fs::read_dir(&out).unwrap().map(|e| e.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files, ["plan.csv", "plan.json"]);
+        let plan: serde_json::Value = serde_json::from_str(&fs::read_to_string(out.join("plan.json")).unwrap()).unwrap();
+        assert_eq!(plan[1]["action"], "library");
+        assert_eq!(plan[1]["track"]["title"], "Owned");
+        let csv = fs::read_to_string(out.join("plan.csv")).unwrap();
+        assert_eq!(csv.lines().nth(4).unwrap(), "4,New Song - Instrumental,Artist A,,skip,instrumental,");
+    }
+
+    fn limited_runner(timeout_ms: Option<u64>, stall_ms: Option<u64>) -> SystemRunner {
+        SystemRunner {
+            timeout: timeout_ms.map(Duration::from_millis),
+            stall_timeout: stall_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn stalled_yt_dlp_is_killed_and_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = limited_runner(None, Some(300));
+        let started = std::time::Instant::now();
+
+        let (report, events) =
+            convert_with_events(&runner, &[track("HANG", "Artist", "")], &fast_retries(1), dir.path(), &AtomicBool::new(false));
+        let report = report.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10), "hung process was not killed");
+        let failure = &report.failures[0];
+        assert_eq!(failure.kind, FailureKind::TimedOut);
+        assert_eq!(failure.attempts, 2);
+        assert!(failure.message.contains("stalled: no output for 0.3s"), "{}", failure.message);
+        assert!(
+            events.iter().any(|e| matches!(e, ConversionEvent::Retrying { query, attempt: 2, reason, .. }
+                if query == "HANG Artist topic" && reason.contains("stalled"))),
+            "{events:?}"
+        );
+    }
+
+    #[test]
+    fn overall_timeout_trips_even_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = limited_runner(Some(400), Some(300));
+
+        let report = convert(&runner, &[track("TRICKLE", "Artist", "")], &fast_retries(0), dir.path()).unwrap();
+
+        let failure = &report.failures[0];
+        assert_eq!(failure.kind, FailureKind::TimedOut);
+        assert!(failure.message.contains("timed out after 0.4s"), "{}", failure.message);
+    }
+
+    #[test]
+    fn reports_live_track_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let (report, events) = convert_with_events(
+            &SystemRunner::default(),
+            &[track("Song", "Artist", "")],
+            &AppConfig::default(),
+            dir.path(),
+            &AtomicBool::new(false),
+        );
+        report.unwrap();
+
+        let events: Vec<_> = events
+            .into_iter()
+            .filter_map(|e| match e {
+                ConversionEvent::Progress(p) => Some(p),
+                _ => None,
+            })
+            .collect();
+        let phases: Vec<_> = events.iter().map(|p| p.phase).collect();
+        assert_eq!(phases.first(), Some(&TrackPhase::Searching));
+        assert_eq!(phases.last(), Some(&TrackPhase::Tagging));
+        let downloads: Vec<_> = events
+            .iter()
+            .filter(|p| p.phase == TrackPhase::Downloading)
+            .filter_map(|p| p.percent)
+            .collect();
+        assert_eq!(downloads, [0.0, 50.0, 100.0]);
+        let converting = phases.iter().position(|p| *p == TrackPhase::Converting).unwrap();
+        assert!(phases[..converting].contains(&TrackPhase::Downloading));
+        assert!(events.iter().all(|p| p.index == 0 && p.total == 1 && p.title == "Song"));
+    }
+
+    #[test]
+    fn emits_track_lifecycle_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracks = [track("Song", "Artist", ""), track("FAIL", "Artist", ""), track("Song", "Artist", "")];
+        let mut config = fast_retries(0);
+        config.playlist_formats.clear();
+
+        let (report, events) = convert_with_events(&SystemRunner::default(), &tracks, &config, dir.path(), &AtomicBool::new(false));
+        report.unwrap();
+
+        let kinds: Vec<&str> = events
+            .iter()
+            .filter(|e| !matches!(e, ConversionEvent::Progress(_)))
+            .map(|e| match e {
+                ConversionEvent::Started { .. } => "started",
+                ConversionEvent::TrackStarted { .. } => "track",
+                ConversionEvent::CandidateChosen { .. } => "chosen",
+                ConversionEvent::TrackSucceeded { .. } => "ok",
+                ConversionEvent::TrackFailed { .. } => "failed",
+                ConversionEvent::TrackSkipped { .. } => "skipped",
+                ConversionEvent::Finished(_) => "finished",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["started", "track", "chosen", "ok", "track", "failed", "skipped", "finished"]);
+    }
+
+    #[test]
+    fn cancel_stops_before_next_track() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = RecordingRunner::new(|_| panic!("no tool should run after cancelling"));
+
+        let (report, events) =
+            convert_with_events(&runner, &[track("Song", "Artist", "")], &AppConfig::default(), dir.path(), &AtomicBool::new(true));
+
+        assert!(report.unwrap().cancelled);
+        assert!(matches!(events.last(), Some(ConversionEvent::Cancelled { completed: 0 })));
+        assert!(!events.iter().any(|e| matches!(e, ConversionEvent::Finished(_))));
+    }
 }
\ No newline at end of file
@@ -1,58 +1,217 @@
-use std::path::{Path, PathBuf};
-use std::process::Command;
-use anyhow::{Result, Context};
-use chrono::Utc;
-
-/// Call yt-dlp to download audio for a track query, saving to output_dir.
-/// Returns the path to the downloaded file.
-pub fn run_yt_dlp(
-    yt_dlp_path: &Path,
-    ffmpeg_path: &Path,
-    query: &str,
-    output_dir: &Path,
-    as_mp3: bool,
-) -> Result<PathBuf> {
-    // Use a unique output template to avoid collisions
-    let timestamp = Utc::now().timestamp_millis();
-    let output_template = output_dir.join(format!("yt2media_{}_%(title)s.%(ext)s", timestamp));
-    let output_template_str = output_template.to_string_lossy();
-
-    let mut cmd = Command::new(yt_dlp_path);
-    cmd.arg("-x")
-        .arg("--audio-format")
-        .arg(if as_mp3 { "mp3" } else { "m4a" })
-        .arg("--ffmpeg-location")
-        .arg(ffmpeg_path)
-        .arg("-o")
-        .arg(&output_template_str)
-        .arg(format!("ytsearch1:{}", query));
-
-    println!("Running command: {:?}", cmd);
-
-    let output = cmd.output().context("Failed to start yt-dlp")?;
-    if !output.status.success() {
-        eprintln!("yt-dlp failed:\nstdout: {}\nstderr: {}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr));
-        return Err(anyhow::anyhow!("yt-dlp failed to download: {}", String::from_utf8_lossy(&output.stderr)));
-    }
-
-    // Find the downloaded file by matching the unique prefix and extension
-    let mut found = None;
-    for entry in std::fs::read_dir(output_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        println!("Found file: {:?}", path); // Add this line
-        if path.file_name()
-            .and_then(|n| n.to_str())
-            .map(|n| n.starts_with(&format!("yt2media_{}", timestamp)))
-            .unwrap_or(false)
-            && path.extension().map(|e| e == "mp3" || e == "m4a").unwrap_or(false)
-        {
-            found = Some(path);
-            break;
-        }
-    }
-
-    found.ok_or_else(|| anyhow::anyhow!("yt-dlp did not produce an output file"))
+use std::ffi::OsString;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context};
+use crate::config::AppConfig;
+
+/// How often the runner wakes up to check timeouts while a tool is quiet.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Captured result of running an external tool (yt-dlp, ffmpeg).
+#[derive(Clone, Debug, Default)]
+pub struct ToolOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs external tools for the conversion engine.
+///
+/// The engine never spawns processes directly, so tests can swap in a fake
+/// runner or point the real one at the bundled stub tool.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &Path, args: &[OsString]) -> Result<ToolOutput>;
+
+    /// Like [`run`](Self::run), but calls `on_line` for every stdout line (split on
+    /// `\n` or `\r`) as it arrives. The default replays the output once the tool exits.
+    fn run_streaming(&self, program: &Path, args: &[OsString], on_line: &mut dyn FnMut(&str)) -> Result<ToolOutput> {
+        let output = self.run(program, args)?;
+        for line in output.stdout.split(['\n', '\r']).filter(|l| !l.is_empty()) {
+            on_line(line);
+        }
+        Ok(output)
+    }
+}
+
+/// Error returned when a tool was killed for taking too long.
+#[derive(Debug)]
+pub struct ToolTimeout {
+    /// `true` if the tool stopped producing output, `false` if it hit the overall limit.
+    pub stalled: bool,
+    pub after: Duration,
+}
+
+impl fmt::Display for ToolTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.stalled {
+            write!(f, "stalled: no output for {}s", self.after.as_secs_f32())
+        } else {
+            write!(f, "timed out after {}s", self.after.as_secs_f32())
+        }
+    }
+}
+
+impl std::error::Error for ToolTimeout {}
+
+/// Error returned when a tool could not be started at all, e.g. because it is missing.
+///
+/// Unlike a failed run this will not get better with another query or a retry.
+#[derive(Debug)]
+pub struct ToolNotStarted {
+    pub program: PathBuf,
+    pub source: std::io::Error,
+}
+
+impl fmt::Display for ToolNotStarted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to start {}", self.program.display())
+    }
+}
+
+impl std::error::Error for ToolNotStarted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Runs tools as real child processes, streaming their output so hung
+/// processes can be killed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner {
+    /// Kill the tool if it runs longer than this.
+    pub timeout: Option<Duration>,
+    /// Kill the tool if it prints nothing for this long.
+    pub stall_timeout: Option<Duration>,
+}
+
+impl SystemRunner {
+    /// Runner using the timeouts from `config` (0 disables a limit).
+    pub fn from_config(config: &AppConfig) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Self {
+            timeout: secs(config.download_timeout_secs),
+            stall_timeout: secs(config.stall_timeout_secs),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+fn spawn_reader(mut pipe: impl Read + Send + 'static, stream: Stream, tx: mpsc::Sender<(Stream, Vec<u8>)>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        // Read raw chunks: progress lines end in '\r', so line-based reads would look like a stall
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 || tx.send((stream, buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Pass every complete line in `pending` to `on_line`, keeping any unfinished tail.
+fn drain_lines(pending: &mut Vec<u8>, on_line: &mut dyn FnMut(&str)) {
+    while let Some(pos) = pending.iter().position(|&b| b == b'\n' || b == b'\r') {
+        let line: Vec<u8> = pending.drain(..=pos).collect();
+        let line = String::from_utf8_lossy(&line[..pos]);
+        if !line.is_empty() {
+            on_line(&line);
+        }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &Path, args: &[OsString]) -> Result<ToolOutput> {
+        self.run_streaming(program, args, &mut |_| {})
+    }
+
+    fn run_streaming(&self, program: &Path, args: &[OsString], on_line: &mut dyn FnMut(&str)) -> Result<ToolOutput> {
+        log::debug!("Running: {}", display_command(program, args));
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|source| ToolNotStarted { program: program.to_path_buf(), source })?;
+
+        let (tx, rx) = mpsc::channel();
+        if let Some(out) = child.stdout.take() {
+            spawn_reader(out, Stream::Stdout, tx.clone());
+        }
+        if let Some(err) = child.stderr.take() {
+            spawn_reader(err, Stream::Stderr, tx);
+        }
+
+        let start = Instant::now();
+        let mut last_output = start;
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut pending = Vec::new();
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok((stream, chunk)) => {
+                    last_output = Instant::now();
+                    match stream {
+                        Stream::Stdout => {
+                            stdout.extend_from_slice(&chunk);
+                            pending.extend_from_slice(&chunk);
+                            drain_lines(&mut pending, on_line);
+                        }
+                        Stream::Stderr => stderr.extend_from_slice(&chunk),
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            let tripped = match (self.timeout, self.stall_timeout) {
+                (Some(limit), _) if start.elapsed() >= limit => Some(ToolTimeout { stalled: false, after: limit }),
+                (_, Some(limit)) if last_output.elapsed() >= limit => Some(ToolTimeout { stalled: true, after: limit }),
+                _ => None,
+            };
+            if let Some(timeout) = tripped {
+                log::warn!("Killing {}: {timeout}", program.display());
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(timeout.into());
+            }
+        }
+
+        pending.push(b'\n');
+        drain_lines(&mut pending, on_line);
+
+        let status = child.wait().with_context(|| format!("Failed to wait for {}", program.display()))?;
+        let output = ToolOutput {
+            success: status.success(),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        };
+        log::debug!("{} exited with {status}; stderr:\n{}", program.display(), output.stderr.trim_end());
+        Ok(output)
+    }
+}
+
+/// Render a command line for logs.
+pub fn display_command(program: &Path, args: &[OsString]) -> String {
+    let mut line = program.display().to_string();
+    for arg in args {
+        line.push(' ');
+        let arg = arg.to_string_lossy();
+        if arg.contains(' ') {
+            line.push_str(&format!("\"{arg}\""));
+        } else {
+            line.push_str(&arg);
+        }
+    }
+    line
 }
\ No newline at end of file
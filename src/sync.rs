@@ -0,0 +1,193 @@
+use crate::config::{SyncConfig, SyncRemoval};
+use crate::csvparse::TrackInfo;
+use crate::library::match_key;
+use crate::naming::unique_path;
+use crate::spotify2media::TrackResult;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Written into the output folder after every run; lists the files the tool created there.
+pub const STATE_FILE: &str = ".spotify2media.json";
+
+/// Identifies a track across runs: its Spotify URI, or title and artist if the CSV has none.
+pub fn track_key(track: &TrackInfo) -> String {
+    match track.spotify_uri.is_empty() {
+        true => match_key(track),
+        false => track.spotify_uri.clone(),
+    }
+}
+
+/// A file the tool wrote for one track.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyncedTrack {
+    pub key: String,
+    pub title: String,
+    pub artist: String,
+    /// Relative to the output folder.
+    pub file: PathBuf,
+}
+
+impl SyncedTrack {
+    /// Only plain relative paths are trusted, so an edited state file can't point sync
+    /// at something outside the output folder.
+    fn path(&self, output_dir: &Path) -> Option<PathBuf> {
+        let plain = self.file.components().all(|c| matches!(c, Component::Normal(_)));
+        (plain && !self.file.as_os_str().is_empty()).then(|| output_dir.join(&self.file))
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    pub tracks: Vec<SyncedTrack>,
+}
+
+/// What the previous run left in the output folder, split by the new track list.
+#[derive(Clone, Debug, Default)]
+pub struct SyncDiff {
+    /// Files that can stay, by [`track_key`]; one per occurrence of the track in the playlist.
+    pub kept: HashMap<String, Vec<PathBuf>>,
+    /// Files of tracks that are no longer in the playlist.
+    pub removed: Vec<PathBuf>,
+}
+
+impl SyncState {
+    /// The state of `output_dir`; empty if no run has written one yet.
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(STATE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(txt) => serde_json::from_str(&txt).with_context(|| format!("Failed to read sync state {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read sync state {:?}", path)),
+        }
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = output_dir.join(STATE_FILE);
+        let txt = serde_json::to_string_pretty(self)?;
+        fs::write(&path, txt).with_context(|| format!("Failed to write sync state {:?}", path))
+    }
+
+    /// Compare the files from earlier runs with the new `tracks`. Files that were deleted
+    /// by hand since are in neither list, so their tracks get converted again. A track
+    /// listed fewer times than before keeps that many files; the rest are removed.
+    pub fn diff(&self, tracks: &[TrackInfo], output_dir: &Path) -> SyncDiff {
+        let mut wanted: HashMap<String, usize> = HashMap::new();
+        for track in tracks {
+            *wanted.entry(track_key(track)).or_default() += 1;
+        }
+        let mut diff = SyncDiff::default();
+        for entry in &self.tracks {
+            let Some(path) = entry.path(output_dir).filter(|p| p.is_file() || p.is_symlink()) else { continue };
+            let files = diff.kept.entry(entry.key.clone()).or_default();
+            if files.len() < wanted.get(&entry.key).copied().unwrap_or(0) {
+                files.push(path);
+            } else {
+                diff.removed.push(path);
+            }
+        }
+        diff.kept.retain(|_, files| !files.is_empty());
+        diff
+    }
+
+    /// The state after a run: every result, plus the files of earlier runs that are still
+    /// there, so an interrupted or non-sync run doesn't forget what it doesn't touch.
+    pub fn update(&self, results: &[TrackResult], output_dir: &Path) -> Self {
+        let mut tracks: Vec<SyncedTrack> = results
+            .iter()
+            .filter_map(|r| {
+                Some(SyncedTrack {
+                    key: track_key(&r.track),
+                    title: r.track.title.clone(),
+                    artist: r.track.artist.clone(),
+                    file: r.output.strip_prefix(output_dir).ok()?.to_path_buf(),
+                })
+            })
+            .collect();
+        let written: HashSet<PathBuf> = tracks.iter().map(|t| t.file.clone()).collect();
+        tracks.extend(
+            self.tracks
+                .iter()
+                .filter(|t| !written.contains(&t.file) && t.path(output_dir).is_some_and(|p| p.is_file() || p.is_symlink()))
+                .cloned(),
+        );
+        Self { tracks }
+    }
+}
+
+/// Archive or delete the `removed` files and return where each one went (`None` = deleted).
+/// Folders the template created are removed once they are empty.
+pub fn remove_files(removed: &[PathBuf], output_dir: &Path, config: &SyncConfig) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    let archive = output_dir.join(&config.archive_dir);
+    let mut taken = HashSet::new();
+    let mut moved = Vec::with_capacity(removed.len());
+    for file in removed {
+        let target = match config.removal {
+            SyncRemoval::Delete => {
+                fs::remove_file(file).with_context(|| format!("Failed to delete {:?}", file))?;
+                None
+            }
+            SyncRemoval::Archive => {
+                let relative = file.strip_prefix(output_dir).unwrap_or(file);
+                let dir = archive.join(relative.parent().unwrap_or(Path::new("")));
+                fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+                let name = file.file_name().and_then(|n| n.to_str()).unwrap_or("track");
+                let target = unique_path(&dir, name, &mut taken, false);
+                fs::rename(file, &target).with_context(|| format!("Failed to move {:?} to {:?}", file, target))?;
+                Some(target)
+            }
+        };
+        let mut dir = file.parent();
+        while let Some(d) = dir.filter(|d| *d != output_dir && d.starts_with(output_dir)) {
+            if fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+        moved.push((file.clone(), target));
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::track;
+
+    #[test]
+    fn diffs_against_previous_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kept.mp3"), "a").unwrap();
+        fs::write(dir.path().join("dropped.mp3"), "b").unwrap();
+        let entry = |key: &str, file: &str| SyncedTrack { key: key.into(), title: String::new(), artist: String::new(), file: file.into() };
+        fs::write(dir.path().join("repeat.mp3"), "c").unwrap();
+        let state = SyncState {
+            tracks: vec![
+                entry("spotify:track:1", "kept.mp3"),
+                entry("spotify:track:1", "repeat.mp3"),
+                entry("spotify:track:2", "dropped.mp3"),
+                entry("spotify:track:3", "deleted-by-hand.mp3"),
+                entry("spotify:track:4", "../outside.mp3"),
+            ],
+        };
+        let mut wanted = track("Song", "Artist", "");
+        wanted.spotify_uri = "spotify:track:1".into();
+
+        let diff = state.diff(&[wanted.clone(), track("New", "Artist", "")], dir.path());
+
+        assert_eq!(diff.kept, HashMap::from([("spotify:track:1".to_string(), vec![dir.path().join("kept.mp3")])]));
+        assert_eq!(diff.removed, [dir.path().join("repeat.mp3"), dir.path().join("dropped.mp3")]);
+
+        // Listed twice, the track keeps both files
+        let diff = state.diff(&[wanted.clone(), wanted], dir.path());
+        assert_eq!(diff.kept["spotify:track:1"], [dir.path().join("kept.mp3"), dir.path().join("repeat.mp3")]);
+
+        // Without a URI the whole title counts, so a live version replaces the studio file
+        let studio = SyncState { tracks: vec![entry(&track_key(&track("Song", "Artist", "")), "kept.mp3")] };
+        let diff = studio.diff(&[track("Song (Live)", "Artist", "")], dir.path());
+        assert!(diff.kept.is_empty());
+        assert_eq!(diff.removed, [dir.path().join("kept.mp3")]);
+    }
+}
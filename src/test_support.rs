@@ -0,0 +1,66 @@
+//! Shared helpers for the offline pipeline tests.
+
+use crate::csvparse::TrackInfo;
+use crate::subprocess::{CommandRunner, ToolOutput};
+use anyhow::Result;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Path to the `fake_tool` example, which `cargo test` builds alongside the tests.
+pub fn fake_tool() -> PathBuf {
+    let exe = std::env::current_exe().expect("test executable path");
+    let target_dir = exe.parent().and_then(Path::parent).expect("target directory");
+    let path = target_dir
+        .join("examples")
+        .join(format!("fake_tool{}", std::env::consts::EXE_SUFFIX));
+    assert!(path.exists(), "{path:?} missing; run the tests with `cargo test`");
+    path
+}
+
+/// Write a small valid MP3 to `path` using the stub's ffmpeg mode.
+pub fn write_mp3(path: &Path) {
+    let status = Command::new(fake_tool())
+        .arg("-i")
+        .arg("input")
+        .arg(path)
+        .status()
+        .expect("run fake_tool");
+    assert!(status.success());
+}
+
+pub fn track(title: &str, artist: &str, album: &str) -> TrackInfo {
+    TrackInfo {
+        title: title.into(),
+        artist: artist.into(),
+        album: album.into(),
+        ..TrackInfo::default()
+    }
+}
+
+type Responder = Box<dyn Fn(&[String]) -> Result<ToolOutput> + Send + Sync>;
+
+/// In-process runner that records every command and answers with a canned result.
+pub struct RecordingRunner {
+    pub calls: Mutex<Vec<Vec<String>>>,
+    respond: Responder,
+}
+
+impl RecordingRunner {
+    pub fn new(respond: impl Fn(&[String]) -> Result<ToolOutput> + Send + Sync + 'static) -> Self {
+        Self { calls: Mutex::new(Vec::new()), respond: Box::new(respond) }
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, _program: &Path, args: &[OsString]) -> Result<ToolOutput> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string_lossy().into_owned()).collect();
+        self.calls.lock().unwrap().push(args.clone());
+        (self.respond)(&args)
+    }
+}
@@ -0,0 +1,171 @@
+use crate::audio::reencode_in_place;
+use crate::config::TrimConfig;
+use crate::spotify2media::Tools;
+use crate::verify::format_duration;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// SponsorBlock category for non-music sections of music videos.
+pub const SPONSORBLOCK_CATEGORY: &str = "music_offtopic";
+
+/// Silence closer than this to the start/end of the file counts as leading/trailing.
+const EDGE_SECS: f64 = 0.05;
+
+/// A section removed from a track, in seconds of the audio it was cut from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cut {
+    pub start: f64,
+    pub end: f64,
+    pub reason: CutReason,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CutReason {
+    LeadingSilence,
+    TrailingSilence,
+    /// Removed by yt-dlp; holds the SponsorBlock category.
+    SponsorBlock(String),
+}
+
+impl Cut {
+    pub fn length(&self) -> f64 {
+        (self.end - self.start).max(0.0)
+    }
+}
+
+impl fmt::Display for Cut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match &self.reason {
+            CutReason::LeadingSilence => "leading silence".to_string(),
+            CutReason::TrailingSilence => "trailing silence".to_string(),
+            CutReason::SponsorBlock(category) => format!("SponsorBlock {category}"),
+        };
+        let at = |secs: f64| format_duration(Duration::from_secs_f64(secs));
+        write!(f, "{reason} {}-{}", at(self.start), at(self.end))
+    }
+}
+
+/// One entry of `sponsorblock_chapters` in yt-dlp's `.info.json`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SponsorSegment {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub category: String,
+}
+
+/// The segments yt-dlp removed with `--sponsorblock-remove`.
+pub fn sponsorblock_cuts(segments: &[SponsorSegment]) -> Vec<Cut> {
+    segments
+        .iter()
+        .filter(|s| s.category == SPONSORBLOCK_CATEGORY)
+        .map(|s| Cut { start: s.start_time, end: s.end_time, reason: CutReason::SponsorBlock(s.category.clone()) })
+        .collect()
+}
+
+/// Silent intervals from `silencedetect` output. A silence still running at the end
+/// of the file has no `silence_end` line and lasts until `duration`.
+fn parse_silence(stderr: &str, duration: f64) -> Vec<(f64, f64)> {
+    let value = |line: &str, key: &str| {
+        let rest = &line[line.find(key)? + key.len()..];
+        rest.split_whitespace().next()?.parse::<f64>().ok()
+    };
+    let mut intervals = Vec::new();
+    let mut start = None;
+    for line in stderr.lines() {
+        if let Some(s) = value(line, "silence_start:") {
+            start = Some(s.max(0.0));
+        } else if let Some(e) = value(line, "silence_end:") {
+            if let Some(s) = start.take() {
+                intervals.push((s, e));
+            }
+        }
+    }
+    if let Some(s) = start {
+        intervals.push((s, duration));
+    }
+    intervals
+}
+
+/// Leading and trailing silence among `intervals`; silence in the middle of a track is kept.
+fn silence_cuts(intervals: &[(f64, f64)], duration: f64) -> Vec<Cut> {
+    let mut cuts = Vec::new();
+    if let Some(&(start, end)) = intervals.first().filter(|(s, _)| *s <= EDGE_SECS) {
+        cuts.push(Cut { start, end, reason: CutReason::LeadingSilence });
+    }
+    if let Some(&(start, end)) = intervals.last().filter(|(_, e)| *e >= duration - EDGE_SECS) {
+        if cuts.first().is_some_and(|lead| lead.end >= start) {
+            // The whole file is silent; don't trim it down to nothing
+            return Vec::new();
+        }
+        cuts.push(Cut { start, end, reason: CutReason::TrailingSilence });
+    }
+    cuts
+}
+
+/// Find leading and trailing silence in `path` with ffmpeg's `silencedetect`.
+pub fn detect_silence(tools: &Tools, path: &Path, config: &TrimConfig) -> Result<Vec<Cut>> {
+    let duration = crate::verify::probe(path)?.duration.as_secs_f64();
+    let args: Vec<OsString> = vec![
+        "-hide_banner".into(),
+        "-nostats".into(),
+        "-i".into(),
+        path.into(),
+        "-af".into(),
+        format!("silencedetect=noise={}dB:d={}", config.silence_threshold_db, config.min_silence_secs).into(),
+        "-f".into(),
+        "null".into(),
+        "-".into(),
+    ];
+    let output = tools.runner.run(tools.ffmpeg, &args).context("Failed to start ffmpeg")?;
+    if !output.success {
+        anyhow::bail!("ffmpeg silence detection failed: {}", output.stderr.trim());
+    }
+    Ok(silence_cuts(&parse_silence(&output.stderr, duration), duration))
+}
+
+/// Remove leading/trailing silence `cuts` from `path` by re-encoding the rest.
+pub fn trim_silence(tools: &Tools, path: &Path, cuts: &[Cut], bitrate_kbps: u32) -> Result<()> {
+    let mut options: Vec<OsString> = Vec::new();
+    for cut in cuts {
+        match cut.reason {
+            CutReason::LeadingSilence => options.extend(["-ss".into(), cut.end.to_string().into()]),
+            CutReason::TrailingSilence => options.extend(["-to".into(), cut.start.to_string().into()]),
+            CutReason::SponsorBlock(_) => {}
+        }
+    }
+    if options.is_empty() {
+        return Ok(());
+    }
+    reencode_in_place(tools, path, options, bitrate_kbps).context("trimming failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_leading_and_trailing_silence() {
+        let stderr = "[silencedetect @ 0x1] silence_start: -0.01\n\
+                      [silencedetect @ 0x1] silence_end: 2.5 | silence_duration: 2.51\n\
+                      [silencedetect @ 0x1] silence_start: 60\n\
+                      [silencedetect @ 0x1] silence_end: 62 | silence_duration: 2\n\
+                      [silencedetect @ 0x1] silence_start: 180.25\n";
+        let cuts = silence_cuts(&parse_silence(stderr, 184.0), 184.0);
+        assert_eq!(
+            cuts,
+            vec![
+                Cut { start: 0.0, end: 2.5, reason: CutReason::LeadingSilence },
+                Cut { start: 180.25, end: 184.0, reason: CutReason::TrailingSilence },
+            ]
+        );
+        assert_eq!(cuts[1].to_string(), "trailing silence 3:00-3:04");
+
+        // A completely silent file is left alone
+        let silent = "silence_start: 0\nsilence_end: 184 | silence_duration: 184\n";
+        assert!(silence_cuts(&parse_silence(silent, 184.0), 184.0).is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::config::VerifyConfig;
+use crate::spotify2media::Tools;
+use anyhow::{Context, Result};
+use lofty::{AudioFile, FileType, TaggedFileExt};
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+/// Technical properties of a converted file.
+#[derive(Clone, Debug)]
+pub struct MediaInfo {
+    pub file_type: FileType,
+    pub duration: Duration,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+}
+
+impl MediaInfo {
+    fn is_lossless(&self) -> bool {
+        matches!(self.file_type, FileType::Flac | FileType::Wav | FileType::Aiff | FileType::Ape | FileType::WavPack)
+    }
+}
+
+/// Outcome of verifying one file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Ok,
+    /// Usable, but probably not the right or a good-quality recording.
+    Suspect(String),
+    /// Unusable: corrupt, empty, or much shorter than the track.
+    Failed(String),
+}
+
+/// Read codec, length and bitrate from the file's headers.
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let file = lofty::read_from_path(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let props = file.properties();
+    Ok(MediaInfo {
+        file_type: file.file_type(),
+        duration: props.duration(),
+        bitrate_kbps: props.audio_bitrate(),
+        sample_rate: props.sample_rate(),
+        channels: props.channels(),
+    })
+}
+
+/// Compare probed properties with what the track should look like.
+pub fn check(info: &MediaInfo, expected: Option<Duration>, config: &VerifyConfig) -> Verdict {
+    if info.duration.is_zero() || info.sample_rate.unwrap_or(0) == 0 || info.channels == Some(0) {
+        return Verdict::Failed("no audio stream".into());
+    }
+    if let Some(expected) = expected {
+        let diff = info.duration.abs_diff(expected);
+        if info.duration < expected / 2 {
+            return Verdict::Failed(format!(
+                "truncated: {} of expected {}",
+                format_duration(info.duration),
+                format_duration(expected)
+            ));
+        }
+        if diff > Duration::from_secs(config.duration_tolerance_secs) {
+            return Verdict::Suspect(format!(
+                "length {} differs from expected {}",
+                format_duration(info.duration),
+                format_duration(expected)
+            ));
+        }
+    }
+    match info.bitrate_kbps {
+        Some(kbps) if !info.is_lossless() && kbps < config.min_bitrate_kbps => {
+            Verdict::Suspect(format!("low bitrate: {kbps} kbit/s"))
+        }
+        _ => Verdict::Ok,
+    }
+}
+
+/// Decode the whole file with ffmpeg; returns the first reported error, if any.
+pub fn decode_errors(tools: &Tools, path: &Path) -> Result<Option<String>> {
+    let args: Vec<OsString> =
+        vec!["-v".into(), "error".into(), "-i".into(), path.into(), "-f".into(), "null".into(), "-".into()];
+    let output = tools.runner.run(tools.ffmpeg, &args).context("Failed to start ffmpeg")?;
+    let first_error = output.stderr.lines().map(str::trim).find(|l| !l.is_empty()).map(String::from);
+    Ok(match (output.success, first_error) {
+        (true, None) => None,
+        (_, Some(error)) => Some(error),
+        (false, None) => Some("ffmpeg could not decode the file".into()),
+    })
+}
+
+/// Run every enabled check on `path`.
+pub fn verify(tools: &Tools, path: &Path, expected: Option<Duration>, config: &VerifyConfig) -> Verdict {
+    let info = match probe(path) {
+        Ok(info) => info,
+        Err(e) => return Verdict::Failed(format!("unreadable: {e:#}")),
+    };
+    if config.decode_check {
+        match decode_errors(tools, path) {
+            Ok(Some(error)) => return Verdict::Failed(format!("decode error: {error}")),
+            Ok(None) => {}
+            Err(e) => return Verdict::Suspect(format!("decode check skipped: {e:#}")),
+        }
+    }
+    check(&info, expected, config)
+}
+
+/// `m:ss`, as shown by music players.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subprocess::SystemRunner;
+    use crate::test_support::{fake_tool, write_mp3};
+    use std::fs;
+
+    fn info(secs: u64, kbps: u32) -> MediaInfo {
+        MediaInfo {
+            file_type: FileType::Mpeg,
+            duration: Duration::from_secs(secs),
+            bitrate_kbps: Some(kbps),
+            sample_rate: Some(44_100),
+            channels: Some(2),
+        }
+    }
+
+    #[test]
+    fn judges_length_and_bitrate() {
+        let config = VerifyConfig::default();
+        let expected = Some(Duration::from_secs(200));
+        assert_eq!(check(&info(205, 320), expected, &config), Verdict::Ok);
+        assert_eq!(check(&info(205, 320), None, &config), Verdict::Ok);
+        assert_eq!(check(&info(260, 320), expected, &config), Verdict::Suspect("length 4:20 differs from expected 3:20".into()));
+        assert_eq!(check(&info(31, 320), expected, &config), Verdict::Failed("truncated: 0:31 of expected 3:20".into()));
+        assert_eq!(check(&info(200, 48), expected, &config), Verdict::Suspect("low bitrate: 48 kbit/s".into()));
+        assert_eq!(check(&info(0, 320), None, &config), Verdict::Failed("no audio stream".into()));
+    }
+
+    #[test]
+    fn probes_real_files_with_and_without_id3() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = fake_tool();
+        let runner = SystemRunner::default();
+        let tools = Tools::new(&runner, Some(&tool), Some(&tool));
+        let config = VerifyConfig { decode_check: true, ..VerifyConfig::default() };
+
+        let tagged = dir.path().join("tagged.mp3");
+        write_mp3(&tagged);
+        let probed = probe(&tagged).unwrap();
+        assert_eq!(probed.file_type, FileType::Mpeg);
+        assert_eq!(probed.bitrate_kbps, Some(128));
+        assert_eq!(verify(&tools, &tagged, Some(Duration::from_secs(1)), &config), Verdict::Ok);
+
+        // Plain MPEG frames without an ID3 header are perfectly valid
+        let bare = dir.path().join("bare.mp3");
+        fs::write(&bare, &fs::read(&tagged).unwrap()[10..]).unwrap();
+        assert_eq!(verify(&tools, &bare, None, &config), Verdict::Ok);
+
+        let garbage = dir.path().join("garbage.mp3");
+        fs::write(&garbage, b"ID3 but nothing else").unwrap();
+        assert!(matches!(verify(&tools, &garbage, None, &config), Verdict::Failed(_)));
+    }
+}